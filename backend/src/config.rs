@@ -1,40 +1,132 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
 use anyhow::{Result, Context};
+use ipnet::IpNet;
+use jsonwebtoken::Algorithm;
+use std::net::IpAddr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
     pub image: ImageConfig,
+    pub filename: FilenameConfig,
     pub cors: CorsConfig,
     pub rate_limit: RateLimitConfig,
+    pub ip_filter: IpFilterConfig,
 }
 
+/// Access log format: actix's default plus the response time in milliseconds and the request id
+pub const DEFAULT_ACCESS_LOG_FORMAT: &str =
+    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %Dms req_id=%{X-Request-Id}i"#;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub web_port: u16,
     pub static_port: u16,
+    pub workers: usize, // Worker threads of each server, defaults to the CPU count
+    pub max_connections: usize, // Concurrent connections accepted by each worker of each server
+    pub shutdown_timeout_seconds: u64, // On SIGTERM or Ctrl-C, how long in-flight requests get to finish
     pub upload_dir: String,
+    pub upload_sharding: String, // "none", "name" or "hash": store files in subdirectories named after a prefix of their name or its hash
+    pub upload_shard_levels: usize, // Nested subdirectory levels of sharded storage, two characters each
     pub max_file_size: usize,
+    pub max_multipart_fields: usize, // Fields accepted in a single multipart request
+    pub max_multipart_size: usize, // Total bytes of field data in a single multipart request
+    pub min_free_bytes: u64, // Disk space that must remain free after storing an upload
+    pub deduplicate_uploads: bool, // Hard-link uploads identical to a stored file instead of storing them again
+    pub max_total_size: Option<u64>, // Bytes a storage may hold, derivatives included; unlimited when unset
+    pub max_upload_bytes_per_sec: Option<u64>, // Bandwidth cap of a single upload request, unlimited when unset
+    pub resumable_upload_expiry_hours: u64, // Resumable uploads not completed within this time are discarded
     pub base_url: Option<String>,
+    pub relative_urls: bool, // Return `/uploads/...` paths instead of absolute URLs
+    pub frontend_404_page: Option<String>, // HTML page served for missing frontend files
+    pub case_insensitive_folder_names: bool,
+    pub breadcrumb_max_depth: Option<usize>, // Closest ancestors kept in listing breadcrumbs, all when unset
+    pub strict_file_types: bool, // Reject files whose content doesn't match their extension
+    pub trusted_extensions: Vec<String>, // Extensions accepted without magic-byte checks
+    pub custom_mime_types: HashMap<String, String>, // Extension to MIME type mappings taking precedence over the built-in ones
+    pub auto_folders: HashMap<String, String>, // MIME category or type to the folder path of uploads sent without a folder
+    pub per_user_storage: bool, // Isolate each authenticated user's files in their own subdirectory
+    pub require_destructive_confirmation: bool, // Destructive operations need a confirmation token round trip
+    pub raw_listing_enabled: bool, // Serve the admin-only listing of the files physically present in the upload directory
+    pub access_log_format: String, // actix `Logger` format string for both servers
+    pub log_buffer_lines: usize, // Recent log lines kept in memory for the admin log stream; 0 disables it
+    pub inline_mime_categories: Vec<String>, // Top-level MIME types served inline, others download as attachments
+    pub gzip_precompression: bool, // Store `.gz` companions of text-like uploads, served to clients accepting gzip
+    pub max_import_size: u64, // Total extracted bytes of an imported archive
+    pub import_concurrency: usize, // Files of an archive processed at once, defaults to the CPU count
+    pub import_ignore: Vec<String>, // Glob patterns of ZIP entry names skipped on import; a trailing `/` matches folders
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub mode: String, // "protected" or "local"
     pub admin_username: String,
-    pub admin_password: String,
+    pub admin_password: String, // Deprecated plaintext fallback when no hash is set
+    pub admin_password_hash: Option<String>, // bcrypt hash, from `snapfilething hash-password`
+    pub users: Vec<UserCredential>, // Replaces the admin pair when set; ADMIN_USERNAME still names the admin
+    pub htpasswd_file: Option<String>, // htpasswd file with bcrypt entries, read into `users` at startup
     pub jwt_secret: String,
+    pub jwt_algorithm: Algorithm, // HS* algorithms sign with JWT_SECRET, others with the key pair below
+    pub jwt_private_key_file: Option<String>, // PEM signing key for RS*, PS*, ES* and EdDSA
+    pub jwt_public_key_file: Option<String>, // PEM verification key matching the private key
+    pub jwt_previous_secrets: Vec<String>, // Rotated-out secrets still accepted when verifying HS* tokens
+    pub jwt_previous_public_key_files: Vec<String>, // Rotated-out PEM public keys still accepted when verifying
+    pub access_token_ttl_seconds: i64,
+    pub refresh_token_ttl_seconds: i64,
     pub disabled_routes: Vec<String>, // Routes that don't require authentication
+    pub public_folder_id: Option<String>, // Folder whose contents can be read without authentication
+    pub public_read_only: bool,
+    pub static_auth_required: bool, // Require credentials on the static file server in protected mode
+    pub signed_url_secret: Option<String>, // HMAC key of signed file URLs; JWT_SECRET with HS* algorithms when unset
+    pub signed_url_max_ttl_seconds: i64, // Longest validity a signed file URL may be given
+    pub token_blacklist_file: Option<String>, // Persist revoked tokens here so they stay revoked across restarts
+}
+
+/// A login account with a bcrypt password hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserCredential {
+    pub username: String,
+    pub password_hash: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageConfig {
     pub thumbnail_size: u32,
+    pub thumbnail_variant_sizes: Vec<u32>, // Extra `{stem}_thumb_{size}` thumbnails; every listed size when THUMBNAIL_SIZE is a list
+    pub max_thumbnail_size: u32, // Upper bound accepted for thumbnail_size
+    pub thumbnail_min_source_dimension: u32, // Smaller sources serve as their own thumbnail; 0 means thumbnail_size
     pub jpeg_quality: u8,
     pub webp_quality: f32,
+    pub webp_quality_range: Option<(f32, f32)>, // (min, max): adapt thumbnail quality to source size instead of webp_quality
+    pub thumbnail_jpeg_fallback: bool, // Also write a JPEG thumbnail for clients without WebP support
+    pub thumbnail_format: String, // "webp", "jpeg" or "png"
     pub qoi_enabled: bool,
+    pub avif_enabled: bool, // Also keep a lossy AVIF copy of image uploads
+    pub avif_quality: u8, // 1-100
+    pub auto_orient: bool, // Apply the EXIF orientation to QOI copies and thumbnails
+    pub strip_metadata: bool, // Remove EXIF/XMP metadata from uploaded JPEG, PNG and WebP images
+    pub keep_original: bool, // When false, images keep only their derivatives
+    pub canonical_image_format: Option<String>, // "jpeg", "png" or "webp": re-encode every image upload
+    pub canonical_image_quality: u8, // JPEG quality of canonical re-encoding
+    pub analysis_enabled: bool, // Store brightness/histogram stats for uploaded images
+    pub tiff_page_count_enabled: bool, // Count the pages of multi-page TIFF scans
+    pub max_derivative_source_pixels: Option<u64>, // Larger images get no QOI copy or analysis, unlimited when unset
+    pub thumbnail_large_sources: bool, // Still thumbnail images over max_derivative_source_pixels
+    pub tile_size: u32, // Side of the deep-zoom tiles cut on demand
+    pub inline_thumbnail_max_bytes: u64, // Largest thumbnail embedded as a data URI with `inline_thumbs`
+    pub inline_thumbnails_max_total_bytes: u64, // Budget of inlined thumbnail bytes per listing
+    pub text_preview_enabled: bool,
+    pub text_preview_max_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilenameConfig {
+    pub preserve_case: bool, // Keep the original case of the filename stem
+    pub lowercase_extension: bool,
+    pub transliterate: bool, // Convert non-ASCII characters to their closest ASCII equivalent
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,21 +151,94 @@ pub struct RateLimitRule {
     pub burst_size: u32,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IpFilterConfig {
+    pub trusted_proxies: Vec<IpNet>, // Peers whose X-Forwarded-For/X-Real-IP headers are believed
+    pub upload: IpRules, // Uploads and imports
+    pub manage: IpRules, // Mutations of files and folders
+    pub admin: IpRules, // Admin and stats endpoints
+}
+
+/// Networks allowed or denied for a route group. An empty allowlist allows everyone
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IpRules {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+impl AuthConfig {
+    /// Key signing file URLs, `None` when there is no secret to sign with
+    pub fn signed_url_key(&self) -> Option<&str> {
+        let hmac = matches!(self.jwt_algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512);
+        self.signed_url_secret.as_deref()
+            .or_else(|| hmac.then_some(self.jwt_secret.as_str()))
+    }
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             server: ServerConfig {
                 web_port: 8080,
                 static_port: 8081,
+                workers: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+                max_connections: 25_000, // actix-web's default
+                shutdown_timeout_seconds: 30, // actix-web's default
                 upload_dir: "./uploads".to_string(),
+                upload_sharding: "none".to_string(),
+                upload_shard_levels: 2,
                 max_file_size: 104857600, // 100MB
+                max_multipart_fields: 16,
+                max_multipart_size: 1073741824, // 1GB
+                min_free_bytes: 0,
+                deduplicate_uploads: true,
+                max_total_size: None,
+                max_upload_bytes_per_sec: None,
+                resumable_upload_expiry_hours: 24,
                 base_url: None,
+                relative_urls: false,
+                frontend_404_page: None,
+                case_insensitive_folder_names: false,
+                breadcrumb_max_depth: None,
+                strict_file_types: false,
+                trusted_extensions: vec!["heic".to_string(), "heif".to_string(), "avif".to_string(), "jxl".to_string()],
+                custom_mime_types: HashMap::new(),
+                auto_folders: HashMap::new(),
+                per_user_storage: false,
+                require_destructive_confirmation: false,
+                raw_listing_enabled: false,
+                access_log_format: DEFAULT_ACCESS_LOG_FORMAT.to_string(),
+                log_buffer_lines: crate::services::log_buffer::DEFAULT_LOG_BUFFER_LINES,
+                inline_mime_categories: vec!["image".to_string(), "video".to_string(), "audio".to_string()],
+                gzip_precompression: false,
+                max_import_size: 10737418240, // 10GB
+                import_concurrency: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+                import_ignore: vec!["__MACOSX/".to_string(), ".DS_Store".to_string(), "Thumbs.db".to_string()],
             },
             auth: AuthConfig {
                 mode: "protected".to_string(),
                 admin_username: "admin".to_string(),
                 admin_password: "changeme".to_string(),
+                admin_password_hash: None,
+                users: Vec::new(),
+                htpasswd_file: None,
                 jwt_secret: "your-super-secret-jwt-key-change-this-in-production".to_string(),
+                jwt_algorithm: Algorithm::HS256,
+                jwt_private_key_file: None,
+                jwt_public_key_file: None,
+                jwt_previous_secrets: Vec::new(),
+                jwt_previous_public_key_files: Vec::new(),
+                access_token_ttl_seconds: 3600,        // 1 hour
+                refresh_token_ttl_seconds: 7 * 86400,  // 7 days
                 disabled_routes: vec![
                     "/".to_string(),
                     "/web".to_string(),
@@ -86,12 +251,45 @@ impl Default for AppConfig {
                     "/api/auth/login".to_string(),
                     "/api/auth/refresh".to_string(),
                 ],
+                public_folder_id: None,
+                public_read_only: false,
+                static_auth_required: false,
+                signed_url_secret: None,
+                signed_url_max_ttl_seconds: 7 * 86400, // 7 days
+                token_blacklist_file: None,
             },
             image: ImageConfig {
                 thumbnail_size: 200,
+                thumbnail_variant_sizes: Vec::new(),
+                max_thumbnail_size: 1024,
+                thumbnail_min_source_dimension: 0,
                 jpeg_quality: 85,
                 webp_quality: 80.0,
+                webp_quality_range: None,
+                thumbnail_jpeg_fallback: false,
+                thumbnail_format: "webp".to_string(),
                 qoi_enabled: true,
+                avif_enabled: false,
+                avif_quality: 70,
+                auto_orient: true,
+                strip_metadata: false,
+                keep_original: true,
+                canonical_image_format: None,
+                canonical_image_quality: 85,
+                analysis_enabled: false,
+                tiff_page_count_enabled: true,
+                max_derivative_source_pixels: None,
+                thumbnail_large_sources: true,
+                tile_size: 256,
+                inline_thumbnail_max_bytes: 8 * 1024,
+                inline_thumbnails_max_total_bytes: 256 * 1024,
+                text_preview_enabled: false,
+                text_preview_max_bytes: 512,
+            },
+            filename: FilenameConfig {
+                preserve_case: false,
+                lowercase_extension: true,
+                transliterate: false,
             },
             cors: CorsConfig {
                 allowed_origins: vec![
@@ -130,6 +328,7 @@ impl Default for AppConfig {
                     "/api-docs".to_string(),
                 ],
             },
+            ip_filter: IpFilterConfig::default(),
         }
     }
 }
@@ -137,11 +336,36 @@ impl Default for AppConfig {
 impl AppConfig {
     /// Get the base URL for static file serving
     pub fn get_static_base_url(&self) -> String {
+        // An empty base yields URLs relative to the client's origin
+        if self.server.relative_urls {
+            return String::new();
+        }
+
         self.server.base_url
             .clone()
             .unwrap_or_else(|| format!("http://localhost:{}", self.server.static_port))
     }
 
+    /// Get the base URL for static file serving as seen by the client of a request.
+    ///
+    /// Without a configured `base_url`, the scheme and host come from the request.
+    /// A port matching the web port is swapped for the static port; a host without
+    /// a port is assumed to be behind a proxy that also routes `/uploads`.
+    pub fn static_base_url_for(&self, scheme: &str, host: &str) -> String {
+        if self.server.relative_urls || self.server.base_url.is_some() {
+            return self.get_static_base_url();
+        }
+
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if port.parse::<u16>().ok() == Some(self.server.web_port) => {
+                format!("{}:{}", name, self.server.static_port)
+            }
+            _ => host.to_string(),
+        };
+
+        format!("{}://{}", scheme, host)
+    }
+
     pub fn load() -> Result<Self> {
         // Load .env file if present
         dotenv::dotenv().ok();
@@ -159,19 +383,175 @@ impl AppConfig {
                 .context("Invalid STATIC_PORT environment variable")?;
         }
         
+        if let Ok(workers) = env::var("WORKERS") {
+            config.server.workers = workers.parse()
+                .context("Invalid WORKERS environment variable")?;
+        }
+        
+        if let Ok(connections) = env::var("MAX_CONNECTIONS") {
+            config.server.max_connections = connections.parse()
+                .context("Invalid MAX_CONNECTIONS environment variable")?;
+        }
+        
+        if let Ok(timeout) = env::var("SHUTDOWN_TIMEOUT") {
+            config.server.shutdown_timeout_seconds = timeout.parse()
+                .context("Invalid SHUTDOWN_TIMEOUT environment variable")?;
+        }
+        
         if let Ok(dir) = env::var("UPLOAD_DIR") {
             config.server.upload_dir = dir;
         }
         
+        if let Ok(sharding) = env::var("UPLOAD_SHARDING") {
+            if !sharding.is_empty() {
+                config.server.upload_sharding = sharding.to_lowercase();
+            }
+        }
+        
+        if let Ok(levels) = env::var("UPLOAD_SHARD_LEVELS") {
+            config.server.upload_shard_levels = levels.parse()
+                .context("Invalid UPLOAD_SHARD_LEVELS environment variable")?;
+        }
+        
         if let Ok(size) = env::var("MAX_FILE_SIZE") {
             config.server.max_file_size = size.parse()
                 .context("Invalid MAX_FILE_SIZE environment variable")?;
         }
         
+        if let Ok(fields) = env::var("MAX_MULTIPART_FIELDS") {
+            config.server.max_multipart_fields = fields.parse()
+                .context("Invalid MAX_MULTIPART_FIELDS environment variable")?;
+        }
+        
+        if let Ok(size) = env::var("MAX_MULTIPART_SIZE") {
+            config.server.max_multipart_size = size.parse()
+                .context("Invalid MAX_MULTIPART_SIZE environment variable")?;
+        }
+        
+        if let Ok(bytes) = env::var("MIN_FREE_BYTES") {
+            config.server.min_free_bytes = bytes.parse()
+                .context("Invalid MIN_FREE_BYTES environment variable")?;
+        }
+        
+        if let Ok(enabled) = env::var("DEDUPLICATE_UPLOADS") {
+            config.server.deduplicate_uploads = enabled.parse()
+                .context("Invalid DEDUPLICATE_UPLOADS environment variable")?;
+        }
+        
+        if let Ok(max_size) = env::var("MAX_TOTAL_SIZE") {
+            config.server.max_total_size = Some(max_size.parse()
+                .context("Invalid MAX_TOTAL_SIZE environment variable")?);
+        }
+        
+        if let Ok(rate) = env::var("MAX_UPLOAD_BYTES_PER_SEC") {
+            config.server.max_upload_bytes_per_sec = Some(rate.parse()
+                .context("Invalid MAX_UPLOAD_BYTES_PER_SEC environment variable")?);
+        }
+        
+        if let Ok(hours) = env::var("RESUMABLE_UPLOAD_EXPIRY_HOURS") {
+            config.server.resumable_upload_expiry_hours = hours.parse()
+                .context("Invalid RESUMABLE_UPLOAD_EXPIRY_HOURS environment variable")?;
+        }
+        
         if let Ok(base_url) = env::var("BASE_URL") {
             config.server.base_url = Some(base_url);
         }
         
+        if let Ok(relative) = env::var("RELATIVE_URLS") {
+            config.server.relative_urls = relative.parse()
+                .context("Invalid RELATIVE_URLS environment variable")?;
+        }
+        
+        if let Ok(page) = env::var("FRONTEND_404_PAGE") {
+            config.server.frontend_404_page = Some(page);
+        }
+        
+        if let Ok(case_insensitive) = env::var("CASE_INSENSITIVE_FOLDER_NAMES") {
+            config.server.case_insensitive_folder_names = case_insensitive.parse()
+                .context("Invalid CASE_INSENSITIVE_FOLDER_NAMES environment variable")?;
+        }
+        
+        if let Ok(depth) = env::var("BREADCRUMB_MAX_DEPTH") {
+            config.server.breadcrumb_max_depth = Some(depth.parse()
+                .context("Invalid BREADCRUMB_MAX_DEPTH environment variable")?);
+        }
+        
+        if let Ok(strict) = env::var("STRICT_FILE_TYPES") {
+            config.server.strict_file_types = strict.parse()
+                .context("Invalid STRICT_FILE_TYPES environment variable")?;
+        }
+        
+        if let Ok(extensions) = env::var("TRUSTED_EXTENSIONS") {
+            config.server.trusted_extensions = extensions
+                .split(',')
+                .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                .filter(|ext| !ext.is_empty())
+                .collect();
+        }
+        
+        if let Ok(mime_types) = env::var("CUSTOM_MIME_TYPES") {
+            config.server.custom_mime_types = parse_custom_mime_types(&mime_types)?;
+        }
+        
+        if let Ok(auto_folders) = env::var("AUTO_FOLDERS") {
+            config.server.auto_folders = parse_auto_folders(&auto_folders)?;
+        }
+        
+        if let Ok(per_user) = env::var("PER_USER_STORAGE") {
+            config.server.per_user_storage = per_user.parse()
+                .context("Invalid PER_USER_STORAGE environment variable")?;
+        }
+        
+        if let Ok(require) = env::var("REQUIRE_DESTRUCTIVE_CONFIRMATION") {
+            config.server.require_destructive_confirmation = require.parse()
+                .context("Invalid REQUIRE_DESTRUCTIVE_CONFIRMATION environment variable")?;
+        }
+        
+        if let Ok(enabled) = env::var("RAW_LISTING_ENABLED") {
+            config.server.raw_listing_enabled = enabled.parse()
+                .context("Invalid RAW_LISTING_ENABLED environment variable")?;
+        }
+        
+        if let Ok(categories) = env::var("INLINE_MIME_CATEGORIES") {
+            config.server.inline_mime_categories = categories.split(',')
+                .map(|category| category.trim().to_lowercase())
+                .filter(|category| !category.is_empty())
+                .collect();
+        }
+        
+        if let Ok(enabled) = env::var("GZIP_PRECOMPRESSION") {
+            config.server.gzip_precompression = enabled.parse()
+                .context("Invalid GZIP_PRECOMPRESSION environment variable")?;
+        }
+        
+        if let Ok(size) = env::var("MAX_IMPORT_SIZE") {
+            config.server.max_import_size = size.parse()
+                .context("Invalid MAX_IMPORT_SIZE environment variable")?;
+        }
+        
+        if let Ok(concurrency) = env::var("IMPORT_CONCURRENCY") {
+            config.server.import_concurrency = concurrency.parse()
+                .context("Invalid IMPORT_CONCURRENCY environment variable")?;
+        }
+        
+        if let Ok(patterns) = env::var("IMPORT_IGNORE") {
+            config.server.import_ignore = patterns.split(',')
+                .map(|pattern| pattern.trim().to_string())
+                .filter(|pattern| !pattern.is_empty())
+                .collect();
+        }
+        
+        if let Ok(format) = env::var("ACCESS_LOG_FORMAT") {
+            if !format.trim().is_empty() {
+                config.server.access_log_format = format;
+            }
+        }
+
+        if let Ok(lines) = env::var("LOG_BUFFER_LINES") {
+            config.server.log_buffer_lines = lines.parse()
+                .context("Invalid LOG_BUFFER_LINES environment variable")?;
+        }
+        
         // Auth configuration
         if let Ok(mode) = env::var("AUTH_MODE") {
             config.auth.mode = mode;
@@ -185,20 +565,133 @@ impl AppConfig {
             config.auth.admin_password = password;
         }
         
+        if let Ok(hash) = env::var("ADMIN_PASSWORD_HASH") {
+            if !hash.trim().is_empty() {
+                config.auth.admin_password_hash = Some(hash.trim().to_string());
+            }
+        }
+        
+        if let Ok(users) = env::var("USERS") {
+            config.auth.users = parse_users(&users)?;
+        }
+        
+        if let Ok(path) = env::var("AUTH_HTPASSWD_FILE") {
+            if !path.trim().is_empty() {
+                if !config.auth.users.is_empty() {
+                    anyhow::bail!("USERS and AUTH_HTPASSWD_FILE cannot both be set");
+                }
+                config.auth.users = parse_htpasswd(path.trim())?;
+                config.auth.htpasswd_file = Some(path.trim().to_string());
+            }
+        }
+        
         if let Ok(jwt_secret) = env::var("JWT_SECRET") {
             config.auth.jwt_secret = jwt_secret;
         }
         
+        if let Ok(algorithm) = env::var("JWT_ALGORITHM") {
+            config.auth.jwt_algorithm = algorithm.trim().parse()
+                .context("Invalid JWT_ALGORITHM environment variable")?;
+        }
+        
+        if let Ok(path) = env::var("JWT_PRIVATE_KEY_FILE") {
+            if !path.trim().is_empty() {
+                config.auth.jwt_private_key_file = Some(path.trim().to_string());
+            }
+        }
+        
+        if let Ok(path) = env::var("JWT_PUBLIC_KEY_FILE") {
+            if !path.trim().is_empty() {
+                config.auth.jwt_public_key_file = Some(path.trim().to_string());
+            }
+        }
+        
+        if let Ok(secrets) = env::var("JWT_PREVIOUS_SECRETS") {
+            config.auth.jwt_previous_secrets = secrets.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        }
+        
+        if let Ok(paths) = env::var("JWT_PREVIOUS_PUBLIC_KEY_FILES") {
+            config.auth.jwt_previous_public_key_files = paths.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        }
+        
+        if let Ok(ttl) = env::var("ACCESS_TOKEN_TTL") {
+            config.auth.access_token_ttl_seconds = ttl.parse()
+                .context("Invalid ACCESS_TOKEN_TTL environment variable")?;
+        }
+        
+        if let Ok(ttl) = env::var("REFRESH_TOKEN_TTL") {
+            config.auth.refresh_token_ttl_seconds = ttl.parse()
+                .context("Invalid REFRESH_TOKEN_TTL environment variable")?;
+        }
+        
         if let Ok(disabled_routes) = env::var("AUTH_DISABLED_ROUTES") {
             config.auth.disabled_routes = disabled_routes.split(',')
                 .map(|s| s.trim().to_string())
                 .collect();
         }
         
+        if let Ok(folder_id) = env::var("PUBLIC_FOLDER_ID") {
+            if !folder_id.trim().is_empty() {
+                config.auth.public_folder_id = Some(folder_id.trim().to_string());
+            }
+        }
+        
+        if let Ok(read_only) = env::var("PUBLIC_READ_ONLY") {
+            config.auth.public_read_only = read_only.parse()
+                .context("Invalid PUBLIC_READ_ONLY environment variable")?;
+        }
+        
+        if let Ok(required) = env::var("STATIC_AUTH_REQUIRED") {
+            config.auth.static_auth_required = required.parse()
+                .context("Invalid STATIC_AUTH_REQUIRED environment variable")?;
+        }
+        
+        if let Ok(secret) = env::var("SIGNED_URL_SECRET") {
+            if !secret.is_empty() {
+                config.auth.signed_url_secret = Some(secret);
+            }
+        }
+        
+        if let Ok(ttl) = env::var("SIGNED_URL_MAX_TTL") {
+            config.auth.signed_url_max_ttl_seconds = ttl.parse()
+                .context("Invalid SIGNED_URL_MAX_TTL environment variable")?;
+        }
+        
+        if let Ok(path) = env::var("TOKEN_BLACKLIST_FILE") {
+            if !path.trim().is_empty() {
+                config.auth.token_blacklist_file = Some(path.trim().to_string());
+            }
+        }
+        
         // Image configuration
-        if let Ok(size) = env::var("THUMBNAIL_SIZE") {
-            config.image.thumbnail_size = size.parse()
+        if let Ok(sizes) = env::var("THUMBNAIL_SIZE") {
+            let sizes = sizes.split(',')
+                .map(|size| size.trim().parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()
                 .context("Invalid THUMBNAIL_SIZE environment variable")?;
+            // The first size is the main thumbnail, a list also gets a variant per size
+            config.image.thumbnail_size = sizes[0];
+            if sizes.len() > 1 {
+                config.image.thumbnail_variant_sizes = sizes;
+                config.image.thumbnail_variant_sizes.sort_unstable();
+                config.image.thumbnail_variant_sizes.dedup();
+            }
+        }
+        
+        if let Ok(max_size) = env::var("MAX_THUMBNAIL_SIZE") {
+            config.image.max_thumbnail_size = max_size.parse()
+                .context("Invalid MAX_THUMBNAIL_SIZE environment variable")?;
+        }
+        
+        if let Ok(min_dimension) = env::var("THUMBNAIL_MIN_SOURCE_DIMENSION") {
+            config.image.thumbnail_min_source_dimension = min_dimension.parse()
+                .context("Invalid THUMBNAIL_MIN_SOURCE_DIMENSION environment variable")?;
         }
         
         if let Ok(quality) = env::var("JPEG_QUALITY") {
@@ -211,10 +704,129 @@ impl AppConfig {
                 .context("Invalid WEBP_QUALITY environment variable")?;
         }
         
+        if let Ok(range) = env::var("WEBP_QUALITY_RANGE") {
+            if !range.trim().is_empty() {
+                let (min, max) = range.split_once(',')
+                    .context("Invalid WEBP_QUALITY_RANGE environment variable, expected min,max")?;
+                config.image.webp_quality_range = Some((
+                    min.trim().parse().context("Invalid WEBP_QUALITY_RANGE minimum")?,
+                    max.trim().parse().context("Invalid WEBP_QUALITY_RANGE maximum")?,
+                ));
+            }
+        }
+        
+        if let Ok(fallback) = env::var("THUMBNAIL_JPEG_FALLBACK") {
+            config.image.thumbnail_jpeg_fallback = fallback.parse()
+                .context("Invalid THUMBNAIL_JPEG_FALLBACK environment variable")?;
+        }
+
+        if let Ok(format) = env::var("THUMBNAIL_FORMAT") {
+            if !format.is_empty() {
+                config.image.thumbnail_format = format.to_lowercase();
+            }
+        }
+
         if let Ok(qoi_enabled) = env::var("QOI_ENABLED") {
             config.image.qoi_enabled = qoi_enabled.parse()
                 .context("Invalid QOI_ENABLED environment variable")?;
         }
+
+        if let Ok(avif_enabled) = env::var("AVIF_ENABLED") {
+            config.image.avif_enabled = avif_enabled.parse()
+                .context("Invalid AVIF_ENABLED environment variable")?;
+        }
+
+        if let Ok(quality) = env::var("AVIF_QUALITY") {
+            config.image.avif_quality = quality.parse()
+                .context("Invalid AVIF_QUALITY environment variable")?;
+        }
+
+        if let Ok(auto_orient) = env::var("AUTO_ORIENT") {
+            config.image.auto_orient = auto_orient.parse()
+                .context("Invalid AUTO_ORIENT environment variable")?;
+        }
+
+        if let Ok(strip_metadata) = env::var("STRIP_METADATA") {
+            config.image.strip_metadata = strip_metadata.parse()
+                .context("Invalid STRIP_METADATA environment variable")?;
+        }
+        
+        if let Ok(keep_original) = env::var("KEEP_ORIGINAL") {
+            config.image.keep_original = keep_original.parse()
+                .context("Invalid KEEP_ORIGINAL environment variable")?;
+        }
+        
+        if let Ok(format) = env::var("CANONICAL_IMAGE_FORMAT") {
+            if !format.is_empty() {
+                config.image.canonical_image_format = Some(format.to_lowercase());
+            }
+        }
+        
+        if let Ok(quality) = env::var("CANONICAL_IMAGE_QUALITY") {
+            config.image.canonical_image_quality = quality.parse()
+                .context("Invalid CANONICAL_IMAGE_QUALITY environment variable")?;
+        }
+        
+        if let Ok(enabled) = env::var("IMAGE_ANALYSIS_ENABLED") {
+            config.image.analysis_enabled = enabled.parse()
+                .context("Invalid IMAGE_ANALYSIS_ENABLED environment variable")?;
+        }
+        
+        if let Ok(enabled) = env::var("TIFF_PAGE_COUNT_ENABLED") {
+            config.image.tiff_page_count_enabled = enabled.parse()
+                .context("Invalid TIFF_PAGE_COUNT_ENABLED environment variable")?;
+        }
+
+        if let Ok(max_pixels) = env::var("MAX_DERIVATIVE_SOURCE_PIXELS") {
+            config.image.max_derivative_source_pixels = Some(max_pixels.parse()
+                .context("Invalid MAX_DERIVATIVE_SOURCE_PIXELS environment variable")?);
+        }
+
+        if let Ok(enabled) = env::var("THUMBNAIL_LARGE_SOURCES") {
+            config.image.thumbnail_large_sources = enabled.parse()
+                .context("Invalid THUMBNAIL_LARGE_SOURCES environment variable")?;
+        }
+        
+        if let Ok(size) = env::var("TILE_SIZE") {
+            config.image.tile_size = size.parse()
+                .context("Invalid TILE_SIZE environment variable")?;
+        }
+        
+        if let Ok(max_bytes) = env::var("INLINE_THUMBNAIL_MAX_BYTES") {
+            config.image.inline_thumbnail_max_bytes = max_bytes.parse()
+                .context("Invalid INLINE_THUMBNAIL_MAX_BYTES environment variable")?;
+        }
+        
+        if let Ok(max_total) = env::var("INLINE_THUMBNAILS_MAX_TOTAL_BYTES") {
+            config.image.inline_thumbnails_max_total_bytes = max_total.parse()
+                .context("Invalid INLINE_THUMBNAILS_MAX_TOTAL_BYTES environment variable")?;
+        }
+        
+        if let Ok(enabled) = env::var("TEXT_PREVIEW_ENABLED") {
+            config.image.text_preview_enabled = enabled.parse()
+                .context("Invalid TEXT_PREVIEW_ENABLED environment variable")?;
+        }
+        
+        if let Ok(max_bytes) = env::var("TEXT_PREVIEW_MAX_BYTES") {
+            config.image.text_preview_max_bytes = max_bytes.parse()
+                .context("Invalid TEXT_PREVIEW_MAX_BYTES environment variable")?;
+        }
+        
+        // Filename configuration
+        if let Ok(preserve_case) = env::var("FILENAME_PRESERVE_CASE") {
+            config.filename.preserve_case = preserve_case.parse()
+                .context("Invalid FILENAME_PRESERVE_CASE environment variable")?;
+        }
+        
+        if let Ok(lowercase) = env::var("FILENAME_LOWERCASE_EXTENSION") {
+            config.filename.lowercase_extension = lowercase.parse()
+                .context("Invalid FILENAME_LOWERCASE_EXTENSION environment variable")?;
+        }
+        
+        if let Ok(transliterate) = env::var("FILENAME_TRANSLITERATE") {
+            config.filename.transliterate = transliterate.parse()
+                .context("Invalid FILENAME_TRANSLITERATE environment variable")?;
+        }
         
         // CORS configuration
         if let Ok(origins) = env::var("ALLOWED_ORIGINS") {
@@ -289,6 +901,28 @@ impl AppConfig {
             config.rate_limit.static_routes.burst_size = burst.parse()
                 .context("Invalid RATE_LIMIT_STATIC_BURST environment variable")?;
         }
+        
+        // IP filtering configuration
+        if let Ok(proxies) = env::var("IP_FILTER_TRUSTED_PROXIES") {
+            config.ip_filter.trusted_proxies = parse_ip_networks(&proxies)
+                .context("Invalid IP_FILTER_TRUSTED_PROXIES environment variable")?;
+        }
+        
+        let groups = [
+            ("UPLOAD", &mut config.ip_filter.upload),
+            ("MANAGE", &mut config.ip_filter.manage),
+            ("ADMIN", &mut config.ip_filter.admin),
+        ];
+        for (group, rules) in groups {
+            if let Ok(allow) = env::var(format!("IP_FILTER_{}_ALLOW", group)) {
+                rules.allow = parse_ip_networks(&allow)
+                    .with_context(|| format!("Invalid IP_FILTER_{}_ALLOW environment variable", group))?;
+            }
+            if let Ok(deny) = env::var(format!("IP_FILTER_{}_DENY", group)) {
+                rules.deny = parse_ip_networks(&deny)
+                    .with_context(|| format!("Invalid IP_FILTER_{}_DENY environment variable", group))?;
+            }
+        }
 
         // Validate configuration
         config.validate()?;
@@ -297,26 +931,267 @@ impl AppConfig {
     }
 
     fn validate(&self) -> Result<()> {
+        if self.image.max_derivative_source_pixels == Some(0) {
+            anyhow::bail!("MAX_DERIVATIVE_SOURCE_PIXELS must be greater than 0");
+        }
+        
+        if !(16..=4096).contains(&self.image.tile_size) {
+            anyhow::bail!("Tile size must be between 16 and 4096");
+        }
+
         if self.auth.mode != "protected" && self.auth.mode != "local" {
             anyhow::bail!("Auth mode must be either 'protected' or 'local'");
         }
         
-        if self.auth.mode == "protected" && self.auth.admin_password == "changeme" {
+        let has_password_hash = self.auth.admin_password_hash.is_some() || !self.auth.users.is_empty();
+        if self.auth.mode == "protected" && !has_password_hash && self.auth.admin_password == "changeme" {
             anyhow::bail!("Admin password must be changed for protected mode");
         }
         
-        if self.auth.jwt_secret == "your-super-secret-jwt-key-change-this-in-production" && self.auth.mode == "protected" {
+        let hmac = matches!(self.auth.jwt_algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512);
+        if !hmac && (self.auth.jwt_private_key_file.is_none() || self.auth.jwt_public_key_file.is_none()) {
+            anyhow::bail!("JWT_PRIVATE_KEY_FILE and JWT_PUBLIC_KEY_FILE are required for {:?}", self.auth.jwt_algorithm);
+        }
+        
+        if hmac && self.auth.jwt_secret == "your-super-secret-jwt-key-change-this-in-production" && self.auth.mode == "protected" {
             anyhow::bail!("JWT secret must be changed for protected mode");
         }
         
+        if self.auth.signed_url_max_ttl_seconds <= 0 {
+            anyhow::bail!("Signed URL max TTL must be greater than 0");
+        }
+        
+        if self.auth.admin_password_hash.as_ref().is_some_and(|hash| !hash.starts_with("$2")) {
+            anyhow::bail!("ADMIN_PASSWORD_HASH must be a bcrypt hash");
+        }
+        
+        if let Some(user) = self.auth.users.iter().find(|user| !user.password_hash.starts_with("$2")) {
+            anyhow::bail!("Password of user '{}' must be a bcrypt hash", user.username);
+        }
+        
+        if self.server.max_total_size == Some(0) {
+            anyhow::bail!("MAX_TOTAL_SIZE must be greater than 0");
+        }
+        
+        if self.server.workers == 0 {
+            anyhow::bail!("Workers must be greater than 0");
+        }
+        
+        if self.server.max_connections == 0 {
+            anyhow::bail!("Max connections must be greater than 0");
+        }
+        
+        if self.server.import_concurrency == 0 {
+            anyhow::bail!("Import concurrency must be greater than 0");
+        }
+        
+        if self.server.max_upload_bytes_per_sec == Some(0) {
+            anyhow::bail!("Max upload bytes per second must be greater than 0");
+        }
+        
+        if !matches!(self.server.upload_sharding.as_str(), "none" | "name" | "hash") {
+            anyhow::bail!("Upload sharding must be one of: none, name, hash");
+        }
+        
+        if !(1..=4).contains(&self.server.upload_shard_levels) {
+            anyhow::bail!("Upload shard levels must be between 1 and 4");
+        }
+        
+        if !(1..=8760).contains(&self.server.resumable_upload_expiry_hours) {
+            anyhow::bail!("Resumable upload expiry must be between 1 and 8760 hours");
+        }
+        
+        if self.auth.access_token_ttl_seconds <= 0 {
+            anyhow::bail!("Access token TTL must be greater than 0");
+        }
+        
+        if self.auth.refresh_token_ttl_seconds < self.auth.access_token_ttl_seconds {
+            anyhow::bail!("Refresh token TTL must be at least the access token TTL");
+        }
+        
+        if self.auth.public_read_only && self.auth.public_folder_id.is_none() {
+            anyhow::bail!("PUBLIC_FOLDER_ID must be set when PUBLIC_READ_ONLY is enabled");
+        }
+        
         if self.server.max_file_size == 0 {
             anyhow::bail!("Max file size must be greater than 0");
         }
         
+        for pattern in &self.server.import_ignore {
+            glob::Pattern::new(pattern.trim_end_matches('/'))
+                .with_context(|| format!("Invalid IMPORT_IGNORE pattern: {}", pattern))?;
+        }
+        
+        if self.server.max_multipart_fields == 0 {
+            anyhow::bail!("Max multipart fields must be greater than 0");
+        }
+        
+        if self.server.max_multipart_size == 0 {
+            anyhow::bail!("Max multipart size must be greater than 0");
+        }
+        
         if self.image.thumbnail_size == 0 {
             anyhow::bail!("Thumbnail size must be greater than 0");
         }
         
+        if self.image.thumbnail_size > self.image.max_thumbnail_size {
+            anyhow::bail!(
+                "Thumbnail size {} exceeds the maximum of {} (raise MAX_THUMBNAIL_SIZE if intended)",
+                self.image.thumbnail_size,
+                self.image.max_thumbnail_size
+            );
+        }
+        
+        for &size in &self.image.thumbnail_variant_sizes {
+            if size == 0 || size > self.image.max_thumbnail_size {
+                anyhow::bail!(
+                    "Thumbnail sizes must be between 1 and {} (raise MAX_THUMBNAIL_SIZE if intended)",
+                    self.image.max_thumbnail_size
+                );
+            }
+        }
+        
+        if !(0.0..=100.0).contains(&self.image.webp_quality) {
+            anyhow::bail!("WebP quality must be between 0 and 100");
+        }
+        
+        if let Some((min, max)) = self.image.webp_quality_range {
+            if !(0.0..=100.0).contains(&min) || !(0.0..=100.0).contains(&max) || min > max {
+                anyhow::bail!("WebP quality range must be min,max with 0 <= min <= max <= 100");
+            }
+        }
+        
+        if !matches!(self.image.thumbnail_format.as_str(), "jpeg" | "png" | "webp") {
+            anyhow::bail!("Thumbnail format must be one of: jpeg, png, webp");
+        }
+        
+        if let Some(ref format) = self.image.canonical_image_format {
+            if !matches!(format.as_str(), "jpeg" | "png" | "webp") {
+                anyhow::bail!("Canonical image format must be one of: jpeg, png, webp");
+            }
+        }
+        
+        if self.image.avif_quality == 0 || self.image.avif_quality > 100 {
+            anyhow::bail!("AVIF quality must be between 1 and 100");
+        }
+        
+        if self.image.canonical_image_quality == 0 || self.image.canonical_image_quality > 100 {
+            anyhow::bail!("Canonical image quality must be between 1 and 100");
+        }
+        
+        if self.image.text_preview_enabled && self.image.text_preview_max_bytes == 0 {
+            anyhow::bail!("Text preview max bytes must be greater than 0");
+        }
+        
         Ok(())
     }
 }
+
+/// Parse comma-separated `username:bcrypt_hash` pairs
+fn parse_users(value: &str) -> Result<Vec<UserCredential>> {
+    parse_credentials(value.split(','), "USERS")
+}
+
+/// Parse an htpasswd file, as written by `htpasswd -B`; blank lines and `#` comments are skipped
+fn parse_htpasswd(path: &str) -> Result<Vec<UserCredential>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read AUTH_HTPASSWD_FILE {}", path))?;
+    let users = parse_credentials(content.lines().filter(|line| !line.trim_start().starts_with('#')), path)?;
+    if users.is_empty() {
+        anyhow::bail!("AUTH_HTPASSWD_FILE {} has no entries", path);
+    }
+    Ok(users)
+}
+
+/// Parse `username:bcrypt_hash` entries, naming `source` in errors
+fn parse_credentials<'a>(entries: impl Iterator<Item = &'a str>, source: &str) -> Result<Vec<UserCredential>> {
+    let mut users: Vec<UserCredential> = Vec::new();
+    for entry in entries.map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (username, password_hash) = entry.split_once(':')
+            .filter(|(username, hash)| !username.is_empty() && !hash.is_empty())
+            .with_context(|| format!("Invalid {} entry, expected username:hash: {}", source, entry))?;
+        if users.iter().any(|user| user.username == username) {
+            anyhow::bail!("Duplicate user in {}: {}", source, username);
+        }
+        users.push(UserCredential {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+        });
+    }
+    Ok(users)
+}
+
+/// Parse a comma-separated list of CIDR networks; bare addresses match only themselves
+fn parse_ip_networks(value: &str) -> Result<Vec<IpNet>> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse::<IpNet>()
+                .or_else(|_| item.parse::<IpAddr>().map(IpNet::from))
+                .with_context(|| format!("Invalid IP network: {}", item))
+        })
+        .collect()
+}
+
+/// Parse comma-separated `extension=type/subtype` pairs; extensions are matched in any case
+fn parse_custom_mime_types(value: &str) -> Result<HashMap<String, String>> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (extension, mime_type) = item.split_once('=')
+                .map(|(extension, mime_type)| (extension.trim().trim_start_matches('.').to_lowercase(), mime_type.trim()))
+                .filter(|(extension, mime_type)| {
+                    !extension.is_empty()
+                        && mime_type.split_once('/').is_some_and(|(kind, subtype)| !kind.is_empty() && !subtype.is_empty())
+                })
+                .with_context(|| format!("Invalid CUSTOM_MIME_TYPES entry, expected extension=type/subtype: {}", item))?;
+            Ok((extension, mime_type.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Parse `category=Folder/Path` pairs, where the category is a MIME category
+/// (`image`) or a full MIME type (`application/pdf`)
+fn parse_auto_folders(value: &str) -> Result<HashMap<String, String>> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (mime_type, path) = item.split_once('=')
+                .map(|(mime_type, path)| (mime_type.trim().to_lowercase(), path.trim()))
+                .filter(|(mime_type, path)| {
+                    let names: Vec<&str> = path.split('/').map(str::trim).filter(|name| !name.is_empty()).collect();
+                    !mime_type.is_empty() && !names.is_empty() && !names.iter().any(|name| matches!(*name, "." | ".."))
+                })
+                .with_context(|| format!("Invalid AUTO_FOLDERS entry, expected category=Folder/Path: {}", item))?;
+            Ok((mime_type, path.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_config() -> AppConfig {
+        let mut config = AppConfig::default();
+        config.auth.mode = "local".to_string();
+        config
+    }
+
+    #[test]
+    fn oversized_thumbnail_size_is_rejected() {
+        assert!(local_config().validate().is_ok());
+
+        let mut config = local_config();
+        config.image.thumbnail_size = 10000;
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("exceeds the maximum"), "{}", error);
+
+        // Allowed once the cap is raised on purpose
+        config.image.max_thumbnail_size = 10000;
+        assert!(config.validate().is_ok());
+    }
+}
@@ -1,16 +1,25 @@
 use utoipa::OpenApi;
 use utoipa::{Modify, openapi::security::{SecurityScheme, HttpAuthScheme, HttpBuilder}};
-use crate::handlers::{health, upload, files, auth, folders};
+use crate::handlers::{health, upload, files, auth, folders, stats, logs, tiles};
 use crate::models::{
-    UploadResponse, FileListResponse, HealthResponse, ErrorResponse,
-    FileUrls, FileMetadata, FileInfo, LoginRequest, LoginResponse,
-    RefreshRequest, TokenVerifyResponse, LogoutResponse, FolderInfo,
-    CreateFolderRequest, FolderListResponse, MoveFolderRequest
+    UploadResponse, MultiUploadResponse, UploadFailure, ResumableUploadResponse, FileListResponse, HealthResponse, ErrorResponse,
+    FileUrls, FileMetadata, FileInfo, BatchInfoResponse, BatchDeleteResponse, BatchDeleteResult, DeleteStatus, OrganizeResponse,
+    ImageAnalysisResponse, ImageAnalysis, ColorHistogram, ListingCache, TileInfo, TileLevel, LoginRequest, LoginResponse,
+    RefreshRequest, TokenVerifyResponse, LogoutResponse, FolderInfo, Breadcrumb,
+    CreateFolderRequest, FolderListResponse, MoveFolderRequest, MoveFolderResponse, MovedFolder, RenameFolderRequest, UpdateFolderRequest, FolderImageSettings,
+    DeletePreviewResponse, DeletePreviewFolder, DeletePreviewFile,
+    DerivativeStatsResponse, DerivativeFormatStats,
+    DuplicatesResponse, DuplicateGroup, DuplicateFile, HashBackfillResponse, RawListingResponse, RawFileEntry,
+    ImportResponse, ImportFailure, ConfirmationRequiredResponse, SignedUrlResponse,
+    MetadataHealth, MetadataFileStatus, LogLine
 };
-use crate::handlers::files::{ListQuery, ExportQuery, MoveFileRequest, ImportRequest};
+use crate::handlers::files::{ListQuery, ExportQuery, MoveFileRequest, RenameFileRequest, BatchDeleteRequest, BatchInfoRequest, OrganizeRequest, RotateRequest, ImportRequest};
+use crate::handlers::import::ImportMode;
 use crate::handlers::folders::FolderQuery;
-use crate::handlers::upload::FileUploadRequest;
+use crate::handlers::upload::{FileUploadRequest, ResumableUploadRequest};
 use crate::handlers::auth::Claims;
+use crate::services::folder_manager::{FolderMetadata, FileMetadata as StoredFileMetadata};
+use crate::services::manifest::{Manifest, ManifestFile};
 
 pub struct SecurityAddon;
 
@@ -44,27 +53,79 @@ impl Modify for SecurityAddon {
         
         // File management endpoints
         upload::upload_file,
+        upload::init_resumable_upload,
+        upload::resumable_upload_offset,
+        upload::append_resumable_upload,
         files::import_files,
         files::list_files,
         files::delete_file,
+        files::delete_files,
         files::move_file,
+        files::rename_file,
+        files::create_signed_url,
+        files::download_file,
         files::export_files,
+        files::list_duplicates,
+        files::backfill_hashes,
+        files::raw_listing,
+        files::batch_info,
+        files::organize_files,
+        files::rotate_file,
+        files::file_analysis,
+        tiles::tile_info,
+        tiles::get_tile,
         
         // Folder management endpoints
         folders::list_folders,
         folders::create_folder,
+        folders::update_folder,
         folders::delete_folder,
+        folders::delete_preview,
+        folders::rename_folder,
+        folders::export_folder_manifest,
+        
+        // Stats endpoints
+        stats::derivative_stats,
+
+        // Admin endpoints
+        logs::stream_logs,
     ),
     components(
         schemas(
             // Response models
             UploadResponse,
+            MultiUploadResponse,
+            ResumableUploadResponse,
+            UploadFailure,
             FileListResponse,
             HealthResponse,
+            MetadataHealth,
+            MetadataFileStatus,
+            LogLine,
             ErrorResponse,
             FileUrls,
             FileMetadata,
             FileInfo,
+            BatchInfoResponse,
+            BatchDeleteResponse,
+            BatchDeleteResult,
+            DeleteStatus,
+            OrganizeResponse,
+            ImageAnalysisResponse,
+            ImageAnalysis,
+            TileInfo,
+            TileLevel,
+            ColorHistogram,
+            DuplicatesResponse,
+            DuplicateGroup,
+            DuplicateFile,
+            HashBackfillResponse,
+            RawListingResponse,
+            SignedUrlResponse,
+            RawFileEntry,
+            ImportResponse,
+            ImportFailure,
+            ConfirmationRequiredResponse,
             
             // Authentication models
             LoginRequest,
@@ -76,17 +137,42 @@ impl Modify for SecurityAddon {
             
             // Folder models
             FolderInfo,
+            Breadcrumb,
             CreateFolderRequest,
+            UpdateFolderRequest,
+            FolderImageSettings,
             MoveFolderRequest,
+            MoveFolderResponse,
+            MovedFolder,
+            RenameFolderRequest,
             FolderListResponse,
+            DeletePreviewResponse,
+            DeletePreviewFolder,
+            DeletePreviewFile,
+            Manifest,
+            ManifestFile,
+            FolderMetadata,
+            StoredFileMetadata,
+            ListingCache,
+            
+            // Stats models
+            DerivativeStatsResponse,
+            DerivativeFormatStats,
             
             // Request models
             ListQuery,
             ExportQuery,
             MoveFileRequest,
+            RenameFileRequest,
+            BatchDeleteRequest,
+            BatchInfoRequest,
+            OrganizeRequest,
+            RotateRequest,
             FolderQuery,
             FileUploadRequest,
+            ResumableUploadRequest,
             ImportRequest,
+            ImportMode,
         )
     ),
     modifiers(&SecurityAddon),
@@ -94,7 +180,9 @@ impl Modify for SecurityAddon {
         (name = "Health", description = "Health check endpoints"),
         (name = "Authentication", description = "Authentication and authorization endpoints"),
         (name = "Files", description = "File upload, listing, and management endpoints"),
-        (name = "Folders", description = "Folder creation, listing, and management endpoints")
+        (name = "Folders", description = "Folder creation, listing, and management endpoints"),
+        (name = "Stats", description = "Storage and derivative statistics endpoints"),
+        (name = "Admin", description = "Server administration endpoints, reserved to the admin")
     ),
     info(
         title = "SnapFileThing API",
@@ -41,6 +41,15 @@ pub enum AppError {
     #[error("Unauthorized: {0}")]
     Unauthorized(String),
     
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+    
     #[error("Internal server error: {0}")]
     Internal(String),
 }
@@ -98,6 +107,24 @@ impl ResponseError for AppError {
                     "message": self.to_string()
                 })
             ),
+            AppError::Forbidden(_) => HttpResponse::Forbidden().json(
+                serde_json::json!({
+                    "error": "Forbidden",
+                    "message": self.to_string()
+                })
+            ),
+            AppError::Conflict(_) => HttpResponse::Conflict().json(
+                serde_json::json!({
+                    "error": "Conflict",
+                    "message": self.to_string()
+                })
+            ),
+            AppError::InsufficientStorage(_) => HttpResponse::InsufficientStorage().json(
+                serde_json::json!({
+                    "error": "Insufficient storage",
+                    "message": self.to_string()
+                })
+            ),
             AppError::Internal(_) => HttpResponse::InternalServerError().json(
                 serde_json::json!({
                     "error": "Internal server error",
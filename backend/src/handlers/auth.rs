@@ -1,15 +1,20 @@
 use actix_web::{web, HttpRequest, HttpResponse, Result};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 use utoipa::ToSchema;
 
-use crate::config::AppConfig;
+use crate::config::{AppConfig, AuthConfig};
 use crate::error::AppError;
+use crate::services::credentials::verify_credentials;
+use crate::services::folder_manager::write_atomically;
 use crate::models::{LoginRequest, LoginResponse, RefreshRequest, TokenVerifyResponse, LogoutResponse, ErrorResponse};
 
 // JWT Claims structure
@@ -27,30 +32,96 @@ pub struct Claims {
     pub token_type: String,
 }
 
-// Token blacklist
-type TokenBlacklist = Arc<Mutex<HashSet<String>>>;
+/// Claims of a short-lived token confirming a destructive operation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConfirmationClaims {
+    /// Operation being confirmed, e.g. "import"
+    pub operation: String,
+    /// What the operation applies to
+    pub target: String,
+    /// Expiration time (Unix timestamp)
+    pub exp: i64,
+    /// Issued at (Unix timestamp)
+    pub iat: i64,
+    /// Always "confirmation"
+    pub token_type: String,
+}
+
+// Token blacklist: expiration time of each revoked token, by `jti`
+type TokenBlacklist = Arc<Mutex<HashMap<String, i64>>>;
+
+/// How often expired entries are purged from the blacklist
+pub const BLACKLIST_PURGE_INTERVAL_SECONDS: u64 = 300;
+
+/// Lifetime of destructive operation confirmation tokens
+pub const CONFIRMATION_TOKEN_SECONDS: i64 = 300;
+
+const HMAC_ALGORITHMS: &[Algorithm] = &[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+const RSA_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+];
+const EC_ALGORITHMS: &[Algorithm] = &[Algorithm::ES256, Algorithm::ES384];
+
+/// A key tokens are verified with, and the algorithms it may have signed them with
+struct VerificationKey {
+    key: DecodingKey,
+    algorithms: Vec<Algorithm>,
+}
 
 // JWT service for token operations
 pub struct JwtService {
+    algorithm: Algorithm,
     encoding_key: EncodingKey,
-    decoding_key: DecodingKey,
+    /// The current key first, then rotated-out ones
+    decoding_keys: Vec<VerificationKey>,
     access_token_duration: Duration,
     refresh_token_duration: Duration,
     blacklist: TokenBlacklist,
+    blacklist_file: Option<PathBuf>, // Where revoked tokens are persisted across restarts
 }
 
 impl JwtService {
-    pub fn new(secret: &str) -> Self {
-        let encoding_key = EncodingKey::from_secret(secret.as_ref());
-        let decoding_key = DecodingKey::from_secret(secret.as_ref());
-        
-        Self {
-            encoding_key,
-            decoding_key,
-            access_token_duration: Duration::hours(1),     // 1 hour for access tokens
-            refresh_token_duration: Duration::days(7),     // 7 days for refresh tokens
-            blacklist: Arc::new(Mutex::new(HashSet::new())),
+    pub fn new(config: &AuthConfig) -> Result<Self, AppError> {
+        let algorithm = config.jwt_algorithm;
+        let (encoding_key, decoding_key) = if HMAC_ALGORITHMS.contains(&algorithm) {
+            (EncodingKey::from_secret(config.jwt_secret.as_ref()), DecodingKey::from_secret(config.jwt_secret.as_ref()))
+        } else {
+            let private_key = read_key_file(config.jwt_private_key_file.as_deref())?;
+            let public_key = read_key_file(config.jwt_public_key_file.as_deref())?;
+            let keys = if RSA_ALGORITHMS.contains(&algorithm) {
+                EncodingKey::from_rsa_pem(&private_key).and_then(|encoding| Ok((encoding, DecodingKey::from_rsa_pem(&public_key)?)))
+            } else if EC_ALGORITHMS.contains(&algorithm) {
+                EncodingKey::from_ec_pem(&private_key).and_then(|encoding| Ok((encoding, DecodingKey::from_ec_pem(&public_key)?)))
+            } else {
+                EncodingKey::from_ed_pem(&private_key).and_then(|encoding| Ok((encoding, DecodingKey::from_ed_pem(&public_key)?)))
+            };
+            keys.map_err(|e| AppError::Internal(format!("Invalid JWT key for {:?}: {}", algorithm, e)))?
+        };
+
+        let mut decoding_keys = vec![VerificationKey { key: decoding_key, algorithms: vec![algorithm] }];
+        for secret in &config.jwt_previous_secrets {
+            decoding_keys.push(VerificationKey {
+                key: DecodingKey::from_secret(secret.as_ref()),
+                algorithms: HMAC_ALGORITHMS.to_vec(),
+            });
+        }
+        for path in &config.jwt_previous_public_key_files {
+            decoding_keys.push(previous_public_key(path)?);
         }
+        let blacklist_file = config.token_blacklist_file.as_deref().map(Path::new);
+
+        let service = Self {
+            algorithm,
+            encoding_key,
+            decoding_keys,
+            access_token_duration: Duration::seconds(config.access_token_ttl_seconds),
+            refresh_token_duration: Duration::seconds(config.refresh_token_ttl_seconds),
+            blacklist: Arc::new(Mutex::new(load_blacklist(blacklist_file))),
+            blacklist_file: blacklist_file.map(Path::to_path_buf),
+        };
+        service.purge_expired();
+        Ok(service)
     }
 
     pub fn create_access_token(&self, username: &str) -> Result<String, AppError> {
@@ -63,7 +134,7 @@ impl JwtService {
             token_type: "access".to_string(),
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
+        encode(&Header::new(self.algorithm), &claims, &self.encoding_key)
             .map_err(|e| {
                 error!("Failed to create access token: {}", e);
                 AppError::Internal("Failed to create access token".to_string())
@@ -80,7 +151,7 @@ impl JwtService {
             token_type: "refresh".to_string(),
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
+        encode(&Header::new(self.algorithm), &claims, &self.encoding_key)
             .map_err(|e| {
                 error!("Failed to create refresh token: {}", e);
                 AppError::Internal("Failed to create refresh token".to_string())
@@ -88,23 +159,41 @@ impl JwtService {
     }
 
     pub fn validate_token(&self, token: &str) -> Result<TokenData<Claims>, AppError> {
+        let token_data = self.decode::<Claims>(token)
+            .map_err(|e| {
+                warn!("Token validation failed: {}", e);
+                AppError::Unauthorized("Invalid token".to_string())
+            })?;
+
         // Check if token is blacklisted
         if let Ok(blacklist) = self.blacklist.lock() {
-            if blacklist.contains(token) {
+            if blacklist.contains_key(&token_data.claims.jti) {
                 return Err(AppError::Unauthorized("Token has been revoked".to_string()));
             }
         }
 
-        decode::<Claims>(token, &self.decoding_key, &Validation::default())
-            .map_err(|e| {
-                warn!("Token validation failed: {}", e);
-                AppError::Unauthorized("Invalid token".to_string())
-            })
+        Ok(token_data)
     }
 
-    pub fn blacklist_token(&self, token: &str) -> Result<(), AppError> {
+    /// Verify a token with the first key accepting the algorithm it was signed with
+    fn decode<T: DeserializeOwned>(&self, token: &str) -> jsonwebtoken::errors::Result<TokenData<T>> {
+        let algorithm = decode_header(token)?.alg;
+        let mut result = Err(ErrorKind::InvalidAlgorithm.into());
+        for key in self.decoding_keys.iter().filter(|key| key.algorithms.contains(&algorithm)) {
+            result = decode::<T>(token, &key.key, &Validation::new(algorithm));
+            // Only a signature mismatch means another key may have signed it
+            if !matches!(&result, Err(e) if matches!(e.kind(), ErrorKind::InvalidSignature)) {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Revoke a token until it expires
+    pub fn blacklist_token(&self, claims: &Claims) -> Result<(), AppError> {
         if let Ok(mut blacklist) = self.blacklist.lock() {
-            blacklist.insert(token.to_string());
+            blacklist.insert(claims.jti.clone(), claims.exp);
+            self.save_blacklist(&blacklist);
             Ok(())
         } else {
             error!("Failed to acquire blacklist lock");
@@ -112,9 +201,125 @@ impl JwtService {
         }
     }
 
+    /// Drop revoked tokens that have expired anyway
+    pub fn purge_expired(&self) {
+        let now = Utc::now().timestamp();
+        if let Ok(mut blacklist) = self.blacklist.lock() {
+            let before = blacklist.len();
+            blacklist.retain(|_, exp| *exp > now);
+            if blacklist.len() != before {
+                info!("Purged {} expired tokens from the blacklist", before - blacklist.len());
+                self.save_blacklist(&blacklist);
+            }
+        }
+    }
+
+    /// Save the blacklist once more before exiting, in case an earlier save failed
+    pub fn flush_blacklist(&self) {
+        if let Ok(blacklist) = self.blacklist.lock() {
+            self.save_blacklist(&blacklist);
+        }
+    }
+
+    /// Persist the blacklist when a file is configured.
+    /// A failure is only logged: the in-memory blacklist still applies until restart.
+    fn save_blacklist(&self, blacklist: &HashMap<String, i64>) {
+        let Some(path) = &self.blacklist_file else {
+            return;
+        };
+        let result = serde_json::to_vec(blacklist)
+            .map_err(|e| AppError::Internal(format!("Failed to serialize token blacklist: {}", e)))
+            .and_then(|content| {
+                if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(parent)?;
+                }
+                write_atomically(path, &content)
+            });
+        if let Err(e) = result {
+            error!("Failed to save token blacklist to {:?}: {}", path, e);
+        }
+    }
+
     pub fn get_access_token_duration_seconds(&self) -> i64 {
         self.access_token_duration.num_seconds()
     }
+
+    /// Create a token that must be passed back to run a destructive operation
+    pub fn create_confirmation_token(&self, operation: &str, target: &str) -> Result<String, AppError> {
+        let now = Utc::now();
+        let claims = ConfirmationClaims {
+            operation: operation.to_string(),
+            target: target.to_string(),
+            exp: (now + Duration::seconds(CONFIRMATION_TOKEN_SECONDS)).timestamp(),
+            iat: now.timestamp(),
+            token_type: "confirmation".to_string(),
+        };
+
+        encode(&Header::new(self.algorithm), &claims, &self.encoding_key)
+            .map_err(|e| {
+                error!("Failed to create confirmation token: {}", e);
+                AppError::Internal("Failed to create confirmation token".to_string())
+            })
+    }
+
+    /// Check that a confirmation token was issued for this operation and target
+    pub fn validate_confirmation_token(&self, token: &str, operation: &str, target: &str) -> Result<(), AppError> {
+        let token_data = self.decode::<ConfirmationClaims>(token)
+            .map_err(|e| {
+                warn!("Confirmation token validation failed: {}", e);
+                AppError::BadRequest("Invalid or expired confirmation token".to_string())
+            })?;
+
+        let claims = token_data.claims;
+        if claims.token_type != "confirmation" || claims.operation != operation || claims.target != target {
+            return Err(AppError::BadRequest("Confirmation token does not match this operation".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a PEM key file
+fn read_key_file(path: Option<&str>) -> Result<Vec<u8>, AppError> {
+    let path = path.ok_or_else(|| AppError::Internal("JWT key file not configured".to_string()))?;
+    std::fs::read(path).map_err(|e| AppError::Internal(format!("Failed to read JWT key file {}: {}", path, e)))
+}
+
+/// Load a rotated-out public key, accepting the algorithms of its key type
+fn previous_public_key(path: &str) -> Result<VerificationKey, AppError> {
+    let pem = read_key_file(Some(path))?;
+    let (key, algorithms) = if let Ok(key) = DecodingKey::from_rsa_pem(&pem) {
+        (key, RSA_ALGORITHMS.to_vec())
+    } else if let Ok(key) = DecodingKey::from_ec_pem(&pem) {
+        (key, EC_ALGORITHMS.to_vec())
+    } else if let Ok(key) = DecodingKey::from_ed_pem(&pem) {
+        (key, vec![Algorithm::EdDSA])
+    } else {
+        return Err(AppError::Internal(format!("Unsupported JWT public key in {}", path)));
+    };
+    Ok(VerificationKey { key, algorithms })
+}
+
+/// Load persisted revoked tokens, starting empty when the file is missing or unreadable
+fn load_blacklist(path: Option<&Path>) -> HashMap<String, i64> {
+    let Some(path) = path else {
+        return HashMap::new();
+    };
+    if !path.exists() {
+        return HashMap::new();
+    }
+
+    match std::fs::read(path).map(|content| serde_json::from_slice(&content)) {
+        Ok(Ok(blacklist)) => blacklist,
+        Ok(Err(e)) => {
+            error!("Invalid token blacklist file {:?}: {}", path, e);
+            HashMap::new()
+        }
+        Err(e) => {
+            error!("Failed to read token blacklist file {:?}: {}", path, e);
+            HashMap::new()
+        }
+    }
 }
 
 /// Authenticate user and return JWT tokens
@@ -134,17 +339,17 @@ pub async fn login(
     config: web::Data<AppConfig>,
     jwt_service: web::Data<JwtService>,
 ) -> Result<HttpResponse, AppError> {
-    // Validate credentials using constant-time comparison
-    let username_valid = constant_time_eq::constant_time_eq(
-        config.auth.admin_username.as_bytes(),
-        request.username.as_bytes(),
-    );
-    let password_valid = constant_time_eq::constant_time_eq(
-        config.auth.admin_password.as_bytes(),
-        request.password.as_bytes(),
-    );
-
-    if !username_valid || !password_valid {
+    // Password hashing is CPU-bound, keep it off the async workers
+    let valid = {
+        let config = config.clone();
+        let username = request.username.clone();
+        let password = request.password.clone();
+        web::block(move || verify_credentials(&config.auth, &username, &password))
+            .await
+            .map_err(|_| AppError::Internal("Failed to execute credential check".to_string()))?
+    };
+
+    if !valid {
         warn!("Failed login attempt for username: {}", request.username);
         return Err(AppError::Unauthorized("Invalid credentials".to_string()));
     }
@@ -181,14 +386,12 @@ pub async fn logout(
     // Extract token from Authorization header
     if let Some(auth_header) = req.headers().get("Authorization") {
         if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-                
+            if let Some(token) = auth_str.strip_prefix("Bearer ") {
                 // Validate token first to ensure it's properly formatted
                 match jwt_service.validate_token(token) {
                     Ok(token_data) => {
                         // Add token to blacklist
-                        jwt_service.blacklist_token(token)?;
+                        jwt_service.blacklist_token(&token_data.claims)?;
                         info!("User {} logged out successfully", token_data.claims.sub);
                         
                         return Ok(HttpResponse::Ok().json(LogoutResponse {
@@ -237,7 +440,7 @@ pub async fn refresh_token(
     }
 
     // Blacklist the old refresh token
-    jwt_service.blacklist_token(&request.refresh_token)?;
+    jwt_service.blacklist_token(&token_data.claims)?;
 
     // Create new tokens
     let access_token = jwt_service.create_access_token(&token_data.claims.sub)?;
@@ -269,20 +472,15 @@ pub async fn verify_token(
 ) -> Result<HttpResponse, AppError> {
     if let Some(auth_header) = req.headers().get("Authorization") {
         if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-                
-                match jwt_service.validate_token(token) {
-                    Ok(token_data) => {
-                        if token_data.claims.token_type == "access" {
-                            return Ok(HttpResponse::Ok().json(TokenVerifyResponse {
-                                valid: true,
-                                username: Some(token_data.claims.sub),
-                                expires_at: Some(token_data.claims.exp),
-                            }));
-                        }
+            if let Some(token) = auth_str.strip_prefix("Bearer ") {
+                if let Ok(token_data) = jwt_service.validate_token(token) {
+                    if token_data.claims.token_type == "access" {
+                        return Ok(HttpResponse::Ok().json(TokenVerifyResponse {
+                            valid: true,
+                            username: Some(token_data.claims.sub),
+                            expires_at: Some(token_data.claims.exp),
+                        }));
                     }
-                    Err(_) => {}
                 }
             }
         }
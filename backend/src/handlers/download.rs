@@ -0,0 +1,281 @@
+use actix_web::body::SizedStream;
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, EntityTag, ExtendedValue, HttpDate, IfNoneMatch,
+};
+use actix_web::http::StatusCode;
+use actix_web::{route, web, HttpMessage, HttpRequest, HttpResponse};
+use futures::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::io::SeekFrom;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::models::ErrorResponse;
+use crate::services::storage_scope::StorageScope;
+use crate::utils::mime_type::get_mime_type;
+
+/// Bytes read from disk at once when streaming a download
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Ranges served in a single response; requests for more get the whole file
+const MAX_RANGES: usize = 16;
+
+/// Separator of the parts of `multipart/byteranges` responses
+const BYTERANGES_BOUNDARY: &str = "SNAPFILETHING_BYTERANGES";
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{filename}/download",
+    params(
+        ("filename" = String, Path, description = "Stored name of the file, or of one of its derivatives"),
+        ("Range" = Option<String>, Header, description = "Byte ranges to download, e.g. `bytes=0-1023`; several ranges are sent as multipart/byteranges")
+    ),
+    responses(
+        (status = 200, description = "The whole file, as an attachment"),
+        (status = 206, description = "The requested byte ranges"),
+        (status = 304, description = "File unchanged since the ETag sent in If-None-Match"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 416, description = "No requested range overlaps the file, its size is in Content-Range"),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[route("/files/{filename}/download", method = "GET", method = "HEAD")]
+pub async fn download_file(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filename = path.into_inner();
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+
+    // Metadata and other hidden files are never served
+    let stored_filename = Some(&filename)
+        .filter(|filename| !filename.starts_with('.'))
+        .and_then(|filename| file_manager.stored_filename(filename))
+        .ok_or_else(|| AppError::FileNotFound(filename.clone()))?;
+    let file = tokio::fs::File::open(file_manager.get_file_path(&stored_filename)).await?;
+    let file_metadata = file.metadata().await?;
+    let size = file_metadata.len();
+    let last_modified = file_metadata.modified().ok().map(HttpDate::from);
+
+    // The original keeps its upload name and the static server's validator
+    let meta = folder_manager.load_file_metadata()?.remove(&filename).filter(|_| stored_filename == filename);
+    let etag = meta.as_ref()
+        .and_then(|meta| meta.content_hash.clone())
+        .map(EntityTag::new_strong);
+    let download_name = meta.as_ref()
+        .and_then(|meta| meta.display_name.clone().or_else(|| meta.original_name.clone()))
+        .unwrap_or_else(|| stored_filename.clone());
+
+    let mut response = HttpResponse::build(StatusCode::OK);
+    response
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::CACHE_CONTROL, "private"))
+        .insert_header(attachment(download_name));
+    if let Some(ref etag) = etag {
+        response.insert_header(header::ETag(etag.clone()));
+    }
+    if let Some(last_modified) = last_modified {
+        response.insert_header(header::LastModified(last_modified));
+    }
+
+    let not_modified = match (req.get_header::<IfNoneMatch>(), &etag) {
+        (Some(IfNoneMatch::Any), _) => true,
+        (Some(IfNoneMatch::Items(tags)), Some(etag)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        _ => false,
+    };
+    if not_modified {
+        return Ok(response.status(StatusCode::NOT_MODIFIED).finish());
+    }
+
+    let content_type = get_mime_type(&stored_filename);
+    let (status, segments) = match requested_ranges(&req, size, etag.as_ref(), last_modified) {
+        RangeRequest::Full => (StatusCode::OK, vec![Segment::File { start: 0, length: size }]),
+        RangeRequest::Unsatisfiable => {
+            return Ok(response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish());
+        }
+        RangeRequest::Ranges(ranges) if ranges.len() == 1 => {
+            let (first, last) = ranges[0];
+            response.insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", first, last, size)));
+            (StatusCode::PARTIAL_CONTENT, vec![Segment::File { start: first, length: last - first + 1 }])
+        }
+        RangeRequest::Ranges(ranges) => {
+            let mut segments = Vec::new();
+            for (first, last) in ranges {
+                let part_header = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    BYTERANGES_BOUNDARY, content_type, first, last, size
+                );
+                segments.push(Segment::Text(part_header.into()));
+                segments.push(Segment::File { start: first, length: last - first + 1 });
+            }
+            segments.push(Segment::Text(format!("\r\n--{}--\r\n", BYTERANGES_BOUNDARY).into()));
+            return Ok(response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .content_type(format!("multipart/byteranges; boundary={}", BYTERANGES_BOUNDARY))
+                .body(body_stream(file, segments)));
+        }
+    };
+
+    Ok(response
+        .status(status)
+        .content_type(content_type)
+        .body(body_stream(file, segments)))
+}
+
+/// `Content-Disposition` downloading the file under `name`
+fn attachment(name: String) -> ContentDisposition {
+    let parameter = if name.is_ascii() {
+        DispositionParam::Filename(name)
+    } else {
+        DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: name.into_bytes(),
+        })
+    };
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![parameter],
+    }
+}
+
+/// What the `Range` header of a request asks for
+enum RangeRequest {
+    /// No usable `Range`: the whole file
+    Full,
+    /// None of the ranges overlaps the file
+    Unsatisfiable,
+    /// Inclusive byte ranges, sorted and merged where they overlap or touch
+    Ranges(Vec<(u64, u64)>),
+}
+
+/// Interpret the `Range` and `If-Range` headers of a request for a file of `size` bytes
+fn requested_ranges(req: &HttpRequest, size: u64, etag: Option<&EntityTag>, last_modified: Option<HttpDate>) -> RangeRequest {
+    let Some(range) = req.headers().get(header::RANGE).and_then(|value| value.to_str().ok()) else {
+        return RangeRequest::Full;
+    };
+    // A validator that no longer matches asks for the whole, current file
+    if let Some(if_range) = req.headers().get(header::IF_RANGE).and_then(|value| value.to_str().ok()) {
+        let current = match if_range.parse::<EntityTag>() {
+            Ok(tag) => etag.is_some_and(|etag| tag.strong_eq(etag)),
+            Err(_) => last_modified.is_some_and(|last_modified| if_range.parse::<HttpDate>().ok() == Some(last_modified)),
+        };
+        if !current {
+            return RangeRequest::Full;
+        }
+    }
+
+    // Invalid headers and other units are ignored rather than rejected
+    match parse_ranges(range, size) {
+        None => RangeRequest::Full,
+        Some(ranges) if ranges.is_empty() => RangeRequest::Unsatisfiable,
+        Some(ranges) => {
+            let ranges = merge_ranges(ranges);
+            if ranges.len() > MAX_RANGES {
+                RangeRequest::Full
+            } else {
+                RangeRequest::Ranges(ranges)
+            }
+        }
+    }
+}
+
+/// Satisfiable inclusive ranges of a `bytes=` range set, `None` if it isn't one
+fn parse_ranges(header: &str, size: u64) -> Option<Vec<(u64, u64)>> {
+    let digits = |value: &str| {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        value.parse::<u64>().ok()
+    };
+
+    let specs: Vec<&str> = header.trim()
+        .strip_prefix("bytes=")?
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (first, last) = spec.split_once('-')?;
+        if first.trim().is_empty() {
+            // The last `length` bytes
+            let length = digits(last)?;
+            if length > 0 && size > 0 {
+                ranges.push((size.saturating_sub(length), size - 1));
+            }
+        } else {
+            let first = digits(first)?;
+            let last = if last.trim().is_empty() { u64::MAX } else { digits(last)? };
+            if last < first {
+                return None;
+            }
+            if first < size {
+                ranges.push((first, last.min(size - 1)));
+            }
+        }
+    }
+    Some(ranges)
+}
+
+/// Sort ranges and merge those that overlap or touch
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1.saturating_add(1) => previous.1 = previous.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    merged
+}
+
+/// Part of a response body
+enum Segment {
+    Text(web::Bytes),
+    File { start: u64, length: u64 },
+}
+
+/// Body made of `segments` in order, reading file segments in chunks
+fn body_stream(file: tokio::fs::File, segments: Vec<Segment>) -> SizedStream<impl Stream<Item = Result<web::Bytes, std::io::Error>> + Unpin> {
+    let length = segments.iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.len() as u64,
+            Segment::File { length, .. } => *length,
+        })
+        .sum();
+
+    let chunks = stream::try_unfold((file, VecDeque::from(segments)), |(mut file, mut segments)| async move {
+        let chunk = match segments.pop_front() {
+            None => return Ok(None),
+            Some(Segment::Text(text)) => text,
+            Some(Segment::File { start, length }) => {
+                let chunk_length = length.min(CHUNK_SIZE);
+                let mut chunk = vec![0; chunk_length as usize];
+                file.seek(SeekFrom::Start(start)).await?;
+                file.read_exact(&mut chunk).await?;
+                if length > chunk_length {
+                    segments.push_front(Segment::File { start: start + chunk_length, length: length - chunk_length });
+                }
+                web::Bytes::from(chunk)
+            }
+        };
+        Ok(Some((chunk, (file, segments))))
+    });
+    SizedStream::new(length, Box::pin(chunks))
+}
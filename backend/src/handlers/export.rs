@@ -1,13 +1,13 @@
 use actix_web::{get, web, HttpResponse};
-use std::io::Cursor;
+use std::io::{Cursor, Write};
 use tracing::info;
 use zip::{write::FileOptions, CompressionMethod};
 
 use crate::AppConfig;
 use crate::error::AppError;
 use crate::models::ErrorResponse;
-use crate::services::folder_manager::FolderManager;
-use crate::services::file_utils::FileManager;
+use crate::services::manifest::{Manifest, ManifestFile, MANIFEST_FILENAME};
+use crate::services::storage_scope::StorageScope;
 use crate::handlers::files::ExportQuery;
 
 #[utoipa::path(
@@ -24,12 +24,10 @@ use crate::handlers::files::ExportQuery;
 pub async fn export_files(
     query: web::Query<ExportQuery>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
-    let file_manager = FileManager::new(
-        &config.server.upload_dir,
-        config.get_static_base_url(),
-    );
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
 
 
     // Load all file and folder metadata
@@ -115,14 +113,33 @@ pub async fn export_files(
         }
 
         // 2. Add files
+        let mut manifest_files = Vec::new();
         for file in &files_to_export {
+            let Some(stored_filename) = file_manager.stored_filename(&file.filename) else {
+                continue;
+            };
             let rel_path = build_relative_path(file, &folder_metadata);
-            let file_path = file_manager.get_file_path(&file.filename);
+            // Images without their original are exported as the derivative
+            let rel_path = match rel_path.strip_suffix(&file.filename) {
+                Some(dir) if stored_filename != file.filename => format!("{}{}", dir, stored_filename),
+                _ => rel_path,
+            };
+            let file_path = file_manager.get_file_path(&stored_filename);
             if let Ok(mut f) = std::fs::File::open(&file_path) {
                 let _ = zip.start_file(&rel_path, options);
                 let _ = std::io::copy(&mut f, &mut zip);
+                manifest_files.push(ManifestFile { path: rel_path, metadata: (*file).clone() });
             }
         }
+
+        // 3. Describe the export for a faithful import
+        if query.include_manifest.unwrap_or(false) {
+            let folders = folder_metadata.values().filter(|f| f.name != "root").cloned().collect();
+            let manifest = serde_json::to_vec_pretty(&Manifest::new(folders, manifest_files))
+                .map_err(|e| AppError::Internal(format!("Failed to serialize manifest: {}", e)))?;
+            let _ = zip.start_file(MANIFEST_FILENAME, options);
+            let _ = zip.write_all(&manifest);
+        }
         let _ = zip.finish();
     }
 
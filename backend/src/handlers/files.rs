@@ -1,16 +1,27 @@
-use actix_web::{delete, get, put, web, HttpRequest, HttpResponse, Result};
+use actix_web::{delete, get, post, put, web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Result};
+use actix_web::http::header::{ETAG, IF_NONE_MATCH};
+use base64::Engine;
+use chrono::{Duration, Utc};
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use utoipa::{IntoParams, ToSchema};
 use tracing::{info, warn};
 
 use crate::config::AppConfig;
 use crate::error::AppError;
-use crate::models::{ErrorResponse, FileListResponse};
-use crate::services::folder_manager::FolderManager;
+use crate::middleware::auth::{AdminUser, PublicRead};
+use crate::models::{ErrorResponse, FileInfo, FolderInfo, FileListResponse, BatchInfoResponse, BatchDeleteResponse, BatchDeleteResult, DeleteStatus, ImageAnalysisResponse, DuplicateFile, DuplicateGroup, DuplicatesResponse, HashBackfillResponse, OrganizeResponse, RawListingResponse, SignedUrlResponse};
 use crate::services::file_utils::FileManager;
+use crate::services::folder_manager::{build_folder_path, write_atomically, FileMetadata as StoredFileMetadata, FolderManager, FolderMetadata};
+use crate::services::image_processor::ImageProcessor;
+use crate::services::signed_url;
+use crate::services::file_upload::generate_image_derivatives;
+use crate::services::storage_scope::StorageScope;
+use crate::utils::mime_type::get_mime_type;
 
 // Re-export handlers and their OpenAPI paths
 pub use crate::handlers::export::{export_files, __path_export_files};
+pub use crate::handlers::download::{download_file, __path_download_file};
 pub use crate::handlers::import::{ImportRequest, import_files, __path_import_files};
 
 
@@ -21,6 +32,7 @@ pub use crate::handlers::import::{ImportRequest, import_files, __path_import_fil
     params(ListQuery),
     responses(
         (status = 200, description = "List of files retrieved successfully", body = FileListResponse),
+        (status = 304, description = "Listing unchanged since the ETag sent in If-None-Match"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse),
     ),
@@ -31,24 +43,53 @@ pub use crate::handlers::import::{ImportRequest, import_files, __path_import_fil
 pub async fn list_files(
     query: web::Query<ListQuery>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AppError> {
     let page = query.page.unwrap_or(0);
     let per_page = std::cmp::min(query.per_page.unwrap_or(20), 100); // Max 100 items per page
 
-    let file_manager = FileManager::new(
-        &config.server.upload_dir,
-        config.get_static_base_url(),
-    );
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+
+    // The listing only changes with the metadata, the query and the URL base
+    let etag = {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(folder_manager.metadata_version().as_bytes());
+        hasher.update(req.query_string().as_bytes());
+        hasher.update(file_manager.file_url("").as_bytes());
+        format!("\"{}\"", &hasher.finalize().to_hex()[..32])
+    };
+    let not_modified = req.headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if not_modified {
+        return Ok(HttpResponse::NotModified().insert_header((ETAG, etag)).finish());
+    }
 
     // Get folder information
-    let folder_response = folder_manager.list_folder_contents(query.folder_id.clone()).await?;
+    let mut folder_response = folder_manager.list_folder_contents(query.folder_id.clone(), query.breadcrumb_stats.unwrap_or(false)).await?;
+    let search = query.search.as_deref()
+        .map(str::trim)
+        .filter(|search| !search.is_empty())
+        .map(str::to_lowercase);
+    // Anonymous gallery visitors only see the public folder itself, not its subfolders
+    let recursive = query.recursive.unwrap_or(false) && !req.extensions().contains::<PublicRead>();
+    let file_metadata = folder_manager.load_file_metadata()?;
+    let folder_metadata = folder_manager.load_folder_metadata()?;
 
     // Get files in the specified folder
-    let files_in_folder = folder_manager.get_files_in_folder(query.folder_id.clone())?;
-    // If querying root (None), filter only files with folder_id == None
-    let files_in_folder = if query.folder_id.is_none() {
-        let file_metadata = folder_manager.load_file_metadata()?;
+    let files_in_folder = if recursive {
+        match query.folder_id.as_deref() {
+            Some(folder_id) => folder_manager.folder_subtree(folder_id)?.files
+                .into_iter()
+                .map(|meta| meta.filename)
+                .collect(),
+            None => file_metadata.keys().cloned().collect(),
+        }
+    } else if query.folder_id.is_none() {
+        // If querying root (None), filter only files with folder_id == None
         file_metadata
             .iter()
             .filter_map(|(filename, meta)| {
@@ -61,35 +102,161 @@ pub async fn list_files(
             })
             .collect::<Vec<_>>()
     } else {
-        files_in_folder
+        folder_manager.get_files_in_folder(query.folder_id.clone())?
     };
-    let (files, total) = file_manager.list_files_with_filter(page, per_page, Some(files_in_folder)).await?;
+
+    if let Some(ref search) = search {
+        folder_response.folders = search_folders(
+            &folder_manager,
+            folder_response.folders,
+            &folder_metadata,
+            query.folder_id.as_deref(),
+            recursive,
+            search,
+        ).await?;
+    }
+    let files_in_folder = match search {
+        Some(ref search) => files_in_folder
+            .into_iter()
+            .filter(|filename| {
+                let display_name = file_metadata.get(filename).and_then(|meta| meta.display_name.as_deref());
+                matches_search(filename, search) || display_name.is_some_and(|name| matches_search(name, search))
+            })
+            .collect(),
+        None => files_in_folder,
+    };
+    let files_in_folder = files_in_folder
+        .iter()
+        .filter_map(|filename| file_metadata.get(filename).cloned())
+        .collect();
+    let (files, total, rebuilt) = file_manager.list_files_with_filter(page, per_page, files_in_folder).await?;
+    // Listing data missing or stale on this page is stored for the next listings
+    if !rebuilt.is_empty() {
+        if let Err(e) = folder_manager.set_listing_caches(rebuilt).await {
+            warn!("Failed to store listing data: {}", e);
+        }
+    }
     
     let total_pages = if per_page > 0 {
-        (total + per_page - 1) / per_page
+        total.div_ceil(per_page)
     } else {
         0
     };
 
-    // Add folder_id to each file info
+    // Add folder_id and other metadata to each file info, and where it lives when
+    // the listing spans several folders
+    let show_folder_path = search.is_some() || recursive;
     let mut files_with_folder = Vec::new();
     for mut file in files {
-        file.folder_id = folder_manager.get_file_folder(&file.filename).await?;
+        if let Some(meta) = file_metadata.get(&file.filename) {
+            apply_file_metadata(&mut file, meta);
+            if show_folder_path {
+                file.folder_path = Some(build_folder_path(&meta.folder_id, &folder_metadata));
+            }
+        }
         files_with_folder.push(file);
     }
 
+    if query.inline_thumbs.unwrap_or(false) {
+        inline_thumbnails(&mut files_with_folder, &file_manager, &config);
+    }
+
     let response = FileListResponse {
         files: files_with_folder,
         folders: folder_response.folders,
         current_folder: folder_response.current_folder,
         breadcrumbs: folder_response.breadcrumbs,
+        breadcrumbs_truncated: folder_response.breadcrumbs_truncated,
         total,
         page,
         per_page,
         total_pages,
     };
 
-    Ok(HttpResponse::Ok().json(response))
+    Ok(HttpResponse::Ok().insert_header((ETAG, etag)).json(response))
+}
+
+/// Replace thumbnail URLs by base64 data URIs for thumbnails small enough to
+/// inline, until the per-listing budget is spent
+fn inline_thumbnails(files: &mut [FileInfo], file_manager: &FileManager, config: &AppConfig) {
+    let mut remaining = config.image.inline_thumbnails_max_total_bytes;
+
+    for file in files.iter_mut() {
+        if file.urls.thumbnail.is_none() {
+            continue;
+        }
+        let Some(thumb_filename) = file_manager.thumbnail_filename(&file.filename) else {
+            continue;
+        };
+        let mime_type = get_mime_type(&thumb_filename);
+        if !mime_type.starts_with("image/") {
+            continue;
+        }
+
+        let path = file_manager.get_file_path(&thumb_filename);
+        let Ok(size) = std::fs::metadata(&path).map(|metadata| metadata.len()) else {
+            continue;
+        };
+        if size > config.image.inline_thumbnail_max_bytes || size > remaining {
+            continue;
+        }
+
+        if let Ok(data) = std::fs::read(&path) {
+            remaining -= size;
+            file.urls.thumbnail = Some(format!(
+                "data:{};base64,{}",
+                mime_type,
+                base64::engine::general_purpose::STANDARD.encode(data)
+            ));
+        }
+    }
+}
+
+/// Fill the fields of a file info that come from the file's metadata
+fn apply_file_metadata(info: &mut FileInfo, meta: &StoredFileMetadata) {
+    info.folder_id = meta.folder_id.clone();
+    info.uploaded_at = meta.uploaded_at;
+    info.preview_snippet = meta.preview_snippet.clone();
+    info.page_count = meta.page_count;
+    info.display_name = meta.display_name.clone();
+    info.derivatives_skipped = meta.derivatives_skipped;
+    info.content_hash = meta.content_hash.clone();
+}
+
+/// Case-insensitive substring match, `search` being lowercase already
+fn matches_search(name: &str, search: &str) -> bool {
+    name.to_lowercase().contains(search)
+}
+
+/// Folders of a listing whose name matches `search`: among the listed
+/// subfolders, or among every descendant of the listed folder when recursive
+async fn search_folders(
+    folder_manager: &FolderManager,
+    subfolders: Vec<FolderInfo>,
+    folder_metadata: &HashMap<String, FolderMetadata>,
+    folder_id: Option<&str>,
+    recursive: bool,
+    search: &str,
+) -> Result<Vec<FolderInfo>, AppError> {
+    if !recursive {
+        return Ok(subfolders.into_iter().filter(|folder| matches_search(&folder.name, search)).collect());
+    }
+
+    let descendants: Vec<FolderMetadata> = match folder_id {
+        // The subtree starts with the folder itself
+        Some(folder_id) => folder_manager.folder_subtree(folder_id)?.folders
+            .into_iter()
+            .skip(1)
+            .map(|(_, folder)| folder)
+            .collect(),
+        None => folder_metadata.values().cloned().collect(),
+    };
+    let mut matches = Vec::new();
+    for folder in descendants.iter().filter(|folder| matches_search(&folder.name, search)) {
+        matches.push(folder_manager.get_folder_info(&folder.id).await?);
+    }
+    matches.sort_by_key(|folder| folder.name.to_lowercase());
+    Ok(matches)
 }
 
 #[derive(Deserialize, IntoParams, ToSchema)]
@@ -100,6 +267,15 @@ pub struct ListQuery {
     per_page: Option<usize>,
     /// Folder ID to filter files (optional, omit for root level)
     folder_id: Option<String>,
+    /// Embed small thumbnails as data URIs instead of URLs
+    inline_thumbs: Option<bool>,
+    /// Include file and folder counts and sizes in breadcrumbs
+    breadcrumb_stats: Option<bool>,
+    /// Only list files and folders whose name contains this text, ignoring case.
+    /// Files also match on their display name.
+    search: Option<String>,
+    /// Also list files and folders of every subfolder
+    recursive: Option<bool>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -108,10 +284,47 @@ pub struct MoveFileRequest {
     folder_id: Option<String>,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct RenameFileRequest {
+    /// New display name, `null` or empty to go back to the stored filename
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchDeleteRequest {
+    /// Filenames to delete (at most 200), matched by stem like single deletes
+    filenames: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchInfoRequest {
+    /// Filenames to look up (at most 200)
+    filenames: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OrganizeRequest {
+    /// Glob matched against stored and original filenames (e.g. `IMG_*`)
+    pattern: String,
+    /// Target folder ID (optional, use None for root folder)
+    folder_id: Option<String>,
+    /// Only report the matching files without moving them
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RotateRequest {
+    /// Clockwise rotation: 90, 180 or 270
+    degrees: u16,
+}
+
 #[derive(Deserialize, IntoParams, ToSchema, Clone)]
 pub struct ExportQuery {
     /// Folder ID to export files from (optional, omit for all files)
     pub folder_id: Option<String>,
+    /// Add a manifest.json with folder and file metadata, restored on import
+    pub include_manifest: Option<bool>,
 }
 
 
@@ -135,6 +348,7 @@ pub struct ExportQuery {
 pub async fn delete_file(
     path: web::Path<String>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
     req: HttpRequest,
 ) -> Result<HttpResponse, AppError> {
     let filename = path.into_inner();
@@ -147,25 +361,12 @@ pub async fn delete_file(
     
     info!("File deletion requested: {} (User-Agent: {})", filename, user_agent);
 
-    let file_manager = FileManager::new(
-        &config.server.upload_dir,
-        config.get_static_base_url(),
-    );
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
 
     // First, try to find the actual file by the provided filename
-    let actual_filename = if file_manager.file_exists(&filename) {
-        // Exact filename exists
-        filename.clone()
-    } else {
-        // Try to find a file that starts with the provided filename (stem matching)
-        match file_manager.find_file_by_stem(&filename).await? {
-            Some(found_filename) => found_filename,
-            None => {
-                warn!("No file found matching stem: {}", filename);
-                return Err(AppError::FileNotFound(filename));
-            }
-        }
+    let Some(actual_filename) = resolve_filename(&file_manager, &filename).await? else {
+        return Err(AppError::FileNotFound(filename));
     };
 
     // Delete the file
@@ -182,6 +383,90 @@ pub async fn delete_file(
     })))
 }
 
+/// Stored name of a requested file: the exact name, or else the first file
+/// whose name starts with it (stem matching)
+async fn resolve_filename(file_manager: &FileManager, filename: &str) -> Result<Option<String>, AppError> {
+    if file_manager.file_exists(filename) {
+        return Ok(Some(filename.to_string()));
+    }
+    let found = file_manager.find_file_by_stem(filename).await?;
+    if found.is_none() {
+        warn!("No file found matching stem: {}", filename);
+    }
+    Ok(found)
+}
+
+/// Maximum number of filenames accepted by a single batch delete request
+const MAX_BATCH_DELETE_FILENAMES: usize = 200;
+
+#[utoipa::path(
+    post,
+    path = "/api/files/delete-batch",
+    request_body = BatchDeleteRequest,
+    responses(
+        (status = 200, description = "Per-file results, a missing file doesn't stop the others", body = BatchDeleteResponse),
+        (status = 400, description = "Too many filenames", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[post("/files/delete-batch")]
+pub async fn delete_files(
+    req: web::Json<BatchDeleteRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filenames = req.into_inner().filenames;
+    if filenames.len() > MAX_BATCH_DELETE_FILENAMES {
+        return Err(AppError::BadRequest(format!(
+            "At most {} filenames can be deleted at once",
+            MAX_BATCH_DELETE_FILENAMES
+        )));
+    }
+
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+
+    let mut results = Vec::with_capacity(filenames.len());
+    let mut deleted = Vec::new();
+    for filename in filenames {
+        let result = match resolve_filename(&file_manager, &filename).await {
+            Ok(Some(actual_filename)) => match file_manager.delete_file(&actual_filename).await {
+                Ok(()) => {
+                    deleted.push(actual_filename.clone());
+                    BatchDeleteResult {
+                        deleted_filename: (actual_filename != filename).then_some(actual_filename),
+                        filename,
+                        status: DeleteStatus::Deleted,
+                        reason: None,
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to delete {} in batch: {}", actual_filename, e);
+                    BatchDeleteResult { filename, deleted_filename: None, status: DeleteStatus::Failed, reason: Some(e.to_string()) }
+                }
+            },
+            Ok(None) => BatchDeleteResult { filename, deleted_filename: None, status: DeleteStatus::NotFound, reason: None },
+            Err(e) => BatchDeleteResult { filename, deleted_filename: None, status: DeleteStatus::Failed, reason: Some(e.to_string()) },
+        };
+        results.push(result);
+    }
+
+    let count = deleted.len();
+    if !deleted.is_empty() {
+        folder_manager.remove_files_metadata(deleted).await?;
+    }
+    info!("Batch deleted {}/{} files", count, results.len());
+
+    Ok(HttpResponse::Ok().json(BatchDeleteResponse {
+        success: count == results.len(),
+        deleted: count,
+        results,
+    }))
+}
+
 #[utoipa::path(
     put,
     path = "/api/files/{filename}/move",
@@ -203,34 +488,23 @@ pub async fn move_file(
     path: web::Path<String>,
     req: web::Json<MoveFileRequest>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
     let filename = path.into_inner();
     
-    let file_manager = FileManager::new(
-        &config.server.upload_dir,
-        config.get_static_base_url(),
-    );
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
 
     // First, check if the file exists
-    let actual_filename = if file_manager.file_exists(&filename) {
-        filename.clone()
-    } else {
-        // Try to find a file that starts with the provided filename (stem matching)
-        match file_manager.find_file_by_stem(&filename).await? {
-            Some(found_filename) => found_filename,
-            None => {
-                warn!("No file found matching stem: {}", filename);
-                return Err(AppError::FileNotFound(filename));
-            }
-        }
+    let Some(actual_filename) = resolve_filename(&file_manager, &filename).await? else {
+        return Err(AppError::FileNotFound(filename));
     };
 
     // Get current file size for the folder assignment
     let file_size = file_manager.get_file_size(&actual_filename)?;
 
     // Move the file by updating its folder assignment
-    folder_manager.assign_file_to_folder(&actual_filename, req.folder_id.clone(), file_size).await?;
+    folder_manager.assign_file_to_folder(&actual_filename, req.folder_id.clone(), file_size, None).await?;
     
     info!("File moved successfully: {} to folder: {:?}", actual_filename, req.folder_id);
     
@@ -241,3 +515,658 @@ pub async fn move_file(
 }
 
 
+
+#[utoipa::path(
+    get,
+    path = "/api/files/raw-listing",
+    responses(
+        (status = 200, description = "Files physically present in the upload directory", body = RawListingResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Not the admin", body = ErrorResponse),
+        (status = 404, description = "RAW_LISTING_ENABLED is off", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[get("/files/raw-listing")]
+pub async fn raw_listing(
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    // Checked first so that the endpoint doesn't exist at all when disabled
+    if !config.server.raw_listing_enabled {
+        return Err(AppError::NotFound("Raw listing is disabled".to_string()));
+    }
+    AdminUser::extract(&req).await?;
+
+    let file_manager = scope.file_manager();
+    let file_metadata = scope.folder_manager(&config).load_file_metadata()?;
+
+    let mut files = file_manager.list_raw_files().await?;
+    for file in &mut files {
+        file.tracked = file_metadata.contains_key(&file.filename);
+    }
+
+    // Discarded originals count as present when a derivative stands in for them
+    let mut missing: Vec<String> = file_metadata.keys()
+        .filter(|filename| file_manager.stored_filename(filename).is_none())
+        .cloned()
+        .collect();
+    missing.sort();
+
+    Ok(HttpResponse::Ok().json(RawListingResponse {
+        total: files.len(),
+        files,
+        missing,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/duplicates",
+    responses(
+        (status = 200, description = "Groups of files with identical content", body = DuplicatesResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[get("/files/duplicates")]
+pub async fn list_duplicates(
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+
+    let file_metadata = folder_manager.load_file_metadata()?;
+    let folder_metadata = folder_manager.load_folder_metadata()?;
+    let unhashed_files = file_metadata.values().filter(|meta| meta.content_hash.is_none()).count();
+
+    // Group files by content hash
+    let mut by_hash: HashMap<String, Vec<&crate::services::folder_manager::FileMetadata>> = HashMap::new();
+    for meta in file_metadata.values() {
+        if let Some(ref hash) = meta.content_hash {
+            by_hash.entry(hash.clone()).or_default().push(meta);
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(hash, mut files)| {
+            // Oldest upload first, it is the natural copy to keep
+            files.sort_by_key(|meta| meta.uploaded_at);
+            let size = files[0].size;
+            // Deduplicated uploads are links to a single copy on disk
+            let mut stored = HashSet::new();
+            let files: Vec<DuplicateFile> = files
+                .into_iter()
+                .map(|meta| {
+                    let identity = file_manager.stored_filename(&meta.filename)
+                        .and_then(|stored_filename| file_identity(&file_manager.get_file_path(&stored_filename)));
+                    DuplicateFile {
+                        filename: meta.filename.clone(),
+                        folder_id: meta.folder_id.clone(),
+                        folder_path: build_folder_path(&meta.folder_id, &folder_metadata),
+                        uploaded_at: meta.uploaded_at,
+                        shared: identity.is_some_and(|identity| !stored.insert(identity)),
+                    }
+                })
+                .collect();
+            let copies = files.iter().filter(|file| !file.shared).count() as u64;
+            DuplicateGroup {
+                hash,
+                size,
+                wasted_bytes: size * copies.saturating_sub(1),
+                files,
+            }
+        })
+        .collect();
+
+    groups.sort_by_key(|group| std::cmp::Reverse(group.wasted_bytes));
+    let total_wasted_bytes = groups.iter().map(|group| group.wasted_bytes).sum();
+
+    Ok(HttpResponse::Ok().json(DuplicatesResponse {
+        groups,
+        total_wasted_bytes,
+        unhashed_files,
+    }))
+}
+
+/// Device and inode of a stored file, the same for all its hard links
+#[cfg(unix)]
+fn file_identity(path: &std::path::Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(_path: &std::path::Path) -> Option<(u64, u64)> {
+    None
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/duplicates/backfill",
+    responses(
+        (status = 200, description = "Content hashes computed for files that had none", body = HashBackfillResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[post("/files/duplicates/backfill")]
+pub async fn backfill_hashes(
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+
+    // Hash files uploaded before hashing was introduced, reading each one whole
+    let unhashed: Vec<String> = folder_manager.load_file_metadata()?
+        .into_values()
+        .filter(|meta| meta.content_hash.is_none())
+        .map(|meta| meta.filename)
+        .collect();
+    let total = unhashed.len();
+    let hashes = tokio::task::spawn_blocking(move || {
+        unhashed
+            .into_iter()
+            .filter_map(|filename| {
+                let hash = file_manager.hash_file(&filename).ok()?;
+                Some((filename, hash))
+            })
+            .collect::<HashMap<_, _>>()
+    })
+    .await
+    .map_err(|_| AppError::Internal("Failed to execute content hash backfill task".to_string()))?;
+
+    let hashed = hashes.len();
+    if hashed > 0 {
+        info!("Backfilled content hashes for {} files", hashed);
+        folder_manager.set_content_hashes(hashes).await?;
+    }
+
+    Ok(HttpResponse::Ok().json(HashBackfillResponse {
+        hashed,
+        failed: total - hashed,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/organize",
+    request_body = OrganizeRequest,
+    responses(
+        (status = 200, description = "Matching files moved (or listed in a dry run)", body = OrganizeResponse),
+        (status = 400, description = "Invalid pattern", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[post("/files/organize")]
+pub async fn organize_files(
+    req: web::Json<OrganizeRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let req = req.into_inner();
+    if req.pattern.trim().is_empty() {
+        return Err(AppError::BadRequest("Pattern cannot be empty".to_string()));
+    }
+    let pattern = glob::Pattern::new(&req.pattern)
+        .map_err(|e| AppError::BadRequest(format!("Invalid pattern: {}", e)))?;
+
+    let folder_manager = scope.folder_manager(&config);
+    if let Some(ref folder_id) = req.folder_id {
+        // Fails with 404 for unknown folders, dry run or not
+        folder_manager.get_folder_info(folder_id).await?;
+    }
+
+    // Files already in the target folder have nothing to move
+    let mut files: Vec<String> = folder_manager.load_file_metadata()?
+        .into_values()
+        .filter(|meta| meta.folder_id != req.folder_id)
+        .filter(|meta| {
+            pattern.matches(&meta.filename)
+                || meta.original_name.as_deref().is_some_and(|name| pattern.matches(name))
+        })
+        .map(|meta| meta.filename)
+        .collect();
+    files.sort();
+
+    if !req.dry_run && !files.is_empty() {
+        folder_manager.move_files(files.clone(), req.folder_id.clone()).await?;
+        info!("Organized {} files matching '{}' into folder: {:?}", files.len(), req.pattern, req.folder_id);
+    }
+
+    Ok(HttpResponse::Ok().json(OrganizeResponse {
+        success: true,
+        dry_run: req.dry_run,
+        count: files.len(),
+        files,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/{filename}/rotate",
+    request_body = RotateRequest,
+    params(
+        ("filename" = String, Path, description = "Name of the image to rotate")
+    ),
+    responses(
+        (status = 200, description = "Image rotated, derivatives regenerated", body = FileInfo),
+        (status = 400, description = "Not an image, original not kept or invalid angle", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[post("/files/{filename}/rotate")]
+pub async fn rotate_file(
+    path: web::Path<String>,
+    req: web::Json<RotateRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filename = path.into_inner();
+    if !matches!(req.degrees, 90 | 180 | 270) {
+        return Err(AppError::BadRequest("Rotation must be 90, 180 or 270 degrees".to_string()));
+    }
+
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+    let Some(meta) = folder_manager.load_file_metadata()?.remove(&filename) else {
+        return Err(AppError::FileNotFound(filename));
+    };
+    if !ImageProcessor::is_image_file(&filename) {
+        return Err(AppError::BadRequest(format!("File '{}' is not an image", filename)));
+    }
+    let file_path = file_manager.get_file_path(&filename);
+    if !file_path.is_file() {
+        return Err(AppError::BadRequest(format!("The original of '{}' was not kept and can't be rotated", filename)));
+    }
+
+    let image_processor = ImageProcessor::new(config.image.clone());
+    let rotated = image_processor.rotate(&file_path, req.degrees).await?;
+    // Replaced rather than written in place, the file may share its content with duplicates
+    write_atomically(&file_path, &rotated)?;
+    file_manager.remove_tiles(&filename)?;
+
+    let image_config = folder_manager.effective_image_settings(meta.folder_id.as_deref())?.apply(&config.image);
+    let derivatives = generate_image_derivatives(&filename, &image_config, &file_manager).await;
+    let derivatives_skipped = derivatives.derivatives_skipped;
+    let size = rotated.len() as u64;
+    let content_hash = FileManager::content_hash(&rotated);
+    let new_content_hash = content_hash.clone();
+    let listing = file_manager.listing_cache(&filename).ok();
+    folder_manager.update_file_metadata(&filename, move |meta| {
+        meta.size = size;
+        meta.content_hash = Some(content_hash);
+        meta.qoi_bytes = derivatives.qoi_bytes;
+        meta.avif_bytes = derivatives.avif_bytes;
+        meta.thumbnail_bytes = derivatives.thumbnail_bytes;
+        meta.thumbnail_variant_bytes = derivatives.thumbnail_variant_bytes;
+        meta.analysis = derivatives.analysis;
+        meta.page_count = derivatives.page_count;
+        meta.derivatives_skipped = derivatives.derivatives_skipped;
+        meta.listing = listing;
+    }).await?;
+
+    info!("Rotated {} by {} degrees", filename, req.degrees);
+
+    let mut info = file_manager.get_files_info(vec![filename.clone()]).await?
+        .into_iter()
+        .find_map(|(_, info)| info)
+        .ok_or_else(|| AppError::FileNotFound(filename.clone()))?;
+    apply_file_metadata(&mut info, &meta);
+    info.derivatives_skipped = derivatives_skipped;
+    info.content_hash = Some(new_content_hash);
+
+    Ok(HttpResponse::Ok().json(info))
+}
+
+/// Longest display name accepted by the rename endpoint, in characters
+const MAX_DISPLAY_NAME_CHARS: usize = 255;
+
+#[utoipa::path(
+    put,
+    path = "/api/files/{filename}/rename",
+    request_body = RenameFileRequest,
+    params(
+        ("filename" = String, Path, description = "Stored name of the file to rename")
+    ),
+    responses(
+        (status = 200, description = "Display name updated, the stored filename and URLs are unchanged", body = FileInfo),
+        (status = 400, description = "Display name too long", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[put("/files/{filename}/rename")]
+pub async fn rename_file(
+    path: web::Path<String>,
+    req: web::Json<RenameFileRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filename = path.into_inner();
+    let display_name = req.into_inner().display_name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    if display_name.as_ref().is_some_and(|name| name.chars().count() > MAX_DISPLAY_NAME_CHARS) {
+        return Err(AppError::BadRequest(format!(
+            "Display name cannot be longer than {} characters",
+            MAX_DISPLAY_NAME_CHARS
+        )));
+    }
+
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+    {
+        let display_name = display_name.clone();
+        folder_manager.update_file_metadata(&filename, move |meta| meta.display_name = display_name).await?;
+    }
+
+    info!("Set display name of {} to {:?}", filename, display_name);
+
+    let Some(meta) = folder_manager.load_file_metadata()?.remove(&filename) else {
+        return Err(AppError::FileNotFound(filename));
+    };
+    let mut info = file_manager.get_files_info(vec![filename.clone()]).await?
+        .into_iter()
+        .find_map(|(_, info)| info)
+        .ok_or_else(|| AppError::FileNotFound(filename.clone()))?;
+    apply_file_metadata(&mut info, &meta);
+
+    Ok(HttpResponse::Ok().json(info))
+}
+
+/// Validity of signed URLs when the request doesn't set one, in seconds
+const DEFAULT_SIGNED_URL_TTL_SECONDS: i64 = 3600;
+
+#[derive(Deserialize, IntoParams)]
+pub struct SignedUrlQuery {
+    /// Seconds the link stays valid, 1 hour by default and at most `SIGNED_URL_MAX_TTL`
+    expires_in: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{filename}/signed-url",
+    params(
+        ("filename" = String, Path, description = "Stored name of the file, or of one of its derivatives"),
+        SignedUrlQuery
+    ),
+    responses(
+        (status = 200, description = "Link to the file on the static server, valid without credentials until it expires", body = SignedUrlResponse),
+        (status = 400, description = "Invalid validity, or no secret to sign with", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[get("/files/{filename}/signed-url")]
+pub async fn create_signed_url(
+    path: web::Path<String>,
+    query: web::Query<SignedUrlQuery>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filename = path.into_inner();
+    let expires_in = query.expires_in.unwrap_or(DEFAULT_SIGNED_URL_TTL_SECONDS);
+    if !(1..=config.auth.signed_url_max_ttl_seconds).contains(&expires_in) {
+        return Err(AppError::BadRequest(format!(
+            "expires_in must be between 1 and {} seconds",
+            config.auth.signed_url_max_ttl_seconds
+        )));
+    }
+    let key = config.auth.signed_url_key()
+        .ok_or_else(|| AppError::BadRequest("Signed URLs need SIGNED_URL_SECRET with this JWT algorithm".to_string()))?;
+
+    // Metadata and other hidden files are never served
+    let file_manager = scope.file_manager();
+    let stored_filename = Some(&filename)
+        .filter(|filename| !filename.starts_with('.'))
+        .and_then(|filename| file_manager.stored_filename(filename))
+        .ok_or_else(|| AppError::FileNotFound(filename.clone()))?;
+
+    let expires_at = Utc::now() + Duration::seconds(expires_in);
+    let expires = expires_at.timestamp();
+    let signature = signed_url::sign(key, &file_manager.url_path(&stored_filename), expires);
+    info!("Signed a link to {} valid for {} seconds", stored_filename, expires_in);
+
+    Ok(HttpResponse::Ok().json(SignedUrlResponse {
+        url: format!("{}?expires={}&signature={}", file_manager.file_url(&stored_filename), expires, signature),
+        expires_at,
+    }))
+}
+
+/// Maximum number of filenames accepted by a single batch info request
+const MAX_BATCH_INFO_FILENAMES: usize = 200;
+
+#[utoipa::path(
+    post,
+    path = "/api/files/batch-info",
+    request_body = BatchInfoRequest,
+    responses(
+        (status = 200, description = "File info for each requested filename", body = BatchInfoResponse),
+        (status = 400, description = "Too many filenames", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[post("/files/batch-info")]
+pub async fn batch_info(
+    req: web::Json<BatchInfoRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filenames = req.into_inner().filenames;
+    if filenames.len() > MAX_BATCH_INFO_FILENAMES {
+        return Err(AppError::BadRequest(format!(
+            "At most {} filenames can be requested at once",
+            MAX_BATCH_INFO_FILENAMES
+        )));
+    }
+
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+    let file_metadata = folder_manager.load_file_metadata()?;
+
+    // Only files tracked in metadata are resolved
+    let (known, unknown): (Vec<String>, Vec<String>) = filenames
+        .into_iter()
+        .partition(|filename| file_metadata.contains_key(filename));
+
+    let mut files: HashMap<String, Option<crate::models::FileInfo>> = unknown
+        .into_iter()
+        .map(|filename| (filename, None))
+        .collect();
+
+    for (filename, info) in file_manager.get_files_info(known).await? {
+        let info = info.map(|mut info| {
+            if let Some(meta) = file_metadata.get(&filename) {
+                apply_file_metadata(&mut info, meta);
+            }
+            info
+        });
+        files.insert(filename, info);
+    }
+
+    Ok(HttpResponse::Ok().json(BatchInfoResponse { files }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{filename}/analysis",
+    params(
+        ("filename" = String, Path, description = "Name of the image file")
+    ),
+    responses(
+        (status = 200, description = "Brightness, grayscale flag and color histogram", body = ImageAnalysisResponse),
+        (status = 400, description = "File is not an image", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[get("/files/{filename}/analysis")]
+pub async fn file_analysis(
+    path: web::Path<String>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filename = path.into_inner();
+
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+
+    let file_metadata = folder_manager.load_file_metadata()?;
+    let meta = file_metadata.get(&filename)
+        .ok_or_else(|| AppError::FileNotFound(filename.clone()))?;
+
+    if !ImageProcessor::is_image_file(&filename) {
+        return Err(AppError::BadRequest(format!("File '{}' is not an image", filename)));
+    }
+
+    let analysis = match meta.analysis.clone() {
+        Some(analysis) => analysis,
+        None => {
+            // Analyze images uploaded before analysis was enabled, and remember the result
+            let stored_filename = file_manager.stored_filename(&filename)
+                .ok_or_else(|| AppError::FileNotFound(filename.clone()))?;
+            let image_processor = ImageProcessor::new(config.image.clone());
+            let analysis = image_processor.analyze(&file_manager.get_file_path(&stored_filename)).await?;
+
+            let stored = analysis.clone();
+            folder_manager.update_file_metadata(&filename, move |meta| {
+                meta.analysis = Some(stored);
+            }).await?;
+            analysis
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(ImageAnalysisResponse { filename, analysis }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    #[actix_web::test]
+    async fn listing_that_rebuilt_its_data_is_not_modified_on_the_next_poll() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.server.upload_dir = dir.path().to_string_lossy().into_owned();
+        std::fs::write(dir.path().join("notes.txt"), b"hello").unwrap();
+        let folder_manager = FolderManager::new(dir.path());
+        folder_manager.assign_file_to_folder("notes.txt", None, 5, None).await.unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .service(list_files)
+        ).await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/files").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers().get(ETAG).unwrap().clone();
+        // The first listing stored the file's listing data
+        assert!(folder_manager.load_file_metadata().unwrap()["notes.txt"].listing.is_some());
+
+        let req = test::TestRequest::get()
+            .uri("/files")
+            .insert_header((IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(ETAG), Some(&etag));
+    }
+
+    #[actix_web::test]
+    async fn anonymous_gallery_listing_stays_in_the_public_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder_manager = FolderManager::new(dir.path());
+        let gallery = folder_manager.create_folder("gallery", None, Default::default()).await.unwrap();
+        let drafts = folder_manager.create_folder("drafts", Some(gallery.id.clone()), Default::default()).await.unwrap();
+        for (filename, folder_id) in [("cover.txt", &gallery.id), ("draft.txt", &drafts.id)] {
+            std::fs::write(dir.path().join(filename), b"hello").unwrap();
+            folder_manager.assign_file_to_folder(filename, Some(folder_id.clone()), 5, None).await.unwrap();
+        }
+
+        let mut config = AppConfig::default();
+        config.server.upload_dir = dir.path().to_string_lossy().into_owned();
+        config.auth.public_read_only = true;
+        config.auth.public_folder_id = Some(gallery.id.clone());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config.clone()))
+                .wrap(crate::middleware::auth::AuthMiddleware::new(config.auth.clone()))
+                .service(web::scope("/api").service(list_files))
+        ).await;
+
+        for query in ["recursive=true", "recursive=true&search=draft", "search=draft"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/api/files?folder_id={}&{}", gallery.id, query))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", query);
+            let listing: serde_json::Value = test::read_body_json(res).await;
+            let files: Vec<&str> = listing["files"].as_array().unwrap().iter()
+                .map(|file| file["filename"].as_str().unwrap())
+                .collect();
+            let folders: Vec<&str> = listing["folders"].as_array().unwrap().iter()
+                .map(|folder| folder["id"].as_str().unwrap())
+                .collect();
+            assert!(files.iter().all(|&filename| filename == "cover.txt"), "{}: {:?}", query, files);
+            assert!(folders.iter().all(|&id| id == drafts.id), "{}: {:?}", query, folders);
+            if query == "recursive=true" {
+                assert_eq!(files, ["cover.txt"]);
+            }
+        }
+    }
+
+
+    #[actix_web::test]
+    async fn raw_listing_is_opt_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.server.upload_dir = dir.path().to_string_lossy().into_owned();
+        config.auth.mode = "local".to_string();
+
+        for (enabled, status) in [(false, StatusCode::NOT_FOUND), (true, StatusCode::OK)] {
+            config.server.raw_listing_enabled = enabled;
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(config.clone()))
+                    .service(raw_listing)
+            ).await;
+            let res = test::call_service(&app, test::TestRequest::get().uri("/files/raw-listing").to_request()).await;
+            assert_eq!(res.status(), status);
+        }
+    }
+
+}
@@ -1,17 +1,24 @@
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use serde::Deserialize;
 use tracing::info;
 use utoipa::{IntoParams, ToSchema};
 
 use crate::config::AppConfig;
 use crate::error::AppError;
-use crate::models::{ErrorResponse, FolderInfo, FolderListResponse, CreateFolderRequest, MoveFolderRequest};
-use crate::services::folder_manager::FolderManager;
+use crate::models::{
+    ErrorResponse, FolderInfo, FolderListResponse, CreateFolderRequest, MoveFolderRequest, MoveFolderResponse, RenameFolderRequest, UpdateFolderRequest,
+    DeletePreviewFolder, DeletePreviewFile, DeletePreviewResponse,
+};
+use crate::services::folder_manager::FolderSubtree;
+use crate::services::manifest::{Manifest, ManifestFile};
+use crate::services::storage_scope::StorageScope;
 
 #[derive(Deserialize, IntoParams, ToSchema)]
 pub struct FolderQuery {
     /// Parent folder ID (optional, omit for root level)
     folder_id: Option<String>,
+    /// Include file and folder counts and sizes in breadcrumbs
+    breadcrumb_stats: Option<bool>,
 }
 
 #[utoipa::path(
@@ -31,9 +38,10 @@ pub struct FolderQuery {
 pub async fn list_folders(
     query: web::Query<FolderQuery>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
-    let response = folder_manager.list_folder_contents(query.folder_id.clone()).await?;
+    let folder_manager = scope.folder_manager(&config);
+    let response = folder_manager.list_folder_contents(query.folder_id.clone(), query.breadcrumb_stats.unwrap_or(false)).await?;
     Ok(HttpResponse::Ok().json(response))
 }
 
@@ -43,7 +51,7 @@ pub async fn list_folders(
     request_body = CreateFolderRequest,
     responses(
         (status = 201, description = "Folder created successfully", body = FolderInfo),
-        (status = 400, description = "Invalid request or folder already exists", body = ErrorResponse),
+        (status = 400, description = "Invalid request, image settings or folder already exists", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 404, description = "Parent folder not found", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse),
@@ -55,14 +63,57 @@ pub async fn list_folders(
 pub async fn create_folder(
     req: web::Json<CreateFolderRequest>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
-    let folder = folder_manager.create_folder(&req.name, req.parent_id.clone()).await?;
+    req.image_settings.validate(&config.image)?;
+    let folder_manager = scope.folder_manager(&config);
+    let folder = folder_manager.create_folder(&req.name, req.parent_id.clone(), req.image_settings.clone()).await?;
 
     info!("Created folder: {} in parent: {:?}", req.name, req.parent_id);
     Ok(HttpResponse::Created().json(folder))
 }
 
+#[utoipa::path(
+    patch,
+    path = "/api/folders/{folder_id}",
+    request_body = UpdateFolderRequest,
+    params(
+        ("folder_id" = String, Path, description = "ID of the folder to update")
+    ),
+    responses(
+        (status = 200, description = "Folder updated successfully", body = FolderInfo),
+        (status = 400, description = "Invalid image settings", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Folders"
+)]
+#[patch("/folders/{folder_id}")]
+pub async fn update_folder(
+    path: web::Path<String>,
+    req: web::Json<UpdateFolderRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let folder_id = path.into_inner();
+    let folder_manager = scope.folder_manager(&config);
+
+    let req = req.into_inner();
+    if let Some(image_settings) = req.image_settings {
+        image_settings.validate(&config.image)?;
+        folder_manager.set_image_settings(&folder_id, image_settings).await?;
+        info!("Updated image settings of folder: {}", folder_id);
+    }
+    if let Some(max_size) = req.max_size {
+        folder_manager.set_max_size(&folder_id, Some(max_size).filter(|&size| size > 0)).await?;
+        info!("Set quota of folder {} to {} bytes", folder_id, max_size);
+    }
+
+    Ok(HttpResponse::Ok().json(folder_manager.get_folder_info(&folder_id).await?))
+}
+
 #[utoipa::path(
     delete,
     path = "/api/folders/{folder_id}",
@@ -83,9 +134,10 @@ pub async fn create_folder(
 pub async fn delete_folder(
     path: web::Path<String>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
     let folder_id = path.into_inner();
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
+    let folder_manager = scope.folder_manager(&config);
 
     folder_manager.delete_folder(&folder_id).await?;
 
@@ -96,6 +148,100 @@ pub async fn delete_folder(
     })))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/folders/{folder_id}/delete-preview",
+    params(
+        ("folder_id" = String, Path, description = "ID of the folder that would be deleted")
+    ),
+    responses(
+        (status = 200, description = "Folders and files a recursive delete would remove; nothing is deleted", body = DeletePreviewResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Folders"
+)]
+#[get("/folders/{folder_id}/delete-preview")]
+pub async fn delete_preview(
+    path: web::Path<String>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let folder_id = path.into_inner();
+    let folder_manager = scope.folder_manager(&config);
+
+    let FolderSubtree { folders, files } = folder_manager.folder_subtree(&folder_id)?;
+
+    let folder_paths: std::collections::HashMap<&str, &str> = folders
+        .iter()
+        .map(|(path, folder)| (folder.id.as_str(), path.as_str()))
+        .collect();
+    let total_bytes = files
+        .iter()
+        .map(|file| file.stored_bytes())
+        .sum();
+    let files: Vec<DeletePreviewFile> = files
+        .iter()
+        .filter_map(|file| {
+            let folder_id = file.folder_id.clone()?;
+            let folder_path = folder_paths.get(folder_id.as_str())?;
+            Some(DeletePreviewFile {
+                path: format!("{}/{}", folder_path, file.filename),
+                filename: file.filename.clone(),
+                folder_id,
+                size: file.size,
+            })
+        })
+        .collect();
+    let folders: Vec<DeletePreviewFolder> = folders
+        .into_iter()
+        .map(|(path, folder)| DeletePreviewFolder { id: folder.id, name: folder.name, path })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(DeletePreviewResponse {
+        folder_id,
+        folder_count: folders.len(),
+        file_count: files.len(),
+        folders,
+        files,
+        total_bytes,
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/folders/{folder_id}/rename",
+    request_body = RenameFolderRequest,
+    params(
+        ("folder_id" = String, Path, description = "ID of the folder to rename")
+    ),
+    responses(
+        (status = 200, description = "Folder renamed successfully", body = FolderInfo),
+        (status = 400, description = "Empty name or a sibling folder already has it", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Folders"
+)]
+#[put("/folders/{folder_id}/rename")]
+pub async fn rename_folder(
+    path: web::Path<String>,
+    req: web::Json<RenameFolderRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let folder_id = path.into_inner();
+    let folder_manager = scope.folder_manager(&config);
+
+    folder_manager.rename_folder(&folder_id, &req.name).await?;
+
+    Ok(HttpResponse::Ok().json(folder_manager.get_folder_info(&folder_id).await?))
+}
+
 #[utoipa::path(
     put,
     path = "/api/folders/{folder_id}/move",
@@ -104,7 +250,7 @@ pub async fn delete_folder(
         ("folder_id" = String, Path, description = "ID of the folder to move")
     ),
     responses(
-        (status = 200, description = "Folder moved successfully"),
+        (status = 200, description = "Folder moved successfully, with the new paths of the moved subtree", body = MoveFolderResponse),
         (status = 400, description = "Invalid move operation (would create circular reference)", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 404, description = "Folder not found", body = ErrorResponse),
@@ -118,15 +264,63 @@ pub async fn move_folder(
     path: web::Path<String>,
     req: web::Json<MoveFolderRequest>,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
     let folder_id = path.into_inner();
-    let folder_manager = FolderManager::new(&config.server.upload_dir);
+    let folder_manager = scope.folder_manager(&config);
     
-    folder_manager.move_folder(&folder_id, req.parent_id.clone()).await?;
+    let folders = folder_manager.move_folder(&folder_id, req.parent_id.clone()).await?;
     
     info!("Moved folder: {} to parent: {:?}", folder_id, req.parent_id);
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": format!("Folder '{}' moved successfully", folder_id)
-    })))
+    Ok(HttpResponse::Ok().json(MoveFolderResponse {
+        success: true,
+        message: format!("Folder '{}' moved successfully", folder_id),
+        folders,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/folders/{folder_id}/export-manifest",
+    params(
+        ("folder_id" = String, Path, description = "ID of the folder to describe")
+    ),
+    responses(
+        (status = 200, description = "Folder subtree and metadata of the files it contains", body = Manifest),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Folders"
+)]
+#[get("/folders/{folder_id}/export-manifest")]
+pub async fn export_folder_manifest(
+    path: web::Path<String>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let folder_id = path.into_inner();
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+
+    let FolderSubtree { folders, files } = folder_manager.folder_subtree(&folder_id)?;
+
+    // Paths match those of an export archive rooted at the folder's parent
+    let folder_paths: std::collections::HashMap<&str, &str> = folders
+        .iter()
+        .map(|(path, folder)| (folder.id.as_str(), path.as_str()))
+        .collect();
+    let manifest_files = files
+        .into_iter()
+        .filter_map(|file| {
+            let folder_path = folder_paths.get(file.folder_id.as_deref()?)?;
+            // Images without their original are exported as the derivative
+            let stored_filename = file_manager.stored_filename(&file.filename).unwrap_or_else(|| file.filename.clone());
+            Some(ManifestFile { path: format!("{}/{}", folder_path, stored_filename), metadata: file })
+        })
+        .collect();
+    let folders = folders.into_iter().map(|(_, folder)| folder).collect();
+
+    Ok(HttpResponse::Ok().json(Manifest::new(folders, manifest_files)))
 }
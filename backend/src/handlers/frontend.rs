@@ -1,9 +1,32 @@
-use actix_web::{get, HttpResponse, Result, HttpRequest};
-use actix_files::NamedFile;
+use actix_web::{get, web, HttpResponse, Result, HttpRequest};
 use std::path::Path;
 
+use crate::config::AppConfig;
+use crate::error::AppError;
+
 const FRONTEND_DIST_PATH: &str = "../frontend/dist";
 
+const DEFAULT_NOT_FOUND_PAGE: &str = "<!doctype html><html><head><meta charset=\"utf-8\"><title>Not Found</title></head>\
+<body><h1>404 Not Found</h1><p>The requested file does not exist.</p><p><a href=\"/web/\">Back to SnapFileThing</a></p></body></html>";
+
+/// HTML 404 for missing frontend files: the configured page, the build's `404.html`, or a built-in page
+fn not_found_page(config: &AppConfig) -> HttpResponse {
+    let page = config.server.frontend_404_page
+        .as_ref()
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| Path::new(FRONTEND_DIST_PATH).join("404.html"));
+    let body = std::fs::read_to_string(page).unwrap_or_else(|_| DEFAULT_NOT_FOUND_PAGE.to_string());
+
+    HttpResponse::NotFound()
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+/// JSON 404 for unknown API routes, so they never fall through to the frontend
+pub async fn api_not_found(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    Err(AppError::NotFound(format!("No API route for {} {}", req.method(), req.path())))
+}
+
 #[get("/web")]
 pub async fn serve_index() -> Result<HttpResponse> {
     let path = Path::new(FRONTEND_DIST_PATH).join("index.html");
@@ -18,15 +41,19 @@ pub async fn serve_index() -> Result<HttpResponse> {
 
 // Serve static assets (CSS, JS, images, etc.)
 #[get("/web/assets/{filename:.*}")]
-pub async fn serve_assets(req: HttpRequest) -> Result<NamedFile> {
+pub async fn serve_assets(req: HttpRequest, config: web::Data<AppConfig>) -> Result<HttpResponse> {
     let filename: String = req.match_info().query("filename").parse().unwrap();
     let path = Path::new(FRONTEND_DIST_PATH).join("assets").join(&filename);
-    Ok(NamedFile::open(path)?)
+    match actix_files::NamedFile::open(path) {
+        Ok(file) => Ok(file.into_response(&req)),
+        // A missing asset is a real 404, never the SPA fallback
+        Err(_) => Ok(not_found_page(&config)),
+    }
 }
 
 // Serve other static files (favicon, etc.) and handle frontend routing
 #[get("/web/{path:.*}")]
-pub async fn serve_static_files(req: HttpRequest) -> Result<HttpResponse> {
+pub async fn serve_static_files(req: HttpRequest, config: web::Data<AppConfig>) -> Result<HttpResponse> {
     let path_param: String = req.match_info().query("path").parse().unwrap_or_default();
     
     // If it's an empty path, serve index.html
@@ -47,7 +74,7 @@ pub async fn serve_static_files(req: HttpRequest) -> Result<HttpResponse> {
         let path = Path::new(FRONTEND_DIST_PATH).join(&path_param);
         if path.exists() {
             let content = std::fs::read(&path)?;
-            let content_type = match path_param.split('.').last() {
+            let content_type = match path_param.split('.').next_back() {
                 Some("ico") => "image/x-icon",
                 Some("svg") => "image/svg+xml",
                 Some("png") => "image/png",
@@ -61,6 +88,12 @@ pub async fn serve_static_files(req: HttpRequest) -> Result<HttpResponse> {
         }
     }
     
+    // Paths that name a file are missing files, not client-side routes
+    let last_segment = path_param.rsplit('/').next().unwrap_or_default();
+    if last_segment.contains('.') {
+        return Ok(not_found_page(&config));
+    }
+    
     // For all other routes (like /web/files, /web/upload, etc.), serve the index.html (for React routing)
     let path = Path::new(FRONTEND_DIST_PATH).join("index.html");
     if path.exists() {
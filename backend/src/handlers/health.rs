@@ -1,30 +1,70 @@
 use actix_web::{get, HttpResponse, Result, web};
 use crate::config::AppConfig;
-use crate::models::HealthResponse;
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::models::{HealthResponse, MetadataHealth, MetadataFileStatus};
+use crate::services::folder_manager::FolderManager;
+use crate::services::storage_scope::USERS_DIR;
+use std::path::Path;
+use std::time::Instant;
+
+/// When the server started, for the uptime reported by the health check
+#[derive(Clone, Copy)]
+pub struct StartTime(pub Instant);
 
 #[utoipa::path(
     get,
     path = "/api/health",
     responses(
         (status = 200, description = "Health check successful", body = HealthResponse),
+        (status = 503, description = "Metadata files are corrupt", body = HealthResponse),
     ),
     tag = "Health"
 )]
 #[get("/health")]
-pub async fn health_check(config: web::Data<AppConfig>) -> Result<HttpResponse> {
-    let uptime = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
+pub async fn health_check(config: web::Data<AppConfig>, start_time: web::Data<StartTime>) -> Result<HttpResponse> {
+    let uptime = start_time.0.elapsed().as_secs();
+
+    // Every folder/file operation fails if either metadata file can't be parsed
+    let folder_manager = FolderManager::new(&config.server.upload_dir);
+    let metadata = MetadataHealth {
+        folder_metadata: MetadataFileStatus::from_result(folder_manager.load_folder_metadata()),
+        file_metadata: MetadataFileStatus::from_result(folder_manager.load_file_metadata()),
+    };
+    let healthy = metadata.folder_metadata.valid && metadata.file_metadata.valid;
+    let (total_files, total_size_bytes) = storage_usage(Path::new(&config.server.upload_dir));
 
     let response = HealthResponse {
-        status: "healthy".to_string(),
+        status: if healthy { "healthy" } else { "unhealthy" }.to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
         uptime,
         upload_dir: config.server.upload_dir.clone(),
         auth_mode: config.auth.mode.clone(),
+        metadata,
+        total_files,
+        total_size_bytes,
+        free_bytes: fs2::available_space(&config.server.upload_dir).ok(),
+        min_free_bytes: config.server.min_free_bytes,
     };
 
-    Ok(HttpResponse::Ok().json(response))
+    if healthy {
+        Ok(HttpResponse::Ok().json(response))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(response))
+    }
+}
+
+/// Number of files and bytes used in the shared storage and every user's storage,
+/// read from their metadata rather than walking the upload dir
+fn storage_usage(upload_dir: &Path) -> (usize, u64) {
+    let user_dirs = std::fs::read_dir(upload_dir.join(USERS_DIR))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir());
+
+    std::iter::once(upload_dir.to_path_buf())
+        .chain(user_dirs)
+        .filter_map(|dir| FolderManager::new(dir).load_file_metadata().ok())
+        .flat_map(|file_metadata| file_metadata.into_values())
+        .fold((0, 0), |(files, bytes), meta| (files + 1, bytes + meta.stored_bytes()))
 }
@@ -2,21 +2,38 @@ use actix_multipart::Multipart;
 use actix_web::{post, web, HttpResponse};
 use futures_util::StreamExt;
 use std::collections::HashMap;
-use std::io::Cursor;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
 
 use crate::AppConfig;
 use crate::error::AppError;
-use crate::models::ErrorResponse;
-use crate::services::folder_manager::FolderManager;
+use crate::handlers::auth::{JwtService, CONFIRMATION_TOKEN_SECONDS};
+use crate::models::{ConfirmationRequiredResponse, ErrorResponse, ImportFailure, ImportResponse};
+use crate::services::file_upload::{process_uploaded_file, staging_file, UploadOptions, STAGING_DIR};
+use crate::services::file_utils::FileManager;
+use crate::services::folder_manager::{split_folder_path, FileMetadata, FolderManager, FolderMetadata};
+use crate::services::image_processor::ImageProcessor;
+use crate::services::manifest::{Manifest, MANIFEST_FILENAME};
+use crate::services::storage_scope::{StorageScope, USERS_DIR};
+use crate::utils::multipart::MultipartLimits;
+use crate::utils::validation::validate_uploaded_at;
+
+/// Processed files between two progress log lines
+const IMPORT_PROGRESS_INTERVAL: usize = 50;
 
 #[utoipa::path(
     post,
     path = "/api/files/import",
+    params(ImportQuery),
     request_body(content = ImportRequest, content_type = "multipart/form-data"),
     responses(
-        (status = 200, description = "Files imported successfully"),
+        (status = 200, description = "Import report with imported and failed entries", body = ImportResponse),
         (status = 400, description = "Invalid ZIP file or upload error", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 428, description = "Confirmation required before wiping existing files in replace mode", body = ConfirmationRequiredResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse),
     ),
     security(("bearer_auth" = [])),
@@ -25,58 +42,149 @@ use crate::services::folder_manager::FolderManager;
 #[post("/files/import")]
 pub async fn import_files(
     mut payload: Multipart,
+    query: web::Query<ImportQuery>,
     config: web::Data<AppConfig>,
+    jwt_service: web::Data<JwtService>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
-    let mut zip_data = Vec::new();
+    // The archive is staged on disk, it may be much larger than memory
+    let mut zip_file = None;
+    let mut destination_folder = String::new();
+    let mut mode = ImportMode::default();
+    let mut limits = MultipartLimits::new(&config.server);
     while let Some(item) = payload.next().await {
         let mut field = item.map_err(|e| {
             AppError::BadRequest(format!("Multipart error: {e}"))
         })?;
-        let content_disposition = field.content_disposition();
-        
-        if let Some(cd) = content_disposition {
-            if let Some(filename) = cd.get_filename() {
-                if filename.ends_with(".zip") {
-                    while let Some(chunk) = field.next().await {
-                        let data = chunk.map_err(|e| {
-                            AppError::BadRequest(format!("Upload error: {e}"))
-                        })?;
-                        zip_data.extend_from_slice(&data);
-                    }
-                    break;
-                }
+        limits.add_field()?;
+        let is_zip = zip_file.is_none() && field.content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .is_some_and(|filename| filename.ends_with(".zip"));
+        let is_destination = field.name() == Some("destination_folder");
+        let is_mode = field.name() == Some("mode");
+        if !is_zip && !is_destination && !is_mode {
+            continue;
+        }
+
+        let mut staged = if is_zip { Some(staging_file(&config.server.upload_dir)?) } else { None };
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| {
+                AppError::BadRequest(format!("Upload error: {e}"))
+            })?;
+            limits.add_bytes(chunk.len())?;
+            match staged.as_mut() {
+                Some(file) => file.write_all(&chunk)?,
+                None => data.extend_from_slice(&chunk),
             }
         }
+        if is_zip {
+            zip_file = staged;
+        } else if is_mode {
+            mode = match String::from_utf8_lossy(&data).trim() {
+                "merge" => ImportMode::Merge,
+                "replace" => ImportMode::Replace,
+                other => return Err(AppError::BadRequest(format!("Invalid import mode '{other}', expected merge or replace"))),
+            };
+        } else {
+            destination_folder = String::from_utf8(data)
+                .map_err(|e| AppError::BadRequest(format!("Invalid UTF-8 in destination_folder: {e}")))?;
+        }
     }
-    if zip_data.is_empty() {
+    let Some(zip_file) = zip_file else {
         return Err(AppError::BadRequest("No ZIP file uploaded".to_string()));
-    }
+    };
 
-    // Remove all existing files/folders in upload dir
-    let upload_dir = &config.server.upload_dir;
-    if std::path::Path::new(upload_dir).exists() {
-        std::fs::remove_dir_all(upload_dir).map_err(|e| {
-            AppError::Internal(format!("Failed to clear upload dir: {e}"))
-        })?;
+    // Replacing wipes the storage, optionally require a confirmation round trip first
+    if mode == ImportMode::Replace && config.server.require_destructive_confirmation {
+        let target = scope.upload_dir.to_string_lossy().to_string();
+        match query.confirmation_token {
+            Some(ref token) => jwt_service.validate_confirmation_token(token, "import", &target)?,
+            None => {
+                let folder_manager = scope.folder_manager(&config);
+                return Ok(HttpResponse::PreconditionRequired().json(ConfirmationRequiredResponse {
+                    operation: "import".to_string(),
+                    confirmation_token: jwt_service.create_confirmation_token("import", &target)?,
+                    expires_in: CONFIRMATION_TOKEN_SECONDS,
+                    files_affected: folder_manager.load_file_metadata()?.len(),
+                    folders_affected: folder_manager.load_folder_metadata()?.len(),
+                }));
+            }
+        }
     }
-    std::fs::create_dir_all(upload_dir).map_err(|e| {
-        AppError::Internal(format!("Failed to recreate upload dir: {e}"))
-    })?;
+    let destination_folder = split_folder_path(&destination_folder)?;
 
-    // Unzip the uploaded ZIP file into a temp dir
+    // Stage the archive in a temp dir before touching the upload dir
     use tempfile::tempdir;
     use zip::ZipArchive;
     let temp_dir = tempdir().map_err(|e| AppError::Internal(format!("Failed to create temp dir: {e}")))?;
-    let mut zip = ZipArchive::new(Cursor::new(&zip_data)).map_err(|e| {
+    let mut zip = ZipArchive::new(zip_file).map_err(|e| {
         AppError::BadRequest(format!("Invalid ZIP file: {e}"))
     })?;
-    zip.extract(temp_dir.path()).map_err(|e| AppError::Internal(format!("Failed to extract ZIP: {e}")))?;
 
-    // Traverse the unzipped directory: collect folders and files
+    // Sizes come from the central directory and extraction never writes more
+    // than an entry declares, so checking them up front bounds the disk usage
+    let mut declared_size = 0u64;
+    for index in 0..zip.len() {
+        if let Ok(entry) = zip.by_index_raw(index) {
+            declared_size = declared_size.saturating_add(entry.size());
+        }
+    }
+    if declared_size > config.server.max_import_size {
+        return Err(AppError::BadRequest(format!(
+            "Archive expands to {} bytes, more than the {} allowed",
+            declared_size, config.server.max_import_size
+        )));
+    }
+
+    // Extract entry by entry so a single bad entry doesn't abort the import
+    let mut failed = Vec::new();
+    for index in 0..zip.len() {
+        let mut entry = match zip.by_index(index) {
+            Ok(entry) => entry,
+            Err(e) => {
+                failed.push(ImportFailure { path: format!("entry #{index}"), reason: e.to_string() });
+                continue;
+            }
+        };
+        let Some(rel_path) = entry.enclosed_name() else {
+            failed.push(ImportFailure { path: entry.name().to_string(), reason: "Unsafe path in ZIP".to_string() });
+            continue;
+        };
+        let out_path = temp_dir.path().join(&rel_path);
+        let result = if entry.is_dir() {
+            std::fs::create_dir_all(&out_path)
+        } else if entry.size() > config.server.max_file_size as u64 {
+            failed.push(ImportFailure { path: entry.name().to_string(), reason: AppError::FileTooLarge(entry.size() as usize).to_string() });
+            continue;
+        } else {
+            let declared = entry.size();
+            extract_entry(&mut entry, declared, &out_path)
+        };
+        if let Err(e) = result {
+            if !entry.is_dir() {
+                let _ = std::fs::remove_file(&out_path);
+            }
+            failed.push(ImportFailure { path: entry.name().to_string(), reason: e.to_string() });
+        }
+    }
+
+    // Traverse the unzipped directory: collect folders and files,
+    // leaving out junk such as macOS resource forks
     use walkdir::WalkDir;
+    let ignore = ImportIgnore::new(&config.server.import_ignore);
     let mut folders = Vec::new();
     let mut files = Vec::new();
-    for entry in WalkDir::new(temp_dir.path()).min_depth(1) {
+    let mut files_skipped = 0;
+    let walker = WalkDir::new(temp_dir.path())
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            let ignored = ignore.matches(entry);
+            files_skipped += usize::from(ignored);
+            !ignored
+        });
+    for entry in walker {
         let entry = entry.map_err(|e| AppError::Internal(format!("WalkDir error: {e}")))?;
         let rel_path = entry.path().strip_prefix(temp_dir.path()).unwrap();
         if entry.file_type().is_dir() {
@@ -86,62 +194,284 @@ pub async fn import_files(
         }
     }
 
+    // A manifest from an export restores the metadata the paths can't carry
+    let manifest_path = Path::new(MANIFEST_FILENAME);
+    let manifest = if files.iter().any(|file| file == manifest_path) {
+        files.retain(|file| file != manifest_path);
+        let parsed = std::fs::read(temp_dir.path().join(manifest_path))
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_slice::<Manifest>(&content).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                failed.push(ImportFailure { path: MANIFEST_FILENAME.to_string(), reason: format!("Ignored invalid manifest: {e}") });
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let manifest_folders = manifest.as_ref().map(Manifest::folders_by_path).unwrap_or_default();
+    let manifest_files: HashMap<&str, &FileMetadata> = manifest.as_ref()
+        .map(|manifest| manifest.files.iter().map(|file| (file.path.as_str(), &file.metadata)).collect())
+        .unwrap_or_default();
+
+    // Remove all existing files/folders in upload dir when replacing
+    let upload_dir = &scope.upload_dir;
+    if mode == ImportMode::Replace && upload_dir.exists() {
+        for entry in std::fs::read_dir(upload_dir)? {
+            let path = entry?.path();
+            // Other users' storage lives inside the shared upload dir
+            if scope.is_shared() && path.file_name() == Some(std::ffi::OsStr::new(USERS_DIR)) {
+                continue;
+            }
+            // Files being uploaded, including this archive
+            if path.file_name() == Some(std::ffi::OsStr::new(STAGING_DIR)) {
+                continue;
+            }
+            let result = if path.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+            result.map_err(|e| AppError::Internal(format!("Failed to clear upload dir: {e}")))?;
+        }
+    }
+    std::fs::create_dir_all(upload_dir).map_err(|e| {
+        AppError::Internal(format!("Failed to recreate upload dir: {e}"))
+    })?;
+
     // Sort folders by depth (parents first)
     folders.sort_by_key(|p| p.components().count());
 
     // Create FolderManager
-    let folder_manager = FolderManager::new(upload_dir);
+    let folder_manager = scope.folder_manager(&config);
 
-    // Map of rel_path -> folder_id
-    let mut folder_ids: HashMap<std::path::PathBuf, String> = HashMap::new();
-    folder_ids.insert(std::path::PathBuf::new(), None::<String>.map_or(String::new(), |s| s)); // root
+    // Everything lands under the destination folder, root when none is given
+    let destination_id = folder_manager.ensure_folder_path(destination_folder).await?;
+    let existing_folders = folder_manager.load_folder_metadata()?;
+
+    // Map of rel_path -> folder_id, the archive root being the destination
+    let mut folder_ids: HashMap<PathBuf, Option<String>> = HashMap::new();
+    folder_ids.insert(PathBuf::new(), destination_id.clone());
 
     // Create folders
+    let mut folders_created = 0;
     for folder in &folders {
-        let parent = folder.parent().unwrap_or(std::path::Path::new(""));
-        let parent_id = if parent.as_os_str().is_empty() {
-            None
-        } else {
-            folder_ids.get(parent).cloned()
-        };
+        let parent_id = closest_folder_id(folder, &folder_ids);
         let name = folder.file_name().unwrap().to_string_lossy();
-        let info = folder_manager.create_folder(&name, parent_id.clone()).await?;
-        folder_ids.insert(folder.clone(), info.id.clone());
+        // Merging reuses folders that already exist at the same place
+        let existing = match mode {
+            ImportMode::Merge => folder_manager.find_folder(&name, &parent_id)?,
+            ImportMode::Replace => None,
+        };
+        let reused = existing.is_some();
+        let created = match (existing, manifest_folders.get(folder.to_string_lossy().as_ref())) {
+            (Some(folder_id), _) => Ok(folder_id),
+            // Keep the exported id unless a folder elsewhere already uses it
+            (None, Some(exported)) if !existing_folders.contains_key(&exported.id) => folder_manager.restore_folder(FolderMetadata {
+                parent_id: parent_id.clone(),
+                ..(*exported).clone()
+            }).await.map(|info| info.id),
+            (None, _) => folder_manager.create_folder(&name, parent_id.clone(), Default::default()).await.map(|info| info.id),
+        };
+        match created {
+            Ok(folder_id) => {
+                folder_ids.insert(folder.clone(), Some(folder_id));
+                folders_created += usize::from(!reused);
+            }
+            // Files of a folder that couldn't be created land in its closest created ancestor
+            Err(e) => failed.push(ImportFailure { path: folder.to_string_lossy().to_string(), reason: e.to_string() }),
+        }
     }
 
-    // Copy files and assign to folders (flat, no physical subfolders)
-    use crate::services::file_utils::FileManager;
-    use crate::services::image_processor::ImageProcessor;
-    use crate::services::file_upload::process_uploaded_file;
-    let file_manager = FileManager::new(upload_dir, config.get_static_base_url());
-    let image_processor = ImageProcessor::new(config.image.clone());
+    // Copy files and assign to folders (flat, no physical subfolders), several at a time
+    let file_manager = Arc::new(scope.file_manager());
+    let folder_manager = Arc::new(folder_manager);
+    let image_processor = Arc::new(ImageProcessor::new(config.image.clone()));
 
-    for file in &files {
-        let src_path = temp_dir.path().join(file);
-        let folder = file.parent().unwrap_or(std::path::Path::new(""));
-        let folder_id = if folder.as_os_str().is_empty() {
-            None
-        } else {
-            folder_ids.get(folder).cloned()
+    let total = files.len();
+    let mut tasks = JoinSet::new();
+    let mut results = Vec::with_capacity(total);
+    for (index, file) in files.iter().enumerate() {
+        // Wait for a slot before starting the next file
+        if tasks.len() >= config.server.import_concurrency {
+            if let Some(joined) = tasks.join_next().await {
+                results.push(joined.map_err(|e| AppError::Internal(format!("Import task failed: {e}")))?);
+                log_import_progress(results.len(), total);
+            }
+        }
+
+        let file = file.clone();
+        let src_path = temp_dir.path().join(&file);
+        let folder_id = closest_folder_id(&file, &folder_ids);
+        let exported = manifest_files.get(file.to_string_lossy().as_ref()).map(|meta| (*meta).clone());
+        let (config, file_manager, folder_manager, image_processor) =
+            (config.clone(), file_manager.clone(), folder_manager.clone(), image_processor.clone());
+        tasks.spawn(async move {
+            let result = import_file(&src_path, folder_id, exported, &config, &file_manager, &folder_manager, &image_processor).await;
+            (index, file, result)
+        });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        results.push(joined.map_err(|e| AppError::Internal(format!("Import task failed: {e}")))?);
+        log_import_progress(results.len(), total);
+    }
+    // Report files in archive order whatever order they finished in
+    results.sort_by_key(|(index, _, _)| *index);
+
+    let mut imported = Vec::new();
+    let mut stored_filenames = HashMap::new();
+    for (_, file, result) in results {
+        match result {
+            Ok(filename) => {
+                stored_filenames.insert(file.to_string_lossy().to_string(), filename);
+                imported.push(file.to_string_lossy().to_string());
+            }
+            Err(e) => failed.push(ImportFailure { path: file.to_string_lossy().to_string(), reason: e.to_string() }),
+        }
+    }
+
+    let message = if failed.is_empty() && mode == ImportMode::Replace {
+        "Files imported and upload folder rebuilt successfully".to_string()
+    } else if failed.is_empty() {
+        "Files imported successfully".to_string()
+    } else {
+        format!("Imported {} files, {} entries failed", imported.len(), failed.len())
+    };
+
+    Ok(HttpResponse::Ok().json(ImportResponse {
+        success: failed.is_empty(),
+        message,
+        folders_created,
+        files_imported: imported.len(),
+        files_skipped,
+        imported,
+        stored_filenames,
+        failed,
+    }))
+}
+
+/// Entry names skipped during import
+struct ImportIgnore {
+    folders: Vec<glob::Pattern>,
+    files: Vec<glob::Pattern>,
+}
+
+impl ImportIgnore {
+    /// Patterns ending with `/` only match folders. Invalid patterns are rejected at startup.
+    fn new(patterns: &[String]) -> Self {
+        let mut ignore = Self { folders: Vec::new(), files: Vec::new() };
+        for pattern in patterns {
+            match pattern.strip_suffix('/') {
+                Some(folder) => ignore.folders.extend(glob::Pattern::new(folder).ok()),
+                None => ignore.files.extend(glob::Pattern::new(pattern).ok()),
+            }
+        }
+        ignore
+    }
+
+    fn matches(&self, entry: &walkdir::DirEntry) -> bool {
+        let Some(name) = entry.file_name().to_str() else {
+            return false;
         };
-        let filename = file.file_name().unwrap().to_string_lossy();
-        let file_bytes = std::fs::read(&src_path).map_err(|e| AppError::Internal(format!("Failed to read file: {e}")))?;
-        // Write file and update metadata (flat in uploads/)
-        let _ = process_uploaded_file(
-            file_bytes,
-            &filename,
-            folder_id,
-            &config,
-            &file_manager,
-            &folder_manager,
-            &image_processor,
-        ).await?;
-    }
-
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "Files imported and upload folder rebuilt successfully"
-    })))
+        let patterns = if entry.file_type().is_dir() { &self.folders } else { &self.files };
+        patterns.iter().any(|pattern| pattern.matches(name))
+    }
+}
+
+/// Store one extracted file, returning its stored filename
+async fn import_file(
+    src_path: &Path,
+    folder_id: Option<String>,
+    exported: Option<FileMetadata>,
+    config: &AppConfig,
+    file_manager: &FileManager,
+    folder_manager: &FolderManager,
+    image_processor: &ImageProcessor,
+) -> Result<String, AppError> {
+    let filename = src_path.file_name().unwrap().to_string_lossy().to_string();
+    // Keep the exported stored name, unless the archive holds a derivative under another name
+    let (upload_name, stored_filename) = match &exported {
+        Some(meta) if meta.filename == filename => (
+            meta.original_name.clone().unwrap_or_else(|| filename.clone()),
+            Some(meta.filename.clone()),
+        ),
+        _ => (filename.clone(), None),
+    };
+    let uploaded_at = exported.as_ref().and_then(|meta| match validate_uploaded_at(meta.uploaded_at) {
+        Ok(uploaded_at) => Some(uploaded_at),
+        Err(e) => {
+            warn!("Ignoring exported upload date of {}: {}", filename, e);
+            None
+        }
+    });
+    let options = UploadOptions { folder_id, stored_filename, uploaded_at, auto_folder: false };
+    // The extracted file is moved into place, the temp dir is removed afterwards anyway
+    let uploaded = process_uploaded_file(
+        tempfile::TempPath::from_path(src_path),
+        &upload_name,
+        options,
+        config,
+        file_manager,
+        folder_manager,
+        image_processor,
+    ).await?;
+
+    if let Some(meta) = exported {
+        let original_name = meta.original_name;
+        let display_name = meta.display_name;
+        let analysis = meta.analysis;
+        let restored = folder_manager.update_file_metadata(&uploaded.filename, move |current| {
+            current.original_name = original_name;
+            current.display_name = display_name;
+            if current.analysis.is_none() {
+                current.analysis = analysis;
+            }
+        }).await;
+        if let Err(e) = restored {
+            warn!("Failed to restore exported metadata of {}: {}", uploaded.filename, e);
+        }
+    }
+    Ok(uploaded.filename)
+}
+
+/// Log import progress every few files and at the end
+fn log_import_progress(done: usize, total: usize) {
+    if done.is_multiple_of(IMPORT_PROGRESS_INTERVAL) || done == total {
+        info!("Imported {}/{} files", done, total);
+    }
+}
+
+/// Id of the folder created for the closest ancestor of an archive path
+///
+/// Folders are created parents first, so a missing ancestor is one that failed;
+/// its content goes one level up instead. The archive root always matches.
+fn closest_folder_id(path: &Path, folder_ids: &HashMap<PathBuf, Option<String>>) -> Option<String> {
+    path.ancestors()
+        .skip(1)
+        .find_map(|folder| folder_ids.get(folder))
+        .cloned()
+        .flatten()
+}
+
+/// Extract an entry, refusing to write more than its declared size
+fn extract_entry(entry: &mut impl Read, declared_size: u64, out_path: &Path) -> std::io::Result<()> {
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut out = std::fs::File::create(out_path)?;
+    let written = std::io::copy(&mut entry.take(declared_size + 1), &mut out)?;
+    if written > declared_size {
+        return Err(std::io::Error::other("Entry is larger than its declared size"));
+    }
+    Ok(())
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct ImportQuery {
+    /// Token returned by a previous call when confirmation is required
+    pub confirmation_token: Option<String>,
 }
 
 /// ImportRequest for OpenAPI (multipart/form-data with a file)
@@ -151,4 +481,126 @@ pub struct ImportRequest {
     /// ZIP file to import (binary data)
     #[schema(format = "binary")]
     pub file: String,
+    /// Folder path (e.g. `Imports/2024-06-01`) to import into, created if missing
+    pub destination_folder: Option<String>,
+    /// `merge` (default) or `replace`
+    pub mode: Option<ImportMode>,
+}
+
+/// What happens to the existing files and folders on import
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Keep them, archive files that collide with stored names are renamed
+    #[default]
+    Merge,
+    /// Wipe the storage before importing
+    Replace,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use std::io::Cursor;
+    use zip::write::SimpleFileOptions;
+
+    const BOUNDARY: &str = "snapfilething-test-boundary";
+
+    fn zip_archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (path, content) in entries {
+            zip.start_file(*path, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn multipart_body(archive: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"archive.zip\"\r\nContent-Type: application/zip\r\n\r\n"
+        ).into_bytes();
+        body.extend_from_slice(archive);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    /// Post `archive` to the import endpoint of an app storing files in `upload_dir`
+    async fn import(upload_dir: &Path, archive: &[u8]) -> actix_web::dev::ServiceResponse {
+        let mut config = AppConfig::default();
+        config.server.upload_dir = upload_dir.to_string_lossy().into_owned();
+        let jwt_service = JwtService::new(&config.auth).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(jwt_service))
+                .service(import_files)
+        ).await;
+
+        let req = test::TestRequest::post()
+            .uri("/files/import")
+            .insert_header(("Content-Type", format!("multipart/form-data; boundary={BOUNDARY}")))
+            .set_payload(multipart_body(archive))
+            .to_request();
+        test::call_service(&app, req).await
+    }
+
+    #[actix_web::test]
+    async fn import_keeps_nested_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = zip_archive(&[
+            ("top/top.txt", b"top"),
+            ("top/middle/middle.txt", b"middle"),
+            ("top/middle/bottom/bottom.txt", b"bottom"),
+        ]);
+        let res = import(dir.path(), &archive).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let folder_manager = FolderManager::new(dir.path());
+        let folders = folder_manager.load_folder_metadata().unwrap();
+        let folder_named = |name: &str| folders.values().find(|folder| folder.name == name).unwrap();
+        let (top, middle, bottom) = (folder_named("top"), folder_named("middle"), folder_named("bottom"));
+        assert_eq!(top.parent_id, None);
+        assert_eq!(middle.parent_id.as_ref(), Some(&top.id));
+        assert_eq!(bottom.parent_id.as_ref(), Some(&middle.id));
+
+        let files = folder_manager.load_file_metadata().unwrap();
+        let folder_of = |name: &str| files.values()
+            .find(|file| file.filename.starts_with(name))
+            .and_then(|file| file.folder_id.clone());
+        assert_eq!(folder_of("top"), Some(top.id.clone()));
+        assert_eq!(folder_of("middle"), Some(middle.id.clone()));
+        assert_eq!(folder_of("bottom"), Some(bottom.id.clone()));
+    }
+
+    #[actix_web::test]
+    async fn import_reports_bad_entries_and_keeps_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = zip_archive(&[
+            ("notes/first.txt", b"first"),
+            ("../escape.txt", b"escape"),
+            ("notes/broken.png", b"not a png"),
+            ("notes/second.txt", b"second"),
+        ]);
+        let res = import(dir.path(), &archive).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let report: ImportResponse = test::read_body_json(res).await;
+        assert!(!report.success);
+        let reason_for = |path: &str| report.failed.iter()
+            .find(|failure| failure.path.ends_with(path))
+            .map(|failure| failure.reason.clone());
+        assert_eq!(reason_for("escape.txt").as_deref(), Some("Unsafe path in ZIP"));
+        assert!(reason_for("broken.png").is_some_and(|reason| reason.contains("invalid format")), "{:?}", report.failed);
+        assert_eq!(report.failed.len(), 2);
+
+        let mut imported = report.imported.clone();
+        imported.sort();
+        assert_eq!(imported, ["notes/first.txt", "notes/second.txt"]);
+        assert_eq!(FolderManager::new(dir.path()).load_file_metadata().unwrap().len(), 2);
+        assert!(!dir.path().parent().unwrap().join("escape.txt").exists());
+    }
+
 }
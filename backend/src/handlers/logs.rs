@@ -0,0 +1,59 @@
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpResponse};
+use futures_util::stream::{self, StreamExt};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::AppError;
+use crate::middleware::auth::AdminUser;
+use crate::models::{ErrorResponse, LogLine};
+use crate::services::log_buffer::LogBuffer;
+
+/// Idle time after which a comment is sent, so proxies keep the stream open
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/logs/stream",
+    responses(
+        (status = 200, description = "Server-sent events, one `data:` JSON log line each: the buffered lines first, then new ones as they are logged", body = LogLine, content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Not the admin", body = ErrorResponse),
+        (status = 404, description = "Log buffer disabled", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Admin"
+)]
+#[get("/admin/logs/stream")]
+pub async fn stream_logs(
+    _admin: AdminUser,
+    log_buffer: web::Data<LogBuffer>,
+) -> Result<HttpResponse, AppError> {
+    if !log_buffer.is_enabled() {
+        return Err(AppError::NotFound("Log buffer is disabled (LOG_BUFFER_LINES=0)".to_string()));
+    }
+
+    let (recent, receiver) = log_buffer.subscribe();
+    let backlog = stream::iter(recent.into_iter().map(|line| Ok::<_, AppError>(event(&line))));
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        let chunk = match tokio::time::timeout(KEEP_ALIVE_INTERVAL, receiver.recv()).await {
+            Ok(Ok(line)) => event(&line),
+            Ok(Err(RecvError::Lagged(skipped))) => Bytes::from(format!(": skipped {} lines\n\n", skipped)),
+            Ok(Err(RecvError::Closed)) => return None,
+            Err(_) => Bytes::from_static(b": keep-alive\n\n"),
+        };
+        Some((Ok(chunk), receiver))
+    });
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(backlog.chain(live)))
+}
+
+/// Format a log line as a server-sent event
+fn event(line: &LogLine) -> Bytes {
+    let json = serde_json::to_string(line).unwrap_or_default();
+    Bytes::from(format!("data: {}\n\n", json))
+}
@@ -4,6 +4,11 @@ pub mod health;
 pub mod files;
 pub mod folders;
 pub mod frontend;
+pub mod stats;
+pub mod logs;
+pub mod tiles;
+pub mod precompressed;
 
 pub mod import;
 pub mod export;
+pub mod download;
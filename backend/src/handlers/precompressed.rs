@@ -0,0 +1,94 @@
+use actix_files::NamedFile;
+use actix_web::guard::GuardContext;
+use actix_web::http::header::{
+    ContentDisposition, ContentEncoding, DispositionParam, DispositionType, ACCEPT_ENCODING, VARY,
+};
+use actix_web::http::Method;
+use actix_web::{mime, web, HttpRequest, HttpResponse};
+use std::path::{Path, PathBuf};
+
+use crate::config::AppConfig;
+use crate::error::AppError;
+
+/// Path of the `.gz` companion to serve for a static file request, if any
+///
+/// Only applies when precompression is enabled and the client accepts gzip.
+/// Stored names never need percent-encoding, so anything unusual is left to
+/// the regular file service.
+fn gzip_companion(config: &AppConfig, req_path: &str, method: &Method, accept_encoding: Option<&str>) -> Option<PathBuf> {
+    if !config.server.gzip_precompression || !matches!(*method, Method::GET | Method::HEAD) {
+        return None;
+    }
+    if !accept_encoding.is_some_and(accepts_gzip) {
+        return None;
+    }
+
+    let relative = req_path.strip_prefix("/uploads/")?;
+    let is_safe = relative.split('/').all(|segment| {
+        !segment.is_empty()
+            && !segment.starts_with('.')
+            && segment.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    });
+    if !is_safe {
+        return None;
+    }
+
+    let companion = Path::new(&config.server.upload_dir).join(format!("{}.gz", relative));
+    companion.is_file().then_some(companion)
+}
+
+/// Whether an `Accept-Encoding` value allows gzip
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let refused = params.any(|param| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+/// Route guard matching requests that have a gzip companion to serve
+pub fn has_gzip_companion(ctx: &GuardContext) -> bool {
+    let Some(config) = ctx.app_data::<web::Data<AppConfig>>() else {
+        return false;
+    };
+    let accept_encoding = ctx.head().headers().get(ACCEPT_ENCODING).and_then(|value| value.to_str().ok());
+    gzip_companion(config, ctx.head().uri.path(), &ctx.head().method, accept_encoding).is_some()
+}
+
+/// Serve the precompressed companion of a static file with the original file's type
+pub async fn serve_gzip_companion(req: HttpRequest, config: web::Data<AppConfig>) -> Result<HttpResponse, AppError> {
+    let accept_encoding = req.headers().get(ACCEPT_ENCODING).and_then(|value| value.to_str().ok());
+    let companion = gzip_companion(&config, req.path(), req.method(), accept_encoding)
+        .ok_or_else(|| AppError::NotFound(req.path().to_string()))?;
+    let original = companion.with_extension("");
+    let filename = original.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string();
+
+    let extension = original.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let mut content_type = actix_files::file_extension_to_mime(extension);
+    // Same as `prefer_utf8` on the regular file service
+    if content_type.type_() == mime::TEXT {
+        content_type = format!("{}; charset=utf-8", content_type).parse().unwrap_or(content_type);
+    }
+    let disposition = if config.server.inline_mime_categories.iter().any(|category| category == content_type.type_().as_str()) {
+        DispositionType::Inline
+    } else {
+        DispositionType::Attachment
+    };
+
+    let mut response = NamedFile::open(&companion)?
+        .set_content_type(content_type)
+        .set_content_encoding(ContentEncoding::Gzip)
+        .set_content_disposition(ContentDisposition {
+            disposition,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .use_etag(true)
+        .use_last_modified(true)
+        .into_response(&req);
+    response.headers_mut().insert(VARY, ACCEPT_ENCODING.into());
+    Ok(response)
+}
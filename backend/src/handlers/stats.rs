@@ -0,0 +1,85 @@
+use actix_web::{get, web, HttpResponse};
+use std::path::Path;
+
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::models::{DerivativeFormatStats, DerivativeStatsResponse, ErrorResponse};
+use crate::services::storage_scope::StorageScope;
+
+#[utoipa::path(
+    get,
+    path = "/api/stats/derivatives",
+    responses(
+        (status = 200, description = "Derivative size report", body = DerivativeStatsResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Stats"
+)]
+#[get("/stats/derivatives")]
+pub async fn derivative_stats(
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+    let file_metadata = folder_manager.load_file_metadata()?;
+
+    // Sizes recorded at generation time are preferred, older entries fall back to the filesystem
+    let derivative_size = |recorded: Option<u64>, filename: String| -> Option<u64> {
+        recorded.or_else(|| std::fs::metadata(file_manager.get_file_path(&filename)).ok().map(|m| m.len()))
+    };
+
+    let mut total_original_bytes = 0;
+    let mut qoi = DerivativeFormatStats::default();
+    let mut thumbnail = DerivativeFormatStats::default();
+    let mut qoi_ratio_sum = 0.0;
+    let mut thumbnail_ratio_sum = 0.0;
+
+    for meta in file_metadata.values() {
+        total_original_bytes += meta.size;
+
+        let stem = Path::new(&meta.filename).file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("file");
+
+        if let Some(size) = derivative_size(meta.qoi_bytes, format!("{}.qoi", stem)) {
+            qoi.count += 1;
+            qoi.original_bytes += meta.size;
+            qoi.derivative_bytes += size;
+            if meta.size > 0 {
+                qoi_ratio_sum += size as f64 / meta.size as f64;
+            }
+        }
+
+        let thumbnail_size = meta.thumbnail_bytes.or_else(|| {
+            let thumb_filename = file_manager.generated_thumbnail(&meta.filename)?;
+            derivative_size(None, thumb_filename)
+        });
+        if let Some(size) = thumbnail_size {
+            thumbnail.count += 1;
+            thumbnail.original_bytes += meta.size;
+            thumbnail.derivative_bytes += size;
+            if meta.size > 0 {
+                thumbnail_ratio_sum += size as f64 / meta.size as f64;
+            }
+        }
+    }
+
+    if qoi.count > 0 {
+        qoi.average_ratio = qoi_ratio_sum / qoi.count as f64;
+    }
+    if thumbnail.count > 0 {
+        thumbnail.average_ratio = thumbnail_ratio_sum / thumbnail.count as f64;
+    }
+
+    Ok(HttpResponse::Ok().json(DerivativeStatsResponse {
+        total_files: file_metadata.len(),
+        total_original_bytes,
+        total_qoi_bytes: qoi.derivative_bytes,
+        total_thumbnail_bytes: thumbnail.derivative_bytes,
+        qoi,
+        thumbnail,
+    }))
+}
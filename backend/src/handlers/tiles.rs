@@ -0,0 +1,147 @@
+use actix_files::NamedFile;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use std::path::PathBuf;
+
+use crate::config::{AppConfig, ImageConfig};
+use crate::error::AppError;
+use crate::models::{ErrorResponse, TileInfo, TileLevel};
+use crate::services::file_utils::FileManager;
+use crate::services::image_processor::{tile_pyramid, ImageProcessor};
+use crate::services::storage_scope::StorageScope;
+
+/// An image that can be tiled: its original, the image settings of its folder and its pyramid
+struct TileSource {
+    path: PathBuf,
+    image_config: ImageConfig,
+    width: u32,
+    height: u32,
+    levels: Vec<TileLevel>,
+}
+
+/// Check that `filename` is an image with its original kept, and compute its pyramid
+async fn tile_source(filename: &str, config: &AppConfig, scope: &StorageScope, file_manager: &FileManager) -> Result<TileSource, AppError> {
+    let folder_manager = scope.folder_manager(config);
+    let Some(meta) = folder_manager.load_file_metadata()?.remove(filename) else {
+        return Err(AppError::FileNotFound(filename.to_string()));
+    };
+    if !ImageProcessor::is_image_file(filename) {
+        return Err(AppError::BadRequest(format!("File '{}' is not an image", filename)));
+    }
+    let path = file_manager.get_file_path(filename);
+    if !path.is_file() {
+        return Err(AppError::BadRequest(format!("The original of '{}' was not kept and can't be tiled", filename)));
+    }
+
+    let image_config = folder_manager.effective_image_settings(meta.folder_id.as_deref())?.apply(&config.image);
+    let (width, height) = ImageProcessor::new(image_config.clone()).get_oriented_dimensions(&path).await?;
+    // Each level is cut from the decoded original
+    if let Some(max_pixels) = image_config.max_derivative_source_pixels {
+        if width as u64 * height as u64 > max_pixels {
+            return Err(AppError::BadRequest(format!(
+                "Image '{}' exceeds MAX_DERIVATIVE_SOURCE_PIXELS and can't be tiled",
+                filename
+            )));
+        }
+    }
+
+    let levels = tile_pyramid(width, height, image_config.tile_size);
+    Ok(TileSource { path, image_config, width, height, levels })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{filename}/tiles",
+    params(
+        ("filename" = String, Path, description = "Stored name of the image")
+    ),
+    responses(
+        (status = 200, description = "Levels of the deep-zoom pyramid of the image", body = TileInfo),
+        (status = 400, description = "Not an image, original not kept, or too large to tile", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[get("/files/{filename}/tiles")]
+pub async fn tile_info(
+    path: web::Path<String>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let filename = path.into_inner();
+    let file_manager = scope.file_manager();
+    let source = tile_source(&filename, &config, &scope, &file_manager).await?;
+
+    Ok(HttpResponse::Ok().json(TileInfo {
+        filename,
+        width: source.width,
+        height: source.height,
+        tile_size: source.image_config.tile_size,
+        overlap: 0,
+        format: "webp".to_string(),
+        levels: source.levels,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{filename}/tiles/{level}/{x}/{y}",
+    params(
+        ("filename" = String, Path, description = "Stored name of the image"),
+        ("level" = u32, Path, description = "Pyramid level, 0 being a single pixel"),
+        ("x" = u32, Path, description = "Tile column"),
+        ("y" = u32, Path, description = "Tile row"),
+    ),
+    responses(
+        (status = 200, description = "WebP tile, cut from the original on first request", content_type = "image/webp"),
+        (status = 400, description = "Not an image, original not kept, or too large to tile", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "File, level or tile not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[get("/files/{filename}/tiles/{level}/{x}/{y}")]
+pub async fn get_tile(
+    path: web::Path<(String, u32, u32, u32)>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let (filename, level, x, y) = path.into_inner();
+    let file_manager = scope.file_manager();
+    let source = tile_source(&filename, &config, &scope, &file_manager).await?;
+
+    let tile_level = source.levels.get(level as usize)
+        .ok_or_else(|| AppError::NotFound(format!("Level {} not found, '{}' has {} levels", level, filename, source.levels.len())))?
+        .clone();
+    if x >= tile_level.columns || y >= tile_level.rows {
+        return Err(AppError::NotFound(format!("Tile {}/{} not found in level {} of '{}'", x, y, level, filename)));
+    }
+
+    let tile_size = source.image_config.tile_size;
+    let level_dir = file_manager.tiles_dir(&filename)
+        .join(tile_size.to_string())
+        .join(level.to_string());
+    let tile_path = level_dir.join(format!("{}_{}.webp", x, y));
+
+    // Tiles older than the original were cut from a previous version of it
+    let source_modified = std::fs::metadata(&source.path)?.modified()?;
+    let is_fresh = std::fs::metadata(&tile_path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified >= source_modified);
+    if !is_fresh {
+        ImageProcessor::new(source.image_config)
+            .generate_tile_level(&source.path, tile_level, tile_size, &level_dir)
+            .await?;
+    }
+
+    Ok(NamedFile::open(&tile_path)?
+        .set_content_type(actix_files::file_extension_to_mime("webp"))
+        .use_etag(true)
+        .use_last_modified(true)
+        .into_response(&req))
+}
@@ -1,23 +1,35 @@
 use actix_multipart::Multipart;
-use actix_web::{post, web, HttpResponse};
+use actix_web::{head, patch, post, http::header, web, HttpRequest, HttpResponse};
+use chrono::Utc;
 use futures_util::StreamExt;
+use serde::Deserialize;
+use std::io::Write;
+use tempfile::{NamedTempFile, TempPath};
+use tracing::{info, warn};
 use utoipa::ToSchema;
 
 use crate::config::AppConfig;
 use crate::error::AppError;
-use crate::models::{ErrorResponse, FileMetadata, UploadResponse, FileUrls};
-use crate::services::file_upload::process_uploaded_file;
-use crate::services::file_utils::FileManager;
-use crate::services::folder_manager::FolderManager;
+use crate::models::{ErrorResponse, FileMetadata, UploadResponse, FileUrls, MultiUploadResponse, ResumableUploadResponse, UploadFailure};
+use crate::services::file_upload::{check_free_space, process_uploaded_file, staging_file, UploadOptions, UploadedFile};
+use crate::services::file_utils::{avif_name, thumbnail_name, FileManager};
 use crate::services::image_processor::ImageProcessor;
-use crate::utils::validation::validate_file_size;
+use crate::services::resumable_upload::{self, PendingUpload, UploadGuard};
+use crate::services::storage_scope::StorageScope;
+use crate::utils::multipart::MultipartLimits;
+use crate::utils::throttle::Throttle;
+use crate::utils::validation::{parse_uploaded_at, validate_file_size};
 
 #[derive(ToSchema)]
 #[allow(dead_code)]
 pub struct FileUploadRequest {
+    /// Repeat the field to upload several files at once
     #[schema(format = "binary")]
     file: Vec<u8>,
+    /// Target folder; `root` keeps the file at the root when `AUTO_FOLDERS` is set
     folder_id: Option<String>,
+    /// Original upload date (RFC 3339) to record instead of now, e.g. when migrating
+    uploaded_at: Option<String>,
 }
 
 #[utoipa::path(
@@ -25,10 +37,11 @@ pub struct FileUploadRequest {
     path = "/api/upload",
     request_body(content = FileUploadRequest, content_type = "multipart/form-data"),
     responses(
-        (status = 200, description = "File uploaded successfully", body = UploadResponse),
+        (status = 200, description = "File uploaded successfully. With several `file` fields, the body is a MultiUploadResponse instead", body = UploadResponse),
         (status = 400, description = "Invalid file or file too large", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 413, description = "File too large", body = ErrorResponse),
+        (status = 507, description = "Not enough free disk space", body = ErrorResponse),
     ),
     security(("bearer_auth" = [])),
     tag = "Files"
@@ -37,12 +50,18 @@ pub struct FileUploadRequest {
 pub async fn upload_file(
     mut payload: Multipart,
     config: web::Data<AppConfig>,
+    scope: StorageScope,
 ) -> Result<HttpResponse, AppError> {
-    let mut file_field = None;
+    // Files are only processed once every field is read, since folder_id may come last
+    let mut file_fields: Vec<(String, Result<TempPath, AppError>)> = Vec::new();
     let mut folder_id = None;
+    let mut uploaded_at = None;
+    let mut limits = MultipartLimits::new(&config.server);
+    let mut throttle = Throttle::new(config.server.max_upload_bytes_per_sec);
 
     while let Some(item) = payload.next().await {
         let mut field = item?;
+        limits.add_field()?;
         
         // Get field name first
         let name = field.name().ok_or_else(|| AppError::BadRequest("Invalid field".to_string()))?.to_string();
@@ -56,18 +75,29 @@ pub async fn upload_file(
                     return Err(AppError::BadRequest("Content disposition missing".to_string()));
                 };
                 
-                // Finally read the data
-                let mut data = Vec::new();
+                // Finally stream the data to disk, giving up on the file once it is too large
+                let mut staged = Ok(staging_file(&config.server.upload_dir)?);
+                let mut size = 0;
                 while let Some(chunk) = field.next().await {
-                    data.extend_from_slice(&chunk?);
+                    let chunk = chunk?;
+                    limits.add_bytes(chunk.len())?;
+                    throttle.consume(chunk.len()).await;
+                    size += chunk.len();
+                    if let Ok(file) = staged.as_mut() {
+                        match validate_file_size(size, config.server.max_file_size) {
+                            Ok(()) => file.write_all(&chunk)?,
+                            // Dropping the temp file deletes it; the rest of the field is skipped
+                            Err(e) => staged = Err(e),
+                        }
+                    }
                 }
-                validate_file_size(data.len(), config.server.max_file_size)?;
-                file_field = Some((filename, data));
+                file_fields.push((filename, staged.map(NamedTempFile::into_temp_path)));
             },
             "folder_id" => {
                 let mut folder_data = String::new();
                 while let Some(chunk) = field.next().await {
                     let chunk_bytes = chunk?;
+                    limits.add_bytes(chunk_bytes.len())?;
                     let chunk_str = std::str::from_utf8(&chunk_bytes)
                         .map_err(|e| AppError::BadRequest(format!("Invalid UTF-8 in folder_id: {}", e)))?;
                     folder_data.push_str(chunk_str);
@@ -76,57 +106,327 @@ pub async fn upload_file(
                     folder_id = Some(folder_data);
                 }
             },
+            "uploaded_at" => {
+                let mut date = Vec::new();
+                while let Some(chunk) = field.next().await {
+                    let chunk_bytes = chunk?;
+                    limits.add_bytes(chunk_bytes.len())?;
+                    date.extend_from_slice(&chunk_bytes);
+                }
+                let date = String::from_utf8(date)
+                    .map_err(|e| AppError::BadRequest(format!("Invalid UTF-8 in uploaded_at: {}", e)))?;
+                if !date.trim().is_empty() {
+                    uploaded_at = Some(parse_uploaded_at(&date)?);
+                }
+            },
             _ => continue,
         }
     }
     
-    // Process the file if we have one
-    if let Some((filename, data)) = file_field {
-        let file_manager = FileManager::new(&config.server.upload_dir, config.server.base_url.clone().unwrap_or_default());
-        let folder_manager = FolderManager::new(&config.server.upload_dir);
-        let image_processor = ImageProcessor::new(config.image.clone());
-        
-        let (unique_filename, uploaded_at, file_size) = process_uploaded_file(
-            data,
-            &filename,
-            folder_id,
-            &config,
-            &file_manager,
-            &folder_manager,
-            &image_processor,
-        ).await?;
-        
-        // Generate URLs and response
-        let base_url = config.server.base_url.as_deref().unwrap_or("http://localhost:8080");
-        let stem = unique_filename.rsplit('.').nth(1).unwrap_or("file");
-        
-        let response = UploadResponse {
-            success: true,
-            filename: unique_filename.clone(),
-            urls: FileUrls { 
-                original: format!("{}/uploads/{}", base_url, unique_filename),
-                qoi: if config.image.qoi_enabled && ImageProcessor::is_image_file(&unique_filename) {
-                    Some(format!("{}/uploads/{}.qoi", base_url, stem))
-                } else {
-                    None
-                },
-                thumbnail: if ImageProcessor::is_image_file(&unique_filename) {
-                    Some(format!("{}/uploads/{}_thumb.webp", base_url, stem))
-                } else {
-                    None
-                }
-            },
-            metadata: FileMetadata { 
-                size: file_size,
-                mime_type: "application/octet-stream".to_string(), // TODO: Implement proper MIME type detection
-                uploaded_at,
-                width: None, // TODO: Add image dimensions if it's an image
-                height: None 
-            }
+    if file_fields.is_empty() {
+        return Err(AppError::BadRequest("No file provided".to_string()));
+    }
+
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+    let image_processor = ImageProcessor::new(config.image.clone());
+    let batch = file_fields.len() > 1;
+    // An explicit folder, the root included, always wins over AUTO_FOLDERS
+    let auto_folder = folder_id.is_none();
+    if folder_id.as_deref() == Some("root") {
+        folder_id = None;
+    }
+
+    let mut uploaded = Vec::new();
+    let mut failed = Vec::new();
+    for (filename, staged) in file_fields {
+        let result = match staged {
+            Ok(staged) => process_uploaded_file(
+                staged,
+                &filename,
+                UploadOptions { folder_id: folder_id.clone(), uploaded_at, auto_folder, ..Default::default() },
+                &config,
+                &file_manager,
+                &folder_manager,
+                &image_processor,
+            ).await,
+            Err(e) => Err(e),
         };
-        
-        Ok(HttpResponse::Ok().json(response))
-    } else {
-        Err(AppError::BadRequest("No file provided".to_string()))
+
+        match result {
+            Ok(file) => uploaded.push(upload_response(file, &file_manager)),
+            // A single file keeps the plain error response
+            Err(e) if !batch => return Err(e),
+            Err(e) => {
+                warn!("Failed to upload {} in batch: {}", filename, e);
+                failed.push(UploadFailure { filename, reason: e.to_string() });
+            }
+        }
+    }
+
+    if !batch {
+        return Ok(HttpResponse::Ok().json(uploaded.remove(0)));
+    }
+
+    Ok(HttpResponse::Ok().json(MultiUploadResponse {
+        success: failed.is_empty(),
+        succeeded: uploaded.len(),
+        total: uploaded.len() + failed.len(),
+        uploaded,
+        failed,
+    }))
+}
+
+/// Build the response describing a stored file
+fn upload_response(uploaded: UploadedFile, file_manager: &FileManager) -> UploadResponse {
+    let unique_filename = uploaded.filename;
+    let stem = unique_filename.rsplit('.').nth(1).unwrap_or("file");
+    let stored_filename = file_manager.stored_filename(&unique_filename).unwrap_or_else(|| unique_filename.clone());
+    let thumb_filename = file_manager.generated_thumbnail(&unique_filename);
+    let jpeg_thumb_filename = thumbnail_name(&unique_filename, "jpeg");
+    
+    UploadResponse {
+        success: true,
+        filename: unique_filename.clone(),
+        urls: FileUrls { 
+            original: file_manager.file_url(&stored_filename),
+            // The folder of the file may override `qoi_enabled`
+            qoi: if ImageProcessor::is_image_file(&unique_filename) && file_manager.get_file_path(&format!("{}.qoi", stem)).exists() {
+                Some(file_manager.file_url(&format!("{}.qoi", stem)))
+            } else {
+                None
+            },
+            avif: if ImageProcessor::is_image_file(&unique_filename) && file_manager.get_file_path(&avif_name(&unique_filename)).exists() {
+                Some(file_manager.file_url(&avif_name(&unique_filename)))
+            } else {
+                None
+            },
+            thumbnail: if ImageProcessor::is_image_file(&unique_filename) {
+                // Images too small for a thumbnail are their own thumbnail
+                Some(file_manager.file_url(thumb_filename.as_deref().unwrap_or(&stored_filename)))
+            } else {
+                // PDFs and videos may have one rendered from a frame
+                thumb_filename.map(|thumb_filename| file_manager.file_url(&thumb_filename))
+            },
+            thumbnail_jpeg: if file_manager.get_file_path(&jpeg_thumb_filename).exists() {
+                Some(file_manager.file_url(&jpeg_thumb_filename))
+            } else {
+                None
+            },
+            thumbnails: file_manager.thumbnail_variants(&unique_filename).into_iter()
+                .map(|(size, variant_filename)| (size, file_manager.file_url(&variant_filename)))
+                .collect(),
+        },
+        metadata: FileMetadata { 
+            size: uploaded.size,
+            mime_type: uploaded.mime_type,
+            uploaded_at: uploaded.uploaded_at,
+            width: uploaded.dimensions.map(|(width, _)| width),
+            height: uploaded.dimensions.map(|(_, height)| height),
+            content_hash: Some(uploaded.content_hash),
+            original_name: Some(uploaded.original_name),
+            analysis: uploaded.analysis,
+            page_count: uploaded.page_count,
+        }
+    }
+}
+
+/// Header with the number of bytes of a resumable upload received so far
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+/// Header with the total size of a resumable upload
+const UPLOAD_LENGTH: &str = "Upload-Length";
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResumableUploadRequest {
+    /// Name of the file
+    filename: String,
+    /// Total size of the file, in bytes
+    length: u64,
+    /// Target folder; `root` keeps the file at the root when `AUTO_FOLDERS` is set
+    folder_id: Option<String>,
+    /// Original upload date (RFC 3339) to record instead of now, e.g. when migrating
+    uploaded_at: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/upload/init",
+    request_body = ResumableUploadRequest,
+    responses(
+        (status = 201, description = "Upload created, send its data with PATCH /api/upload/{id}", body = ResumableUploadResponse),
+        (status = 400, description = "Invalid request", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 413, description = "File too large", body = ErrorResponse),
+        (status = 507, description = "Not enough free disk space", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[post("/upload/init")]
+pub async fn init_resumable_upload(
+    request: web::Json<ResumableUploadRequest>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let request = request.into_inner();
+    if request.filename.trim().is_empty() {
+        return Err(AppError::BadRequest("Filename missing".to_string()));
+    }
+    validate_file_size(usize::try_from(request.length).unwrap_or(usize::MAX), config.server.max_file_size)?;
+    let uploaded_at = match request.uploaded_at.as_deref() {
+        Some(date) if !date.trim().is_empty() => Some(parse_uploaded_at(date)?),
+        _ => None,
+    };
+
+    let expiry_hours = config.server.resumable_upload_expiry_hours;
+    resumable_upload::purge_expired(&config.server.upload_dir, expiry_hours);
+    check_free_space(&config.server.upload_dir, request.length, config.server.min_free_bytes)?;
+
+    let upload = PendingUpload {
+        filename: request.filename,
+        length: request.length,
+        folder_id: request.folder_id.filter(|folder_id| !folder_id.is_empty()),
+        uploaded_at,
+        upload_dir: scope.upload_dir.clone(),
+        created_at: Utc::now(),
+    };
+    let upload_id = resumable_upload::create(&config.server.upload_dir, &upload)?;
+    info!("Started resumable upload {} of {} ({} bytes)", upload_id, upload.filename, upload.length);
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/api/upload/{}", upload_id)))
+        .insert_header((UPLOAD_OFFSET, "0"))
+        .json(ResumableUploadResponse {
+            upload_id,
+            offset: 0,
+            length: upload.length,
+            expires_at: upload.expires_at(expiry_hours),
+        }))
+}
+
+#[utoipa::path(
+    head,
+    path = "/api/upload/{id}",
+    params(
+        ("id" = String, Path, description = "Upload ID returned by /api/upload/init")
+    ),
+    responses(
+        (status = 200, description = "Bytes received so far in the `Upload-Offset` header, total size in `Upload-Length`"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "Unknown, completed or expired upload", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[head("/upload/{id}")]
+pub async fn resumable_upload_offset(
+    path: web::Path<String>,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let upload_id = path.into_inner();
+    let upload = pending_upload(&upload_id, &config, &scope)?;
+    let offset = resumable_upload::offset(&config.server.upload_dir, &upload_id)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((UPLOAD_OFFSET, offset.to_string()))
+        .insert_header((UPLOAD_LENGTH, upload.length.to_string()))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .finish())
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/upload/{id}",
+    params(
+        ("id" = String, Path, description = "Upload ID returned by /api/upload/init"),
+        ("Upload-Offset" = u64, Header, description = "Bytes already received, as returned by HEAD /api/upload/{id}")
+    ),
+    request_body(content = Vec<u8>, content_type = "application/offset+octet-stream"),
+    responses(
+        (status = 200, description = "Last bytes received, the file is stored", body = UploadResponse),
+        (status = 204, description = "Bytes received, new offset in the `Upload-Offset` header"),
+        (status = 400, description = "Invalid offset, data past the declared length or invalid file", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "Unknown, completed or expired upload", body = ErrorResponse),
+        (status = 409, description = "Offset doesn't match the bytes received, or another request is sending data", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Files"
+)]
+#[patch("/upload/{id}")]
+pub async fn append_resumable_upload(
+    req: HttpRequest,
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    config: web::Data<AppConfig>,
+    scope: StorageScope,
+) -> Result<HttpResponse, AppError> {
+    let upload_id = path.into_inner();
+    let upload = pending_upload(&upload_id, &config, &scope)?;
+    let _guard = UploadGuard::acquire(&upload_id)
+        .ok_or_else(|| AppError::Conflict(format!("Upload {} is already receiving data", upload_id)))?;
+
+    let client_offset: u64 = req.headers().get(UPLOAD_OFFSET)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| AppError::BadRequest(format!("Missing or invalid {} header", UPLOAD_OFFSET)))?;
+    let mut offset = resumable_upload::offset(&config.server.upload_dir, &upload_id)?;
+    if client_offset != offset {
+        return Err(AppError::Conflict(format!("Upload {} is at offset {}, not {}", upload_id, offset, client_offset)));
+    }
+
+    // Each chunk is written as it arrives, so a dropped connection keeps what was received
+    let data_path = resumable_upload::data_path(&config.server.upload_dir, &upload_id);
+    let mut data = std::fs::OpenOptions::new().append(true).open(&data_path)?;
+    let mut throttle = Throttle::new(config.server.max_upload_bytes_per_sec);
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| AppError::BadRequest(format!("Failed to read upload data: {}", e)))?;
+        if offset + chunk.len() as u64 > upload.length {
+            return Err(AppError::BadRequest(format!("Data goes past the declared length of {} bytes", upload.length)));
+        }
+        throttle.consume(chunk.len()).await;
+        data.write_all(&chunk)?;
+        offset += chunk.len() as u64;
+    }
+    drop(data);
+
+    if offset < upload.length {
+        return Ok(HttpResponse::NoContent()
+            .insert_header((UPLOAD_OFFSET, offset.to_string()))
+            .finish());
+    }
+
+    // Complete: the data goes through the same processing as a regular upload
+    resumable_upload::remove(&config.server.upload_dir, &upload_id, true);
+    let staged = TempPath::from_path(data_path);
+    let file_manager = scope.file_manager();
+    let folder_manager = scope.folder_manager(&config);
+    let image_processor = ImageProcessor::new(config.image.clone());
+    // An explicit folder, the root included, always wins over AUTO_FOLDERS
+    let auto_folder = upload.folder_id.is_none();
+    let folder_id = upload.folder_id.filter(|folder_id| folder_id != "root");
+    let uploaded = process_uploaded_file(
+        staged,
+        &upload.filename,
+        UploadOptions { folder_id, uploaded_at: upload.uploaded_at, auto_folder, ..Default::default() },
+        &config,
+        &file_manager,
+        &folder_manager,
+        &image_processor,
+    ).await?;
+    info!("Completed resumable upload {} as {}", upload_id, uploaded.filename);
+
+    Ok(HttpResponse::Ok()
+        .insert_header((UPLOAD_OFFSET, offset.to_string()))
+        .json(upload_response(uploaded, &file_manager)))
+}
+
+/// Resumable upload `upload_id`, if it is still pending and belongs to the request's storage
+fn pending_upload(upload_id: &str, config: &AppConfig, scope: &StorageScope) -> Result<PendingUpload, AppError> {
+    let upload = resumable_upload::load(&config.server.upload_dir, upload_id)?;
+    if upload.upload_dir != scope.upload_dir || upload.expires_at(config.server.resumable_upload_expiry_hours) <= Utc::now() {
+        return Err(AppError::NotFound(format!("Upload {}", upload_id)));
     }
+    Ok(upload)
 }
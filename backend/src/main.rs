@@ -1,8 +1,9 @@
-use actix_web::{web, App, HttpServer, middleware::Logger, http::Method};
+use actix_web::{guard, web, App, HttpServer, middleware::{Condition, Logger}, http::{header::DispositionType, Method}};
 use actix_files::Files;
 use actix_cors::Cors;
 use std::path::Path;
-use tracing::info;
+use std::time::Instant;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
@@ -18,23 +19,45 @@ mod docs;
 
 use config::AppConfig;
 use middleware::auth::AuthMiddleware;
+use middleware::content_etag::ContentEtagMiddleware;
+use middleware::ip_filter::IpFilterMiddleware;
 use middleware::rate_limit::RateLimitMiddleware;
-use handlers::auth::JwtService;
+use middleware::sharded_files::ShardedFilesMiddleware;
+use middleware::signed_url::SignedUrlMiddleware;
+use handlers::auth::{JwtService, BLACKLIST_PURGE_INTERVAL_SECONDS};
+use services::log_buffer::LogBuffer;
+use services::shard_layout::ShardLayout;
 use docs::ApiDoc;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Initialize tracing
+    // `snapfilething hash-password` prints the bcrypt hash of a password read from stdin
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        let hash = services::credentials::hash_password(password.trim_end_matches(['\r', '\n']))
+            .map_err(std::io::Error::other)?;
+        println!("{}", hash);
+        return Ok(());
+    }
+
+    // Initialize tracing, also keeping recent lines in memory for the admin log stream
+    let start_time = web::Data::new(handlers::health::StartTime(Instant::now()));
+    let log_buffer = LogBuffer::new();
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "snapfilething=info,actix_web=info".into()),
         )
         .with(tracing_subscriber::fmt::layer())
+        .with(log_buffer.layer())
         .init();
 
     // Load configuration
     let config = AppConfig::load().expect("Failed to load configuration");
+    log_buffer.set_capacity(config.server.log_buffer_lines);
+    utils::mime_type::set_custom_mime_types(config.server.custom_mime_types.clone());
+    let log_buffer = web::Data::new(log_buffer);
     
     // Ensure upload directory exists
     if !Path::new(&config.server.upload_dir).exists() {
@@ -47,17 +70,46 @@ async fn main() -> std::io::Result<()> {
     info!("Web interface is available on http://localhost:{}/web/", config.server.web_port);
     info!("API documentation is available at http://localhost:{}/docs", config.server.web_port);
     info!("Static files will be served on http://localhost:{}", config.server.static_port);
+    if config.auth.static_auth_required {
+        info!("Static files require authentication");
+    }
     info!("Authentication mode: {}", config.auth.mode);
+    if let Some(path) = &config.auth.htpasswd_file {
+        info!("Loaded {} users from {}", config.auth.users.len(), path);
+    }
+    if config.auth.mode == "protected" && config.auth.users.is_empty() && config.auth.admin_password_hash.is_none() {
+        warn!("ADMIN_PASSWORD is stored in plaintext and deprecated, set ADMIN_PASSWORD_HASH instead (see `snapfilething hash-password`)");
+    }
 
     let config_clone = config.clone();
     let config_clone2 = config.clone();
     let upload_dir = config.server.upload_dir.clone();
+    let shard_layout = ShardLayout::from_config(&config.server);
+    let inline_mime_categories = config.server.inline_mime_categories.clone();
     let static_port = config.server.static_port;
+    let workers = config.server.workers;
+    let max_connections = config.server.max_connections;
+    let shutdown_timeout = config.server.shutdown_timeout_seconds;
 
     // Create JWT service
-    let jwt_service = web::Data::new(JwtService::new(&config.auth.jwt_secret));
+    let jwt_service = web::Data::new(JwtService::new(&config.auth).expect("Failed to load JWT keys"));
+
+    let shutdown_jwt_service = jwt_service.clone();
+
+    // Periodically drop expired entries from the token blacklist
+    let purge_jwt_service = jwt_service.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(
+            std::time::Duration::from_secs(BLACKLIST_PURGE_INTERVAL_SECONDS),
+        );
+        loop {
+            interval.tick().await;
+            purge_jwt_service.purge_expired();
+        }
+    });
 
     // Start static file server (port 2)
+    let static_jwt_service = jwt_service.clone();
     let static_server = HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -66,16 +118,46 @@ async fn main() -> std::io::Result<()> {
             .max_age(3600);
 
         App::new()
+            .app_data(web::Data::new(config_clone.clone()))
+            .app_data(static_jwt_service.clone())
+            .wrap(ShardedFilesMiddleware::new(&upload_dir, shard_layout))
+            .wrap(ContentEtagMiddleware::new(&upload_dir, shard_layout))
+            .wrap(Condition::new(
+                config_clone.auth.static_auth_required,
+                AuthMiddleware::for_static_files(config_clone.auth.clone(), &upload_dir),
+            ))
+            .wrap(SignedUrlMiddleware::new(&config_clone.auth))
             .wrap(cors)
-            .wrap(Logger::default())
+            .wrap(Logger::new(&config_clone.server.access_log_format))
             .wrap(RateLimitMiddleware::new(&config_clone.rate_limit))
+            // Precompressed companions take over when the client accepts them
+            .service(
+                web::resource("/uploads/{path:.*}")
+                    .guard(guard::fn_guard(handlers::precompressed::has_gzip_companion))
+                    .to(handlers::precompressed::serve_gzip_companion)
+            )
             .service(
                 Files::new("/uploads", &upload_dir)
                     .use_etag(true)
                     .use_last_modified(true)
                     .prefer_utf8(true)
+                    .mime_override({
+                        let inline_mime_categories = inline_mime_categories.clone();
+                        move |category| {
+                            if inline_mime_categories.iter().any(|inline| inline == category.as_str()) {
+                                DispositionType::Inline
+                            } else {
+                                DispositionType::Attachment
+                            }
+                        }
+                    })
             )
     })
+    .workers(workers)
+    .max_connections(max_connections)
+    .shutdown_timeout(shutdown_timeout)
+    // Signals are handled below, to stop both servers together
+    .disable_signals()
     .bind(format!("0.0.0.0:{}", static_port))?
     .run();
 
@@ -99,11 +181,14 @@ async fn main() -> std::io::Result<()> {
             .allowed_headers(config_clone2.cors.allowed_headers.clone())
             .max_age(3600);
 
-        let app = App::new()
+        App::new()
             .app_data(web::Data::new(config_clone2.clone()))
             .app_data(jwt_service.clone())
+            .app_data(log_buffer.clone())
+            .app_data(start_time.clone())
+            .wrap(IpFilterMiddleware::new(&config_clone2.ip_filter))
             .wrap(cors)
-            .wrap(Logger::default())
+            .wrap(Logger::new(&config_clone2.server.access_log_format))
             .wrap(RateLimitMiddleware::new(&config_clone2.rate_limit))
             .wrap(AuthMiddleware::new(config_clone2.auth.clone()))
             .service(
@@ -117,15 +202,38 @@ async fn main() -> std::io::Result<()> {
                             .route("/verify", web::get().to(handlers::auth::verify_token))
                     )
                     .service(handlers::upload::upload_file)
+                    .service(handlers::upload::init_resumable_upload)
+                    .service(handlers::upload::resumable_upload_offset)
+                    .service(handlers::upload::append_resumable_upload)
                     .service(handlers::files::list_files)
+                    .service(handlers::files::list_duplicates)
+                    .service(handlers::files::backfill_hashes)
+                    .service(handlers::files::raw_listing)
+                    .service(handlers::files::batch_info)
+                    .service(handlers::files::delete_files)
+                    .service(handlers::files::organize_files)
+                    .service(handlers::files::rotate_file)
+                    .service(handlers::tiles::tile_info)
+                    .service(handlers::tiles::get_tile)
+                    .service(handlers::files::file_analysis)
                     .service(handlers::files::delete_file)
                     .service(handlers::files::move_file)
+                    .service(handlers::files::rename_file)
+                    .service(handlers::files::create_signed_url)
+                    .service(handlers::files::download_file)
                     .service(handlers::files::export_files)
                     .service(handlers::files::import_files)
                     .service(handlers::folders::list_folders)
                     .service(handlers::folders::create_folder)
+                    .service(handlers::folders::update_folder)
                     .service(handlers::folders::delete_folder)
+                    .service(handlers::folders::delete_preview)
                     .service(handlers::folders::move_folder)
+                    .service(handlers::folders::rename_folder)
+                    .service(handlers::folders::export_folder_manifest)
+                    .service(handlers::stats::derivative_stats)
+                    .service(handlers::logs::stream_logs)
+                    .default_service(web::to(handlers::frontend::api_not_found))
             )
             .service(
                 SwaggerUi::new("/docs/{_:.*}")
@@ -133,14 +241,54 @@ async fn main() -> std::io::Result<()> {
             )
             .service(handlers::frontend::serve_assets)
             .service(handlers::frontend::serve_static_files)
-            .service(handlers::frontend::serve_index);
-        app
+            .service(handlers::frontend::serve_index)
     })
+    .workers(workers)
+    .max_connections(max_connections)
+    .shutdown_timeout(shutdown_timeout)
+    // Signals are handled below, to stop both servers together
+    .disable_signals()
     .bind(format!("0.0.0.0:{}", config.server.web_port))?
     .run();
 
+    // Stop accepting connections on SIGTERM or Ctrl-C, then give in-flight
+    // requests SHUTDOWN_TIMEOUT to finish
+    let static_handle = static_server.handle();
+    let web_handle = web_server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down, waiting up to {}s for in-flight requests", shutdown_timeout);
+        futures::join!(static_handle.stop(true), web_handle.stop(true));
+    });
+
     // Run both servers concurrently
     tokio::try_join!(static_server, web_server).map(|_| ())?;
 
+    // Metadata is written as each request completes; only the blacklist may lag after a failed save
+    shutdown_jwt_service.flush_blacklist();
+    info!("Shutdown complete");
+
     Ok(())
 }
+
+/// Resolve on Ctrl-C, or on SIGTERM as sent by container orchestrators
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM, only Ctrl-C stops the server gracefully: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
@@ -1,32 +1,94 @@
 use actix_web::{
-    dev::{ServiceRequest, ServiceResponse},
-    Error, HttpResponse, body::EitherBody, web,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, body::EitherBody, web, http::Method,
 };
 use actix_web::dev::{Service, Transform};
-use futures::future::{ok, Ready};
+use futures::future::{ok, ready, Ready};
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use base64::Engine;
-use constant_time_eq::constant_time_eq;
 use tracing::warn;
 
 use crate::handlers::auth::JwtService;
-use crate::config::AuthConfig;
+use crate::middleware::signed_url::SignedRequest;
+use crate::config::{AppConfig, AuthConfig};
+use crate::error::AppError;
+use crate::services::credentials::verify_credentials;
+use crate::services::file_utils::original_stem;
+use crate::services::folder_manager::FolderManager;
+use crate::services::storage_scope::USERS_DIR;
+
+/// User authenticated by the middleware, stored in the request extensions
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub username: String,
+}
+
+/// Marker in the request extensions of unauthenticated reads of the public
+/// gallery folder
+#[derive(Debug, Clone, Copy)]
+pub struct PublicRead;
+
+/// Extractor for routes reserved to the admin. Local mode trusts every request.
+pub struct AdminUser;
+
+impl FromRequest for AdminUser {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let Some(config) = req.app_data::<web::Data<AppConfig>>() else {
+            return ready(Err(AppError::Internal("Missing application config".to_string())));
+        };
+        if config.auth.mode == "local" {
+            return ready(Ok(AdminUser));
+        }
+
+        let is_admin = req.extensions().get::<AuthenticatedUser>()
+            .is_some_and(|user| user.username == config.auth.admin_username);
+        ready(if is_admin {
+            Ok(AdminUser)
+        } else {
+            Err(AppError::Forbidden("Only the admin can use this endpoint".to_string()))
+        })
+    }
+}
+
+/// Stems of the files of the public gallery folder, with the metadata version
+/// they were read at
+type PublicStems = (String, Arc<HashSet<String>>);
 
 pub struct AuthMiddleware {
-    auth_config: AuthConfig,
+    auth_config: Arc<AuthConfig>,
+    static_files: Option<Arc<PathBuf>>,
+    public_stems: Arc<Mutex<Option<PublicStems>>>,
 }
 
 impl AuthMiddleware {
     pub fn new(auth_config: AuthConfig) -> Self {
-        Self { auth_config }
+        Self { auth_config: Arc::new(auth_config), static_files: None, public_stems: Arc::default() }
+    }
+
+    /// Guard for the static file server: every `/uploads` path outside the
+    /// public gallery folder requires credentials, which may also be passed as
+    /// a `token` query parameter since browsers can't attach headers to `<img>` requests
+    pub fn for_static_files(auth_config: AuthConfig, upload_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            auth_config: Arc::new(auth_config),
+            static_files: Some(Arc::new(upload_dir.into())),
+            public_stems: Arc::default(),
+        }
     }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -38,20 +100,188 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(AuthMiddlewareService {
-            service,
+            service: Rc::new(service),
             auth_config: self.auth_config.clone(),
+            static_files: self.static_files.clone(),
+            public_stems: self.public_stems.clone(),
         })
     }
 }
 
 pub struct AuthMiddlewareService<S> {
-    service: S,
-    auth_config: AuthConfig,
+    service: Rc<S>,
+    auth_config: Arc<AuthConfig>,
+    /// Upload directory, when guarding the static file server
+    static_files: Option<Arc<PathBuf>>,
+    public_stems: Arc<Mutex<Option<PublicStems>>>,
+}
+
+impl<S> AuthMiddlewareService<S> {
+    /// Check if the request is a read of the public gallery folder
+    fn is_public_read(&self, req: &ServiceRequest) -> bool {
+        if !self.auth_config.public_read_only || req.method() != Method::GET {
+            return false;
+        }
+
+        let Some(ref public_folder_id) = self.auth_config.public_folder_id else {
+            return false;
+        };
+
+        if req.path() != "/api/files" {
+            return false;
+        }
+
+        web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .map(|query| query.get("folder_id") == Some(public_folder_id))
+            .unwrap_or(false)
+    }
+
+    /// Check if a static file request targets an upload of the public gallery
+    /// folder, or one of its derivatives
+    fn is_public_file(&self, req: &ServiceRequest, upload_dir: &Path) -> bool {
+        if !self.auth_config.public_read_only || !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return false;
+        }
+
+        let Some(ref public_folder_id) = self.auth_config.public_folder_id else {
+            return false;
+        };
+
+        // The public folder lives in the shared storage, never under `users/`
+        let Some(segments) = upload_relative_path(req.match_info().as_str()).and_then(upload_segments) else {
+            return false;
+        };
+        let [name] = segments.as_slice() else {
+            return false;
+        };
+
+        self.public_stems(upload_dir, public_folder_id).contains(original_stem(name))
+    }
+
+    /// Stems of the files of the public gallery folder, reloaded when the
+    /// metadata changed since the last request
+    fn public_stems(&self, upload_dir: &Path, public_folder_id: &str) -> Arc<HashSet<String>> {
+        let folder_manager = FolderManager::new(upload_dir);
+        let version = folder_manager.metadata_version();
+        let mut public_stems = self.public_stems.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((cached_version, cached)) = public_stems.as_ref() {
+            if *cached_version == version {
+                return cached.clone();
+            }
+        }
+
+        let loaded: HashSet<String> = folder_manager.load_file_metadata()
+            .unwrap_or_default()
+            .into_values()
+            .filter(|meta| meta.folder_id.as_deref() == Some(public_folder_id))
+            .map(|meta| original_stem(&meta.filename).to_string())
+            .collect();
+        let loaded = Arc::new(loaded);
+        *public_stems = Some((version, loaded.clone()));
+        loaded
+    }
+
+    /// Credentials sent with the request, if any. Tokens are checked here,
+    /// Basic auth pairs are left to the caller.
+    fn credentials(&self, req: &ServiceRequest) -> Option<Credentials> {
+        if self.static_files.is_some() {
+            let query_token = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                .ok()
+                .and_then(|query| query.get("token").cloned());
+            if let Some(token) = query_token {
+                return validate_access_token(req, &token).map(Credentials::Token);
+            }
+        }
+
+        let auth_str = req.headers().get("Authorization")?.to_str().ok()?;
+
+        // Try JWT Bearer token first
+        if let Some(token) = auth_str.strip_prefix("Bearer ") {
+            return validate_access_token(req, token).map(Credentials::Token);
+        }
+
+        // Fallback to Basic Auth for backward compatibility
+        let encoded = auth_str.strip_prefix("Basic ")?;
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).ok()?;
+        let credentials = String::from_utf8(decoded).ok()?;
+        let (provided_username, provided_password) = credentials.split_once(':')?;
+
+        Some(Credentials::Basic(provided_username.to_string(), provided_password.to_string()))
+    }
+}
+
+/// Credentials sent with a request
+enum Credentials {
+    /// Subject of a valid access token
+    Token(String),
+    /// Unchecked Basic auth username and password
+    Basic(String, String),
+}
+
+/// Per-user storage under `/uploads/users/<username>` is only readable by
+/// its owner and the admin. `path` is the path the router matched on.
+fn may_read_upload(auth_config: &AuthConfig, path: &str, username: &str) -> bool {
+    if username == auth_config.admin_username {
+        return true;
+    }
+    let Some(relative) = upload_relative_path(path) else {
+        return true;
+    };
+
+    match upload_segments(relative).as_deref() {
+        Some([first, rest @ ..]) if first == USERS_DIR => rest.first().is_some_and(|owner| owner == username),
+        Some(_) => true,
+        // Refused by the file service anyway
+        None => false,
+    }
+}
+
+/// The part of a request path after the `/uploads` mount point, if it is under it
+fn upload_relative_path(path: &str) -> Option<&str> {
+    path.strip_prefix("/uploads")
+        .filter(|relative| relative.is_empty() || relative.starts_with('/'))
+}
+
+/// Segments of the file a static file request resolves to, relative to the
+/// upload directory, read the way actix-files reads them: percent-decoded
+/// once more, without empty segments and with `..` applied.
+/// None for paths the file service refuses.
+fn upload_segments(relative: &str) -> Option<Vec<String>> {
+    let decoded = percent_encoding::percent_decode_str(relative).decode_utf8().ok()?;
+    // A decoded `%2F` never separates segments
+    if decoded.matches('/').count() != relative.matches('/').count() {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" => {}
+            ".." => {
+                segments.pop();
+            }
+            hidden if hidden.starts_with('.') => return None,
+            _ => segments.push(segment.to_string()),
+        }
+    }
+    Some(segments)
+}
+
+/// Validate a JWT and return its subject if it is an access token
+fn validate_access_token(req: &ServiceRequest, token: &str) -> Option<String> {
+    let jwt_service = req.app_data::<web::Data<JwtService>>()?;
+    let token_data = jwt_service.validate_token(token).ok()?;
+    // Ensure it's an access token
+    if token_data.claims.token_type == "access" {
+        Some(token_data.claims.sub)
+    } else {
+        None
+    }
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -78,41 +308,54 @@ where
             });
         }
 
-        // Check if the route is in the disabled routes list
-        let is_auth_disabled = self.auth_config.disabled_routes.iter().any(|route| {
-            if route == path {
-                return true;
-            }
-            // Handle special patterns
-            if route.ends_with("/*") {
-                let prefix = &route[..route.len() - 2];
-                return path.starts_with(prefix) || path == prefix;
+        // Everything on the static file server is protected, except signed
+        // links and the public gallery folder
+        let is_public = if let Some(upload_dir) = &self.static_files {
+            req.extensions().contains::<SignedRequest>() || self.is_public_file(&req, upload_dir)
+        } else {
+            // Check if the route is in the disabled routes list
+            let is_auth_disabled = self.auth_config.disabled_routes.iter().any(|route| {
+                if route == path {
+                    return true;
+                }
+                // Handle special patterns
+                if route.ends_with("/*") {
+                    let prefix = &route[..route.len() - 2];
+                    return path.starts_with(prefix) || path == prefix;
+                }
+                false
+            });
+
+            // Also check for static file patterns that should always be accessible
+            let is_static_file = path.ends_with(".ico") 
+                || path.ends_with(".png") 
+                || path.ends_with(".jpg") 
+                || path.ends_with(".jpeg") 
+                || path.ends_with(".gif") 
+                || path.ends_with(".svg") 
+                || path.ends_with(".webp") 
+                || path.ends_with(".css") 
+                || path.ends_with(".js") 
+                || path.ends_with(".woff") 
+                || path.ends_with(".woff2") 
+                || path.ends_with(".ttf") 
+                || path.ends_with(".eot") 
+                || path.ends_with(".txt") 
+                || path.ends_with(".json") 
+                || path.ends_with(".webmanifest")
+                || path.starts_with("/assets/")
+                || path.starts_with("/web/assets/")
+                || path.starts_with("/uploads/");
+
+            let is_public_read = self.is_public_read(&req);
+            if is_public_read {
+                req.extensions_mut().insert(PublicRead);
             }
-            false
-        });
-
-        // Also check for static file patterns that should always be accessible
-        let is_static_file = path.ends_with(".ico") 
-            || path.ends_with(".png") 
-            || path.ends_with(".jpg") 
-            || path.ends_with(".jpeg") 
-            || path.ends_with(".gif") 
-            || path.ends_with(".svg") 
-            || path.ends_with(".webp") 
-            || path.ends_with(".css") 
-            || path.ends_with(".js") 
-            || path.ends_with(".woff") 
-            || path.ends_with(".woff2") 
-            || path.ends_with(".ttf") 
-            || path.ends_with(".eot") 
-            || path.ends_with(".txt") 
-            || path.ends_with(".json") 
-            || path.ends_with(".webmanifest")
-            || path.starts_with("/assets/")
-            || path.starts_with("/web/assets/")
-            || path.starts_with("/uploads/");
-
-        if is_auth_disabled || is_static_file {
+
+            is_auth_disabled || is_static_file || is_public_read
+        };
+
+        if is_public {
             let fut = self.service.call(req);
             return Box::pin(async move {
                 let res = fut.await?;
@@ -120,79 +363,95 @@ where
             });
         }
 
-        let username = self.auth_config.admin_username.clone();
-        let password = self.auth_config.admin_password.clone();
+        let credentials = self.credentials(&req);
+        let service = self.service.clone();
+        let auth_config = self.auth_config.clone();
+        let static_files = self.static_files.is_some();
 
-        let auth_header = req.headers().get("Authorization");
-        
-        if let Some(auth_value) = auth_header {
-            if let Ok(auth_str) = auth_value.to_str() {
-                // Try JWT Bearer token first
-                if auth_str.starts_with("Bearer ") {
-                    let token = &auth_str[7..];
-                    
-                    // Get JWT service from app data
-                    if let Some(jwt_service) = req.app_data::<web::Data<JwtService>>() {
-                        match jwt_service.validate_token(token) {
-                            Ok(token_data) => {
-                                // Ensure it's an access token
-                                if token_data.claims.token_type == "access" {
-                                    let fut = self.service.call(req);
-                                    return Box::pin(async move {
-                                        let res = fut.await?;
-                                        Ok(res.map_into_left_body())
-                                    });
-                                }
-                            }
-                            Err(_) => {
-                                // JWT validation failed, continue to basic auth fallback
-                            }
-                        }
-                    }
-                }
-                // Fallback to Basic Auth for backward compatibility
-                else if auth_str.starts_with("Basic ") {
-                    let encoded = &auth_str[6..];
-                    if let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(encoded) {
-                        if let Ok(credentials) = String::from_utf8(decoded) {
-                            let parts: Vec<&str> = credentials.splitn(2, ':').collect();
-                            if parts.len() == 2 {
-                                let provided_username = parts[0];
-                                let provided_password = parts[1];
-                                
-                                // Use constant time comparison to prevent timing attacks
-                                let username_valid = constant_time_eq(
-                                    username.as_bytes(),
-                                    provided_username.as_bytes()
-                                );
-                                let password_valid = constant_time_eq(
-                                    password.as_bytes(),
-                                    provided_password.as_bytes()
-                                );
-                                
-                                if username_valid && password_valid {
-                                    let fut = self.service.call(req);
-                                    return Box::pin(async move {
-                                        let res = fut.await?;
-                                        Ok(res.map_into_left_body())
-                                    });
-                                }
-                            }
-                        }
-                    }
+        Box::pin(async move {
+            let username = match credentials {
+                Some(Credentials::Token(username)) => Some(username),
+                // Password hashing is CPU-bound, keep it off the async workers
+                Some(Credentials::Basic(username, password)) => {
+                    let auth_config = auth_config.clone();
+                    web::block(move || {
+                        verify_credentials(&auth_config, &username, &password).then_some(username)
+                    })
+                    .await?
                 }
+                None => None,
+            };
+
+            let Some(username) = username else {
+                warn!("Unauthorized access attempt to: {}", req.path());
+                let response = HttpResponse::Unauthorized()
+                    .json(serde_json::json!({
+                        "error": "Authentication required",
+                        "message": "Please provide valid credentials"
+                    }));
+                return Ok(req.into_response(response).map_into_right_body());
+            };
+
+            if static_files && !may_read_upload(&auth_config, req.match_info().as_str(), &username) {
+                warn!("User {} denied access to: {}", username, req.path());
+                let response = HttpResponse::Forbidden()
+                    .json(serde_json::json!({
+                        "error": "Forbidden",
+                        "message": "You don't have access to this file"
+                    }));
+                return Ok(req.into_response(response).map_into_right_body());
             }
-        }
 
-        warn!("Unauthorized access attempt to: {}", path);
-        
-        Box::pin(async move {
-            let response = HttpResponse::Unauthorized()
-                .json(serde_json::json!({
-                    "error": "Authentication required",
-                    "message": "Please provide valid credentials"
-                }));
-            Ok(req.into_response(response).map_into_right_body())
+            req.extensions_mut().insert(AuthenticatedUser { username });
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_files::Files;
+    use actix_web::{http::StatusCode, test, App};
+
+    async fn status_for(path: &str, username: &str) -> StatusCode {
+        let dir = tempfile::tempdir().unwrap();
+        for owner in ["alice", "bob"] {
+            let user_dir = dir.path().join(USERS_DIR).join(owner);
+            std::fs::create_dir_all(&user_dir).unwrap();
+            std::fs::write(user_dir.join("notes.txt"), owner).unwrap();
+        }
+
+        let config = AppConfig::default();
+        let jwt_service = JwtService::new(&config.auth).unwrap();
+        let token = jwt_service.create_access_token(username).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(jwt_service))
+                .wrap(AuthMiddleware::for_static_files(config.auth.clone(), dir.path()))
+                .service(Files::new("/uploads", dir.path()))
+        ).await;
+        let req = test::TestRequest::get()
+            .uri(path)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        test::call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn users_read_only_their_own_storage() {
+        assert_eq!(status_for("/uploads/users/bob/notes.txt", "bob").await, StatusCode::OK);
+        assert_eq!(status_for("/uploads/users/alice/notes.txt", "bob").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for("/uploads/users/alice/notes.txt", "admin").await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn encoded_paths_do_not_reach_other_users_storage() {
+        assert_eq!(status_for("/uploads/%75sers/alice/notes.txt", "bob").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for("/uploads//users/alice/notes.txt", "bob").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for("/uploads/x/../users/alice/notes.txt", "bob").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for("/uploads/users/bob/../alice/notes.txt", "bob").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for("/uploads/%75sers/bob/notes.txt", "bob").await, StatusCode::OK);
+    }
+}
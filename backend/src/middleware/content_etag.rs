@@ -0,0 +1,169 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    body::EitherBody,
+    http::{header::{self, EntityTag, IfNoneMatch}, Method, StatusCode},
+    Error, HttpMessage, HttpResponse,
+};
+use futures::future::{Ready, ready, LocalBoxFuture};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::services::folder_manager::FolderManager;
+use crate::services::shard_layout::{ShardLayout, StorageDir};
+
+/// Content hashes of the files of one storage directory, with the metadata
+/// version they were read at
+type DirectoryHashes = (String, Arc<HashMap<String, String>>);
+
+/// Serve uploads with their content hash as `ETag`, the same value listings
+/// report as `content_hash`, so clients can revalidate a file against a
+/// listing without downloading it. Files without a recorded hash, such as
+/// derivatives, keep the `ETag` of the file service.
+pub struct ContentEtagMiddleware {
+    upload_dir: Arc<PathBuf>,
+    layout: ShardLayout,
+    hashes: Arc<Mutex<HashMap<PathBuf, DirectoryHashes>>>,
+}
+
+impl ContentEtagMiddleware {
+    pub fn new(upload_dir: impl Into<PathBuf>, layout: ShardLayout) -> Self {
+        Self {
+            upload_dir: Arc::new(upload_dir.into()),
+            layout,
+            hashes: Arc::default(),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ContentEtagMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ContentEtagService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ContentEtagService {
+            service,
+            upload_dir: self.upload_dir.clone(),
+            layout: self.layout,
+            hashes: self.hashes.clone(),
+        }))
+    }
+}
+
+pub struct ContentEtagService<S> {
+    service: S,
+    upload_dir: Arc<PathBuf>,
+    layout: ShardLayout,
+    hashes: Arc<Mutex<HashMap<PathBuf, DirectoryHashes>>>,
+}
+
+impl<S> ContentEtagService<S> {
+    /// Content hash of the upload a static file request targets, if recorded
+    fn content_hash(&self, req: &ServiceRequest) -> Option<String> {
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return None;
+        }
+        let relative = req.path().strip_prefix("/uploads/")?;
+        if !relative.split('/').all(|segment| !segment.is_empty() && !segment.starts_with('.')) {
+            return None;
+        }
+        let path = self.upload_dir.join(relative);
+        let (directory, filename) = (path.parent()?, path.file_name()?.to_str()?);
+        // URLs stay flat when files are stored in shard subdirectories
+        if !StorageDir::new(directory, self.layout).path(filename).is_file() {
+            return None;
+        }
+        self.directory_hashes(directory).get(filename).cloned()
+    }
+
+    /// Content hashes of the files stored in `directory`, reloaded when its
+    /// metadata changed since the last request
+    fn directory_hashes(&self, directory: &Path) -> Arc<HashMap<String, String>> {
+        let folder_manager = FolderManager::new(directory);
+        let version = folder_manager.metadata_version();
+        let mut hashes = self.hashes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((cached_version, cached)) = hashes.get(directory) {
+            if *cached_version == version {
+                return cached.clone();
+            }
+        }
+
+        let loaded: HashMap<String, String> = folder_manager.load_file_metadata()
+            .unwrap_or_default()
+            .into_values()
+            .filter_map(|meta| Some((meta.filename, meta.content_hash?)))
+            .collect();
+        let loaded = Arc::new(loaded);
+        // Directories without metadata aren't remembered, any path may be requested
+        if !loaded.is_empty() {
+            hashes.insert(directory.to_path_buf(), (version, loaded.clone()));
+        }
+        loaded
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ContentEtagService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let Some(hash) = self.content_hash(&req) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        };
+        let etag = EntityTag::new_strong(hash);
+
+        let not_modified = match req.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            None => false,
+        };
+        if not_modified {
+            return Box::pin(async move {
+                let response = HttpResponse::NotModified()
+                    .insert_header(header::ETag(etag))
+                    .finish();
+                Ok(req.into_response(response).map_into_right_body())
+            });
+        }
+
+        // The file service only knows its own validator, a matching `If-Range`
+        // means the client's partial copy is current
+        let if_range_matches = req.headers().get(header::IF_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<EntityTag>().ok())
+            .is_some_and(|tag| tag.strong_eq(&etag));
+        if if_range_matches {
+            req.headers_mut().remove(header::IF_RANGE);
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            // Precompressed companions are a different representation with their own validator
+            let is_identity = !res.headers().contains_key(header::CONTENT_ENCODING);
+            if matches!(res.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT) && is_identity {
+                if let Ok(value) = etag.to_string().parse() {
+                    res.headers_mut().insert(header::ETAG, value);
+                }
+            }
+            Ok(res.map_into_left_body())
+        })
+    }
+}
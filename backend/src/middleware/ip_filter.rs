@@ -0,0 +1,188 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    Error,
+};
+use futures::future::{Ready, ready, LocalBoxFuture};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::config::{IpFilterConfig, IpRules};
+use crate::error::AppError;
+use crate::middleware::rate_limit::client_ip;
+
+/// Restrict mutating and admin routes to configured networks.
+/// Read endpoints are never filtered.
+pub struct IpFilterMiddleware {
+    config: Arc<IpFilterConfig>,
+}
+
+impl IpFilterMiddleware {
+    pub fn new(config: &IpFilterConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IpFilterMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = IpFilterService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IpFilterService {
+            service,
+            config: self.config.clone(),
+        }))
+    }
+}
+
+pub struct IpFilterService<S> {
+    service: S,
+    config: Arc<IpFilterConfig>,
+}
+
+impl<S> IpFilterService<S> {
+    /// Rules a request must pass. Routes are matched on the path as the router
+    /// decoded it; the escapes it leaves in place (`%25`, `%2F` and `%2B`) could
+    /// hide a filtered route, so such paths must pass every route group.
+    fn rules_for(&self, req: &ServiceRequest) -> Vec<&IpRules> {
+        let path = req.match_info().as_str();
+        if path.contains('%') {
+            return vec![&self.config.admin, &self.config.upload, &self.config.manage];
+        }
+        self.route_rules(req, path).into_iter().collect()
+    }
+
+    /// Rules of the route group a request belongs to, if it is filtered
+    fn route_rules(&self, req: &ServiceRequest, path: &str) -> Option<&IpRules> {
+        let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+
+        if path.starts_with("/api/admin") || path.starts_with("/api/stats") || path == "/api/files/raw-listing" {
+            Some(&self.config.admin)
+        } else if is_read {
+            None
+        } else if path == "/api/upload" || path.starts_with("/api/upload/") || path == "/api/files/import" {
+            Some(&self.config.upload)
+        } else if path == "/api/files/batch-info" {
+            // A lookup that happens to be a POST
+            None
+        } else if path.starts_with("/api/files") || path.starts_with("/api/folders") {
+            Some(&self.config.manage)
+        } else {
+            None
+        }
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for IpFilterService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let rules = self.rules_for(&req);
+        if !rules.is_empty() {
+            let ip = client_ip(&req, Some(&self.config.trusted_proxies));
+            if !rules.iter().all(|group| group.permits(ip)) {
+                warn!("Blocked {} {} from {}", req.method(), req.path(), ip);
+                return Box::pin(async move {
+                    Err(AppError::Forbidden(format!("Access from {} is not allowed", ip)).into())
+                });
+            }
+        }
+
+        Box::pin(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    use std::net::SocketAddr;
+
+    use crate::config::IpFilterConfig;
+
+    fn filter_config() -> IpFilterConfig {
+        IpFilterConfig {
+            upload: IpRules {
+                allow: vec!["10.0.0.0/8".parse().unwrap()],
+                deny: vec![],
+            },
+            manage: IpRules {
+                allow: vec![],
+                deny: vec!["203.0.113.0/24".parse().unwrap()],
+            },
+            admin: IpRules {
+                allow: vec!["127.0.0.0/8".parse().unwrap()],
+                deny: vec![],
+            },
+            ..Default::default()
+        }
+    }
+
+    async fn status_for(method: Method, path: &str, peer: &str) -> StatusCode {
+        let app = test::init_service(
+            App::new()
+                .wrap(IpFilterMiddleware::new(&filter_config()))
+                .default_service(web::to(HttpResponse::Ok))
+        ).await;
+        let req = test::TestRequest::default()
+            .method(method)
+            .uri(path)
+            .peer_addr(format!("{}:40000", peer).parse::<SocketAddr>().unwrap())
+            // Not from a trusted proxy, so ignored
+            .insert_header(("X-Forwarded-For", "10.0.0.1"))
+            .to_request();
+        match app.call(req).await {
+            Ok(res) => res.status(),
+            Err(e) => e.error_response().status(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn allowlisted_networks_may_upload() {
+        assert_eq!(status_for(Method::POST, "/api/upload", "10.1.2.3").await, StatusCode::OK);
+        assert_eq!(status_for(Method::POST, "/api/upload", "192.168.1.10").await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn denylisted_networks_may_not_mutate() {
+        assert_eq!(status_for(Method::DELETE, "/api/files/photo.png", "203.0.113.7").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(Method::POST, "/api/folders", "203.0.113.7").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(Method::DELETE, "/api/files/photo.png", "198.51.100.1").await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn encoded_paths_are_filtered() {
+        assert_eq!(status_for(Method::POST, "/api/%75pload", "192.168.1.10").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(Method::POST, "/api/%66iles/import", "192.168.1.10").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(Method::DELETE, "/api/%66iles/photo.png", "203.0.113.7").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(Method::POST, "/api/upload%2Fabc", "192.168.1.10").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(Method::GET, "/api/%61dmin/logs/stream", "192.168.1.10").await, StatusCode::FORBIDDEN);
+        assert_eq!(status_for(Method::GET, "/api/%61dmin/logs/stream", "127.0.0.1").await, StatusCode::OK);
+        assert_eq!(status_for(Method::POST, "/api/%75pload", "10.1.2.3").await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn reads_stay_open() {
+        assert_eq!(status_for(Method::GET, "/api/files", "203.0.113.7").await, StatusCode::OK);
+        assert_eq!(status_for(Method::POST, "/api/files/batch-info", "203.0.113.7").await, StatusCode::OK);
+        assert_eq!(status_for(Method::GET, "/api/upload/abc", "192.168.1.10").await, StatusCode::OK);
+    }
+}
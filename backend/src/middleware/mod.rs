@@ -1,2 +1,6 @@
 pub mod auth;
+pub mod content_etag;
+pub mod ip_filter;
 pub mod rate_limit;
+pub mod sharded_files;
+pub mod signed_url;
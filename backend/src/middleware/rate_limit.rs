@@ -11,6 +11,7 @@ use std::{
     sync::Arc,
 };
 use std::num::NonZeroU32;
+use ipnet::IpNet;
 use crate::config::RateLimitConfig;
 
 pub struct RateLimitMiddleware {
@@ -112,35 +113,43 @@ impl<S> RateLimitService<S> {
             Some("upload") // Default to upload rate limits for other routes
         }
     }
+}
 
-    fn get_client_ip(&self, req: &ServiceRequest) -> IpAddr {
-        // Try to get IP from X-Forwarded-For header first (for reverse proxies)
-        if let Some(forwarded_for) = req.headers().get("x-forwarded-for") {
-            if let Ok(forwarded_str) = forwarded_for.to_str() {
-                if let Some(first_ip) = forwarded_str.split(',').next() {
-                    if let Ok(ip) = first_ip.trim().parse::<IpAddr>() {
-                        return ip;
-                    }
-                }
-            }
-        }
+/// Client IP of a request. Forwarding headers set by reverse proxies are honored
+/// only when the direct peer is in `trusted_proxies`; `None` trusts every peer.
+pub fn client_ip(req: &ServiceRequest, trusted_proxies: Option<&[IpNet]>) -> IpAddr {
+    let peer_ip = req.peer_addr()
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)));
+
+    let trust_headers = trusted_proxies
+        .is_none_or(|proxies| proxies.iter().any(|net| net.contains(&peer_ip)));
+    if !trust_headers {
+        return peer_ip;
+    }
 
-        // Try X-Real-IP header
-        if let Some(real_ip) = req.headers().get("x-real-ip") {
-            if let Ok(ip_str) = real_ip.to_str() {
-                if let Ok(ip) = ip_str.parse::<IpAddr>() {
+    // Try to get IP from X-Forwarded-For header first (for reverse proxies)
+    if let Some(forwarded_for) = req.headers().get("x-forwarded-for") {
+        if let Ok(forwarded_str) = forwarded_for.to_str() {
+            if let Some(first_ip) = forwarded_str.split(',').next() {
+                if let Ok(ip) = first_ip.trim().parse::<IpAddr>() {
                     return ip;
                 }
             }
         }
+    }
 
-        // Fall back to connection info
-        req.connection_info()
-            .peer_addr()
-            .and_then(|addr| addr.split(':').next())
-            .and_then(|ip_str| ip_str.parse().ok())
-            .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)))
+    // Try X-Real-IP header
+    if let Some(real_ip) = req.headers().get("x-real-ip") {
+        if let Ok(ip_str) = real_ip.to_str() {
+            if let Ok(ip) = ip_str.parse::<IpAddr>() {
+                return ip;
+            }
+        }
     }
+
+    // Fall back to connection info
+    peer_ip
 }
 
 impl<S, B> Service<ServiceRequest> for RateLimitService<S>
@@ -162,14 +171,13 @@ where
         
         if let Some(route_type) = route_type {
             if let Some(limiter) = self.limiters.get(route_type) {
-                let client_ip = self.get_client_ip(&req);
+                let client_ip = client_ip(&req, None);
                 
                 // Check rate limit
                 match limiter.check_key(&client_ip) {
                     Ok(_) => {
                         // Rate limit passed, continue to service
-                        let fut = self.service.call(req);
-                        Box::pin(async move { fut.await })
+                        Box::pin(self.service.call(req))
                     }
                     Err(_) => {
                         // Rate limit exceeded
@@ -180,13 +188,11 @@ where
                 }
             } else {
                 // No rate limiter configured for this route type
-                let fut = self.service.call(req);
-                Box::pin(async move { fut.await })
+                Box::pin(self.service.call(req))
             }
         } else {
             // Route is disabled from rate limiting
-            let fut = self.service.call(req);
-            Box::pin(async move { fut.await })
+            Box::pin(self.service.call(req))
         }
     }
 }
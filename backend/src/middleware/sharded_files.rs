@@ -0,0 +1,112 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{uri::PathAndQuery, Uri},
+    Error,
+};
+use futures::future::{Ready, ready};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::services::shard_layout::{ShardLayout, StorageDir};
+
+/// Serve files stored in shard subdirectories at their flat `/uploads/...`
+/// URL, by pointing the request at the subdirectory before it reaches the
+/// file service. Files still stored flat are served as they are.
+pub struct ShardedFilesMiddleware {
+    upload_dir: Arc<PathBuf>,
+    layout: ShardLayout,
+}
+
+impl ShardedFilesMiddleware {
+    pub fn new(upload_dir: impl Into<PathBuf>, layout: ShardLayout) -> Self {
+        Self {
+            upload_dir: Arc::new(upload_dir.into()),
+            layout,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ShardedFilesMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ShardedFilesService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ShardedFilesService {
+            service,
+            upload_dir: self.upload_dir.clone(),
+            layout: self.layout,
+        }))
+    }
+}
+
+pub struct ShardedFilesService<S> {
+    service: S,
+    upload_dir: Arc<PathBuf>,
+    layout: ShardLayout,
+}
+
+impl<S> ShardedFilesService<S> {
+    /// Request path of the file a static file request targets inside its shard
+    /// subdirectory, if it is stored in one
+    fn sharded_path(&self, req: &ServiceRequest) -> Option<String> {
+        if self.layout == ShardLayout::Flat {
+            return None;
+        }
+        // Decoded the way the file service reads it
+        let relative = req.match_info().as_str().strip_prefix("/uploads/")?;
+        if !relative.split('/').all(|segment| !segment.is_empty() && !segment.starts_with('.')) {
+            return None;
+        }
+        let (directory, filename) = relative.rsplit_once('/').unwrap_or(("", relative));
+        let storage_dir = self.upload_dir.join(directory);
+        let stored = StorageDir::new(&storage_dir, self.layout).path(filename);
+        if stored == storage_dir.join(filename) || !stored.is_file() {
+            return None;
+        }
+
+        let subdir = self.layout.subdir(filename)?;
+        let subdir: Vec<&str> = subdir.iter().map(|segment| segment.to_str()).collect::<Option<_>>()?;
+        let (parent, name) = req.path().rsplit_once('/')?;
+        Some(format!("{}/{}/{}", parent, subdir.join("/"), name))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ShardedFilesService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if let Some(uri) = self.sharded_path(&req).and_then(|path| with_path(&req.head().uri, path)) {
+            req.match_info_mut().get_mut().update(&uri);
+            req.head_mut().uri = uri;
+        }
+        self.service.call(req)
+    }
+}
+
+/// `uri` with its path replaced, keeping the query
+fn with_path(uri: &Uri, path: String) -> Option<Uri> {
+    let mut parts = uri.clone().into_parts();
+    let path_and_query = match parts.path_and_query.as_ref().and_then(|pq| pq.query()) {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Uri::from_parts(parts).ok()
+}
@@ -0,0 +1,107 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    body::EitherBody,
+    web, Error, HttpMessage, HttpResponse,
+};
+use chrono::Utc;
+use futures::future::{Ready, ready, LocalBoxFuture};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::config::AuthConfig;
+use crate::services::signed_url;
+
+/// Marker in the request extensions of static file requests carrying a valid
+/// signature, which the auth middleware lets through without credentials
+#[derive(Debug, Clone, Copy)]
+pub struct SignedRequest;
+
+/// Check the `expires` and `signature` query parameters of static file links
+/// from `/api/files/{filename}/signed-url`. Requests without a signature go
+/// through unchanged; expired and forged links are rejected.
+pub struct SignedUrlMiddleware {
+    auth_config: Arc<AuthConfig>,
+}
+
+impl SignedUrlMiddleware {
+    pub fn new(auth_config: &AuthConfig) -> Self {
+        Self {
+            auth_config: Arc::new(auth_config.clone()),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SignedUrlMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = SignedUrlService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SignedUrlService {
+            service,
+            auth_config: self.auth_config.clone(),
+        }))
+    }
+}
+
+pub struct SignedUrlService<S> {
+    service: S,
+    auth_config: Arc<AuthConfig>,
+}
+
+impl<S> SignedUrlService<S> {
+    /// Whether a signature is valid for the request path and not expired
+    fn is_valid(&self, req: &ServiceRequest, query: &HashMap<String, String>, signature: &str) -> bool {
+        let Some(key) = self.auth_config.signed_url_key() else {
+            return false;
+        };
+        let Some(expires) = query.get("expires").and_then(|expires| expires.parse::<i64>().ok()) else {
+            return false;
+        };
+        expires > Utc::now().timestamp() && signed_url::verify(key, req.path(), expires, signature)
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for SignedUrlService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .map(web::Query::into_inner)
+            .unwrap_or_default();
+        if let Some(signature) = query.get("signature") {
+            if !self.is_valid(&req, &query, signature) {
+                warn!("Rejected an invalid or expired signed link to: {}", req.path());
+                return Box::pin(async move {
+                    let response = HttpResponse::Forbidden()
+                        .json(serde_json::json!({
+                            "error": "Forbidden",
+                            "message": "This link is invalid or has expired"
+                        }));
+                    Ok(req.into_response(response).map_into_right_body())
+                });
+            }
+            req.extensions_mut().insert(SignedRequest);
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
+    }
+}
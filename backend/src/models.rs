@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use utoipa::ToSchema;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -10,13 +11,52 @@ pub struct UploadResponse {
     pub metadata: FileMetadata,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UploadFailure {
+    pub filename: String,
+    pub reason: String,
+}
+
+/// Result of an upload with several files, each stored or rejected on its own
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MultiUploadResponse {
+    /// True when every file was stored
+    pub success: bool,
+    pub total: usize,
+    pub succeeded: usize,
+    pub uploaded: Vec<UploadResponse>,
+    pub failed: Vec<UploadFailure>,
+}
+
+/// A resumable upload waiting for its data
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ResumableUploadResponse {
+    /// ID to send the data to, with `PATCH /api/upload/{id}`
+    pub upload_id: String,
+    /// Bytes received so far
+    pub offset: u64,
+    /// Total size of the file, in bytes
+    pub length: u64,
+    /// The upload is discarded if not completed by then
+    pub expires_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct FileUrls {
     pub original: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub qoi: Option<String>,
+    /// Lossy AVIF copy, see `AVIF_ENABLED`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avif: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thumbnail: Option<String>,
+    /// JPEG copy of the thumbnail for clients without WebP support, see `THUMBNAIL_JPEG_FALLBACK`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_jpeg: Option<String>,
+    /// Thumbnail variants by longest side in pixels, see `THUMBNAIL_SIZE`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub thumbnails: BTreeMap<u32, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -28,11 +68,27 @@ pub struct FileMetadata {
     pub width: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub height: Option<u32>,
+    /// BLAKE3 hash of the stored content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// Filename as uploaded, before sanitization
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_name: Option<String>,
+    /// Brightness and color histogram, when image analysis is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<ImageAnalysis>,
+    /// Number of pages of multi-page documents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_count: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct FileInfo {
+    /// Stored filename, used in URLs and API paths
     pub filename: String,
+    /// Name set with the rename endpoint, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
     pub size: u64,
     pub mime_type: String,
     pub uploaded_at: DateTime<Utc>,
@@ -42,6 +98,21 @@ pub struct FileInfo {
     pub dimensions: Option<(u32, u32)>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub folder_id: Option<String>,
+    /// Path of the file's folder (e.g. "/Photos/2024"), in search and recursive listings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview_snippet: Option<String>,
+    /// Number of pages of multi-page documents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_count: Option<u32>,
+    /// BLAKE3 hash of the stored content, also the `ETag` the static server
+    /// sends for the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// The image is too large for derivatives and is served as its original
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub derivatives_skipped: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -49,13 +120,134 @@ pub struct FileListResponse {
     pub files: Vec<FileInfo>,
     pub folders: Vec<FolderInfo>,
     pub current_folder: Option<FolderInfo>,
-    pub breadcrumbs: Vec<FolderInfo>,
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// Ancestors beyond `BREADCRUMB_MAX_DEPTH` were left out of `breadcrumbs`
+    pub breadcrumbs_truncated: bool,
     pub total: usize,
     pub page: usize,
     pub per_page: usize,
     pub total_pages: usize,
 }
 
+/// Listing data of a stored file, kept in its metadata so listings don't
+/// stat every file or decode every image
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ListingCache {
+    /// File holding the content: the original if kept, otherwise its best derivative
+    pub stored_filename: String,
+    pub size: u64,
+    /// Modification time of the stored file, to notice changes made outside the API
+    pub modified: DateTime<Utc>,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<(u32, u32)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qoi_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avif_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_jpeg_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub thumbnail_variants: BTreeMap<u32, String>,
+}
+
+/// Deep-zoom pyramid of an image, in the Deep Zoom (DZI) layout: the top level
+/// is the full image and each level below halves it, down to a single pixel
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TileInfo {
+    pub filename: String,
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: u32,
+    /// Pixels tiles share with their neighbours
+    pub overlap: u32,
+    pub format: String,
+    /// Levels from the smallest (0) to the full image
+    pub levels: Vec<TileLevel>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TileLevel {
+    pub level: u32,
+    pub width: u32,
+    pub height: u32,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+/// Basic color statistics of an image
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ImageAnalysis {
+    /// Average luma, from 0.0 (black) to 1.0 (white)
+    pub brightness: f32,
+    /// Whether the color channels are nearly identical across the image
+    pub is_grayscale: bool,
+    /// Coarse per-channel histograms: fraction of pixels in each of 8 equal bins
+    pub histogram: ColorHistogram,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ColorHistogram {
+    pub red: Vec<f32>,
+    pub green: Vec<f32>,
+    pub blue: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImageAnalysisResponse {
+    pub filename: String,
+    pub analysis: ImageAnalysis,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchInfoResponse {
+    /// File info by requested filename, `null` for files that don't exist
+    #[schema(value_type = HashMap<String, FileInfo>)]
+    pub files: HashMap<String, Option<FileInfo>>,
+}
+
+/// Outcome of deleting one file of a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteStatus {
+    Deleted,
+    NotFound,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchDeleteResult {
+    /// Filename as requested
+    pub filename: String,
+    /// Stored name of the file that was deleted, when matched by stem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_filename: Option<String>,
+    pub status: DeleteStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchDeleteResponse {
+    /// Whether every requested file was deleted
+    pub success: bool,
+    pub deleted: usize,
+    /// One result per requested filename, in request order
+    pub results: Vec<BatchDeleteResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OrganizeResponse {
+    pub success: bool,
+    /// Whether the files were only matched, not moved
+    pub dry_run: bool,
+    pub count: usize,
+    /// Files moved (or that would be moved) to the target folder
+    pub files: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ErrorResponse {
     pub error: String,
@@ -66,9 +258,174 @@ pub struct ErrorResponse {
 pub struct HealthResponse {
     pub status: String,
     pub version: String,
+    /// Seconds since the server started
     pub uptime: u64,
     pub upload_dir: String,
     pub auth_mode: String,
+    pub metadata: MetadataHealth,
+    /// Files recorded in the metadata, per-user storage included
+    pub total_files: usize,
+    /// Bytes of those files and their derivatives, as recorded in the metadata
+    pub total_size_bytes: u64,
+    /// Disk space available on the upload filesystem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_bytes: Option<u64>,
+    /// Uploads are rejected once they would take free space below this
+    pub min_free_bytes: u64,
+}
+
+/// An application log line, as sent by the admin log stream
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LogLine {
+    pub timestamp: DateTime<Utc>,
+    pub level: String,
+    pub target: String,
+    /// Message and fields, with credentials redacted
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MetadataHealth {
+    pub folder_metadata: MetadataFileStatus,
+    pub file_metadata: MetadataFileStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MetadataFileStatus {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl MetadataFileStatus {
+    pub fn from_result<T>(result: Result<T, crate::error::AppError>) -> Self {
+        match result {
+            Ok(_) => Self { valid: true, error: None },
+            Err(e) => Self { valid: false, error: Some(e.to_string()) },
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct DerivativeFormatStats {
+    /// Number of originals with this derivative
+    pub count: usize,
+    /// Total size of the originals that have this derivative
+    pub original_bytes: u64,
+    /// Total size of the derivative files
+    pub derivative_bytes: u64,
+    /// Average derivative/original size ratio per file
+    pub average_ratio: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DerivativeStatsResponse {
+    pub total_files: usize,
+    pub total_original_bytes: u64,
+    pub total_qoi_bytes: u64,
+    pub total_thumbnail_bytes: u64,
+    pub qoi: DerivativeFormatStats,
+    pub thumbnail: DerivativeFormatStats,
+}
+
+/// A file physically present in the upload directory
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RawFileEntry {
+    pub filename: String,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+    /// Whether the metadata has an entry for this file
+    pub tracked: bool,
+}
+
+/// Temporary link to a file that works without credentials
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SignedUrlResponse {
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RawListingResponse {
+    pub files: Vec<RawFileEntry>,
+    pub total: usize,
+    /// Metadata entries with no file left on disk
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DuplicateFile {
+    pub filename: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder_id: Option<String>,
+    pub folder_path: String,
+    pub uploaded_at: DateTime<Utc>,
+    /// Stored as a link to an earlier file of the group, deleting it frees no space
+    pub shared: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    /// Size of a single copy
+    pub size: u64,
+    /// Bytes that would be freed by keeping only one copy, not counting
+    /// files already sharing their content through a link
+    pub wasted_bytes: u64,
+    pub files: Vec<DuplicateFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DuplicatesResponse {
+    pub groups: Vec<DuplicateGroup>,
+    pub total_wasted_bytes: u64,
+    /// Files left out of the groups for lack of a content hash, see `POST /api/files/duplicates/backfill`
+    pub unhashed_files: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HashBackfillResponse {
+    /// Files that got a content hash
+    pub hashed: usize,
+    /// Files still without one, because they couldn't be read
+    pub failed: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConfirmationRequiredResponse {
+    /// Operation awaiting confirmation
+    pub operation: String,
+    /// Token to pass back as `confirmation_token` to proceed
+    pub confirmation_token: String,
+    /// Token lifetime in seconds
+    pub expires_in: i64,
+    /// Number of files that will be deleted
+    pub files_affected: usize,
+    /// Number of folders that will be deleted
+    pub folders_affected: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportFailure {
+    /// Path of the entry inside the ZIP
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportResponse {
+    /// True when every entry was imported
+    pub success: bool,
+    pub message: String,
+    pub folders_created: usize,
+    pub files_imported: usize,
+    /// Archive entries left out by `IMPORT_IGNORE`, a skipped folder counting once
+    pub files_skipped: usize,
+    /// Paths of the files imported successfully
+    pub imported: Vec<String>,
+    /// Stored filename of each imported path
+    pub stored_filenames: HashMap<String, String>,
+    pub failed: Vec<ImportFailure>,
 }
 
 // Auth-related schemas
@@ -126,6 +483,82 @@ pub struct FolderInfo {
     pub file_count: usize,
     pub folder_count: usize,
     pub size: u64,
+    /// Overrides set on this folder itself
+    #[serde(default, skip_serializing_if = "FolderImageSettings::is_empty")]
+    pub image_settings: FolderImageSettings,
+    /// Quota of the folder and its subfolders, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+}
+
+/// Image processing overrides of a folder. Unset fields inherit from the
+/// parent folder, then from the global configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FolderImageSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qoi_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_size: Option<u32>,
+    /// WebP quality of thumbnails (0-100), replacing the adaptive quality range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_quality: Option<f32>,
+}
+
+impl FolderImageSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fill the unset fields from the settings of an ancestor
+    pub fn inherit(self, ancestor: &Self) -> Self {
+        Self {
+            qoi_enabled: self.qoi_enabled.or(ancestor.qoi_enabled),
+            thumbnail_size: self.thumbnail_size.or(ancestor.thumbnail_size),
+            thumbnail_quality: self.thumbnail_quality.or(ancestor.thumbnail_quality),
+        }
+    }
+
+    /// The global image configuration with these overrides applied
+    pub fn apply(&self, config: &crate::config::ImageConfig) -> crate::config::ImageConfig {
+        let mut config = config.clone();
+        if let Some(qoi_enabled) = self.qoi_enabled {
+            config.qoi_enabled = qoi_enabled;
+        }
+        if let Some(thumbnail_size) = self.thumbnail_size {
+            config.thumbnail_size = thumbnail_size;
+        }
+        if let Some(quality) = self.thumbnail_quality {
+            config.webp_quality = quality;
+            config.webp_quality_range = None;
+        }
+        config
+    }
+
+    pub fn validate(&self, config: &crate::config::ImageConfig) -> Result<(), crate::error::AppError> {
+        if self.thumbnail_size.is_some_and(|size| size == 0 || size > config.max_thumbnail_size) {
+            return Err(crate::error::AppError::BadRequest(format!(
+                "thumbnail_size must be between 1 and {}", config.max_thumbnail_size
+            )));
+        }
+        if self.thumbnail_quality.is_some_and(|quality| !(0.0..=100.0).contains(&quality)) {
+            return Err(crate::error::AppError::BadRequest("thumbnail_quality must be between 0 and 100".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// An ancestor of the listed folder, root first. Stats are only set when requested.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Breadcrumb {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -133,6 +566,23 @@ pub struct CreateFolderRequest {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub parent_id: Option<String>,
+    #[serde(default, skip_serializing_if = "FolderImageSettings::is_empty")]
+    pub image_settings: FolderImageSettings,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpdateFolderRequest {
+    /// Replaces the folder's overrides; omitted fields inherit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_settings: Option<FolderImageSettings>,
+    /// Quota of the folder and its subfolders in bytes, uploads and derivatives included; 0 removes it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RenameFolderRequest {
+    pub name: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -141,9 +591,56 @@ pub struct MoveFolderRequest {
     pub parent_id: Option<String>,
 }
 
+/// A folder of a moved subtree, with its path after the move
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MovedFolder {
+    pub id: String,
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MoveFolderResponse {
+    pub success: bool,
+    pub message: String,
+    /// The moved folder and its descendants, parents first
+    pub folders: Vec<MovedFolder>,
+}
+
+/// A folder a recursive delete would remove, with its path from the deleted folder
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeletePreviewFolder {
+    pub id: String,
+    pub name: String,
+    pub path: String,
+}
+
+/// A file a recursive delete would remove
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeletePreviewFile {
+    pub filename: String,
+    pub folder_id: String,
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeletePreviewResponse {
+    pub folder_id: String,
+    /// The folder itself and its descendants, parents first
+    pub folders: Vec<DeletePreviewFolder>,
+    pub files: Vec<DeletePreviewFile>,
+    pub folder_count: usize,
+    pub file_count: usize,
+    /// Bytes that would be freed, derivatives included
+    pub total_bytes: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct FolderListResponse {
     pub folders: Vec<FolderInfo>,
     pub current_folder: Option<FolderInfo>,
-    pub breadcrumbs: Vec<FolderInfo>,
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// Ancestors beyond `BREADCRUMB_MAX_DEPTH` were left out of `breadcrumbs`
+    pub breadcrumbs_truncated: bool,
 }
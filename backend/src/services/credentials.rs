@@ -0,0 +1,50 @@
+use constant_time_eq::constant_time_eq;
+use std::sync::OnceLock;
+
+use crate::config::AuthConfig;
+use crate::error::AppError;
+
+/// Check a username/password pair against the configured accounts.
+/// The `USERS` list (or htpasswd file) takes over from the single admin pair when set.
+pub fn verify_credentials(config: &AuthConfig, username: &str, password: &str) -> bool {
+    if config.users.is_empty() {
+        // Use constant time comparison to prevent timing attacks
+        let username_valid = constant_time_eq(config.admin_username.as_bytes(), username.as_bytes());
+        let password_valid = match &config.admin_password_hash {
+            Some(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            // Deprecated plaintext password
+            None => constant_time_eq(config.admin_password.as_bytes(), password.as_bytes()),
+        };
+        return username_valid && password_valid;
+    }
+
+    // Compare against every username so the lookup time doesn't depend on the match
+    let mut matched = None;
+    for user in &config.users {
+        if constant_time_eq(user.username.as_bytes(), username.as_bytes()) {
+            matched = Some(user.password_hash.as_str());
+        }
+    }
+
+    match matched {
+        Some(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+        None => {
+            // Hash anyway so unknown usernames take as long as wrong passwords
+            let _ = bcrypt::verify(password, dummy_hash());
+            false
+        }
+    }
+}
+
+/// Hash a password for `ADMIN_PASSWORD_HASH` or `USERS`
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST)
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {}", e)))
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        bcrypt::hash("snapfilething-dummy-password", bcrypt::DEFAULT_COST).unwrap_or_default()
+    })
+}
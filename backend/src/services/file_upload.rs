@@ -1,47 +1,597 @@
-use crate::config::AppConfig;
+use crate::config::{AppConfig, ImageConfig};
 use crate::error::AppError;
-use crate::services::file_utils::FileManager;
-use crate::services::folder_manager::FolderManager;
+use crate::models::ImageAnalysis;
+use crate::services::file_utils::{avif_name, thumbnail_name, thumbnail_variant_name, FileManager};
+use crate::services::folder_manager::{split_folder_path, FolderManager};
 use crate::services::image_processor::ImageProcessor;
-use crate::utils::validation::{validate_file_type, validate_file_size, sanitize_filename};
+use crate::services::media_thumbnail;
+use crate::utils::validation::{validate_file_type, validate_file_size, sanitize_filename, MAX_MAGIC_BYTES};
+use crate::utils::mime_type::{get_mime_type, is_compressible_mime_type};
 use chrono::{DateTime, Utc};
-use std::path::Path;
+use image::ImageFormat;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use tempfile::{NamedTempFile, TempPath};
+
+/// Directory of the upload dir where incoming files are written before being moved into place
+pub const STAGING_DIR: &str = ".staging";
+
+/// Where and how an uploaded file is stored
+#[derive(Debug, Default)]
+pub struct UploadOptions {
+    pub folder_id: Option<String>,
+    /// Name to store the file under instead of a generated one, used when
+    /// restoring a backup. Ignored if taken or if its extension doesn't match.
+    pub stored_filename: Option<String>,
+    /// Original upload date, e.g. from a migration, instead of now
+    pub uploaded_at: Option<DateTime<Utc>>,
+    /// Without a `folder_id`, store the file in the `AUTO_FOLDERS` folder of its type
+    pub auto_folder: bool,
+}
+
+/// Everything known about a file once it has been stored
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub filename: String,
+    pub original_name: String,
+    pub uploaded_at: DateTime<Utc>,
+    pub size: u64,
+    pub mime_type: String,
+    pub dimensions: Option<(u32, u32)>,
+    pub content_hash: String,
+    pub analysis: Option<ImageAnalysis>,
+    pub page_count: Option<u32>,
+}
+
+/// Create a temp file on the upload filesystem, so it can later be renamed into place
+pub fn staging_file(upload_dir: &str) -> Result<NamedTempFile, AppError> {
+    let dir = Path::new(upload_dir).join(STAGING_DIR);
+    std::fs::create_dir_all(&dir)?;
+    // Created by hand rather than by tempfile, whose files are only readable by their owner
+    let path = dir.join(format!("upload-{}", uuid::Uuid::new_v4()));
+    let file = std::fs::File::create_new(&path)?;
+    Ok(NamedTempFile::from_parts(file, TempPath::from_path(path)))
+}
 
 /// Shared logic for processing and saving an uploaded file (from upload or import)
+///
+/// The file is read from `source`, which is moved into the upload dir once
+/// validated and deleted otherwise, so large files never sit in memory.
 pub async fn process_uploaded_file(
-    file_bytes: Vec<u8>,
+    source: TempPath,
     original_filename: &str,
-    folder_id: Option<String>,
+    options: UploadOptions,
     config: &AppConfig,
     file_manager: &FileManager,
     folder_manager: &FolderManager,
     image_processor: &ImageProcessor,
-) -> Result<(String, DateTime<Utc>, u64), AppError> {
+) -> Result<UploadedFile, AppError> {
     // Validate file size
-    validate_file_size(file_bytes.len(), config.server.max_file_size)?;
+    validate_file_size(std::fs::metadata(&source)?.len() as usize, config.server.max_file_size)?;
     // Sanitize filename
-    let sanitized_filename = sanitize_filename(original_filename);
-    let unique_filename = file_manager.generate_unique_filename(&sanitized_filename);
-    let file_path = file_manager.get_file_path(&unique_filename);
-    // Write file
-    std::fs::write(&file_path, &file_bytes)?;
+    let mut sanitized_filename = sanitize_filename(original_filename, &config.filename);
     // Validate file type
-    let _mime_type = validate_file_type(&file_bytes, &unique_filename)?;
-    // Assign file to folder
-    let file_size = file_bytes.len() as u64;
-    folder_manager.assign_file_to_folder(&unique_filename, folder_id.clone(), file_size).await?;
+    let mut mime_type = validate_file_type(
+        &read_prefix(&source, MAX_MAGIC_BYTES)?,
+        &sanitized_filename,
+        config.server.strict_file_types,
+        &config.server.trusted_extensions,
+    )?;
+    // Strip metadata before any re-encoding, which would lose the orientation
+    if config.image.strip_metadata && ImageProcessor::is_image_file(&sanitized_filename) {
+        if let Ok(format) = ImageFormat::from_path(&sanitized_filename) {
+            image_processor.strip_metadata(&source, format).await?;
+        }
+    }
+    // Re-encode images to the canonical format if configured
+    if ImageProcessor::is_image_file(&sanitized_filename) && config.image.canonical_image_format.is_some() {
+        match image_processor.encode_canonical(&std::fs::read(&source)?).await {
+            Ok(Some((encoded, extension))) => {
+                let stem = Path::new(&sanitized_filename).file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+                sanitized_filename = format!("{}.{}", stem, extension);
+                mime_type = get_mime_type(&sanitized_filename);
+                std::fs::write(&source, &encoded)?;
+            }
+            Ok(None) => {}
+            // Keep images the decoder can't handle as uploaded
+            Err(e) => warn!("Failed to re-encode {} to the canonical format, keeping the original: {}", sanitized_filename, e),
+        }
+    }
+    let file_size = std::fs::metadata(&source)?.len();
+    let content_hash = FileManager::hash_path(&source)?;
+    let unique_filename = match options.stored_filename {
+        Some(name) if is_reusable_filename(&name, &sanitized_filename, file_manager) => name,
+        _ => file_manager.generate_unique_filename(&sanitized_filename),
+    };
+    let file_path = file_manager.writable_file_path(&unique_filename)?;
+    let duplicate = if config.server.deduplicate_uploads {
+        find_duplicate(&content_hash, file_size, file_manager, folder_manager)?
+    } else {
+        None
+    };
+    // Move the file into place; staged files already take their space on the upload filesystem
+    let staging_dir = Path::new(&config.server.upload_dir).join(STAGING_DIR);
+    let incoming = if duplicate.is_some() || source.starts_with(&staging_dir) { 0 } else { file_size };
+    check_free_space(&config.server.upload_dir, incoming, config.server.min_free_bytes)?;
+    // Assign file to folder before moving it into place, which reserves its
+    // size against the quotas
+    let uploaded_at = options.uploaded_at.unwrap_or_else(Utc::now);
+    let folder_id = match options.folder_id {
+        None if options.auto_folder => auto_folder(&mime_type, config, folder_manager).await?,
+        folder_id => folder_id,
+    };
+    folder_manager.assign_file_to_folder(&unique_filename, folder_id.clone(), file_size, Some(uploaded_at)).await?;
+    let placed = match duplicate {
+        Some(ref existing) => link_into_place(existing, source, &file_path),
+        None => move_into_place(source, &file_path),
+    };
+    if let Err(e) = placed {
+        if let Err(cleanup) = folder_manager.remove_file_metadata(&unique_filename).await {
+            warn!("Failed to remove the metadata of {} after a failed upload: {}", unique_filename, cleanup);
+        }
+        return Err(e);
+    }
+    // Text preview
+    let preview_snippet = if config.image.text_preview_enabled && mime_type.starts_with("text/") {
+        extract_text_snippet(&read_prefix(&file_path, config.image.text_preview_max_bytes)?)
+    } else {
+        None
+    };
+    // Gzip companion for the static server
+    if config.server.gzip_precompression && is_compressible_mime_type(&mime_type) {
+        if let Err(e) = write_gzip_companion(&file_path) {
+            warn!("Failed to precompress {}: {}", unique_filename, e);
+        }
+    }
     // Image processing
+    let mut derivatives = ImageDerivatives::default();
+    let mut dimensions = None;
     if ImageProcessor::is_image_file(&unique_filename) {
-        let stem = Path::new(&unique_filename).file_stem().and_then(|s| s.to_str()).unwrap_or("file");
-        if config.image.qoi_enabled {
-            let qoi_filename = format!("{}.qoi", stem);
-            let qoi_path = file_manager.get_file_path(&qoi_filename);
-            let _ = image_processor.convert_to_qoi(&file_path, &qoi_path).await;
-        }
-        let thumb_filename = format!("{}_thumb.webp", stem);
-        let thumb_path = file_manager.get_file_path(&thumb_filename);
-        let _ = image_processor.generate_thumbnail(&file_path, &thumb_path).await;
-    }
-    let uploaded_at = Utc::now();
-    Ok((unique_filename, uploaded_at, file_size))
+        dimensions = image_processor.get_dimensions(&file_path).await.ok();
+        let image_config = folder_manager.effective_image_settings(folder_id.as_deref())?.apply(&config.image);
+        derivatives = generate_image_derivatives(&unique_filename, &image_config, file_manager).await;
+        // Discard the original once a derivative can stand in for it, except for
+        // small images that serve as their own thumbnail and huge ones without a QOI copy
+        let has_derivative = derivatives.thumbnail_bytes.is_some() || derivatives.qoi_bytes.is_some();
+        if !config.image.keep_original && !derivatives.is_own_thumbnail && !derivatives.derivatives_skipped && has_derivative {
+            std::fs::remove_file(&file_path)?;
+        }
+    } else if media_thumbnail::is_supported(&mime_type) {
+        let image_config = folder_manager.effective_image_settings(folder_id.as_deref())?.apply(&config.image);
+        derivatives = generate_media_thumbnail(&unique_filename, &mime_type, &image_config, file_manager).await;
+    }
+    let ImageDerivatives { qoi_bytes, avif_bytes, thumbnail_bytes, thumbnail_variant_bytes, analysis, page_count, derivatives_skipped, .. } = derivatives;
+    // Record derived data in metadata, with the listing data so listings don't read the file again
+    let original_name = original_filename.to_string();
+    let listing = file_manager.listing_cache(&unique_filename).ok();
+    {
+        let content_hash = content_hash.clone();
+        let original_name = original_name.clone();
+        let analysis = analysis.clone();
+        folder_manager.update_file_metadata(&unique_filename, move |meta| {
+            meta.preview_snippet = preview_snippet;
+            meta.qoi_bytes = qoi_bytes;
+            meta.avif_bytes = avif_bytes;
+            meta.thumbnail_bytes = thumbnail_bytes;
+            meta.thumbnail_variant_bytes = thumbnail_variant_bytes;
+            meta.content_hash = Some(content_hash);
+            meta.analysis = analysis;
+            meta.original_name = Some(original_name);
+            meta.page_count = page_count;
+            meta.derivatives_skipped = derivatives_skipped;
+            meta.listing = listing;
+        }).await?;
+    }
+    Ok(UploadedFile {
+        filename: unique_filename,
+        original_name,
+        uploaded_at,
+        size: file_size,
+        mime_type,
+        dimensions,
+        content_hash,
+        analysis,
+        page_count,
+    })
+}
+
+/// Folder configured in `AUTO_FOLDERS` for a MIME type, created if missing.
+/// The full type (`application/pdf`) takes precedence over its category (`application`).
+async fn auto_folder(mime_type: &str, config: &AppConfig, folder_manager: &FolderManager) -> Result<Option<String>, AppError> {
+    let auto_folders = &config.server.auto_folders;
+    let category = mime_type.split('/').next().unwrap_or_default();
+    match auto_folders.get(mime_type).or_else(|| auto_folders.get(category)) {
+        Some(path) => folder_manager.ensure_folder_path(split_folder_path(path)?).await,
+        None => Ok(None),
+    }
+}
+
+/// Files and data derived from a stored image
+#[derive(Debug, Default)]
+pub struct ImageDerivatives {
+    pub qoi_bytes: Option<u64>,
+    pub avif_bytes: Option<u64>,
+    pub thumbnail_bytes: Option<u64>,
+    /// Total size of the thumbnail variants
+    pub thumbnail_variant_bytes: Option<u64>,
+    pub analysis: Option<ImageAnalysis>,
+    /// Number of pages, only set for multi-page documents
+    pub page_count: Option<u32>,
+    /// The image is too small for a thumbnail and serves as its own
+    pub is_own_thumbnail: bool,
+    /// The image exceeds `max_derivative_source_pixels`
+    pub derivatives_skipped: bool,
+}
+
+/// Generate (or regenerate) the QOI copy, thumbnail and analysis of a stored image
+///
+/// `image_config` is the configuration of the image's folder, see
+/// `FolderManager::effective_image_settings`.
+pub async fn generate_image_derivatives(
+    filename: &str,
+    image_config: &ImageConfig,
+    file_manager: &FileManager,
+) -> ImageDerivatives {
+    let image_processor = ImageProcessor::new(image_config.clone());
+    let mut derivatives = ImageDerivatives::default();
+    let file_path = file_manager.get_file_path(filename);
+    let stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+    let qoi_path = derivative_path(file_manager, &format!("{}.qoi", stem));
+    let avif_path = derivative_path(file_manager, &avif_name(filename));
+    let thumb_path = derivative_path(file_manager, &thumbnail_name(filename, &image_config.thumbnail_format));
+    let jpeg_thumb_path = derivative_path(file_manager, &thumbnail_name(filename, "jpeg"));
+    // A JPEG thumbnail is its own fallback
+    let jpeg_fallback = image_config.thumbnail_jpeg_fallback && image_config.thumbnail_format != "jpeg";
+
+    let variant_paths: Vec<_> = image_config.thumbnail_variant_sizes.iter()
+        .map(|&size| (size, derivative_path(file_manager, &thumbnail_variant_name(filename, size, &image_config.thumbnail_format))))
+        .collect();
+
+    // A previous version of the image may have had a thumbnail in another format,
+    // and variants are only written for sizes the new version is large enough for
+    for format in ["webp", "png"] {
+        if format != image_config.thumbnail_format {
+            let _ = std::fs::remove_file(file_manager.get_file_path(&thumbnail_name(filename, format)));
+        }
+    }
+    for variant_filename in file_manager.thumbnail_variants(filename).into_values() {
+        let _ = std::fs::remove_file(file_manager.get_file_path(&variant_filename));
+    }
+
+    // Huge sources are decoded as little as possible; the dimensions only need the header
+    if let Some(max_pixels) = image_config.max_derivative_source_pixels {
+        derivatives.derivatives_skipped = image_processor.get_dimensions(&file_path).await
+            .is_ok_and(|(width, height)| width as u64 * height as u64 > max_pixels);
+    }
+    if derivatives.derivatives_skipped {
+        info!("Skipping derivatives of large image {}", filename);
+        // A previous version of the image may have had them
+        let _ = std::fs::remove_file(&qoi_path);
+        if !image_config.thumbnail_large_sources {
+            let _ = std::fs::remove_file(&thumb_path);
+            let _ = std::fs::remove_file(&jpeg_thumb_path);
+        }
+    }
+
+    if image_config.qoi_enabled && !derivatives.derivatives_skipped
+        && image_processor.convert_to_qoi(&file_path, &qoi_path).await.is_ok()
+    {
+        derivatives.qoi_bytes = std::fs::metadata(&qoi_path).ok().map(|m| m.len());
+    }
+
+    // A previous version of the image, or of the settings, may have had one
+    let _ = std::fs::remove_file(&avif_path);
+    if image_config.avif_enabled && !derivatives.derivatives_skipped {
+        match image_processor.convert_to_avif(&file_path, &avif_path).await {
+            Ok(()) => derivatives.avif_bytes = std::fs::metadata(&avif_path).ok().map(|m| m.len()),
+            Err(e) => warn!("Failed to convert {} to AVIF: {}", filename, e),
+        }
+    }
+
+    // A previous version of the image, or of the folder settings, may have had one
+    if !jpeg_fallback && image_config.thumbnail_format != "jpeg" {
+        let _ = std::fs::remove_file(&jpeg_thumb_path);
+    }
+    if !derivatives.derivatives_skipped || image_config.thumbnail_large_sources {
+        let jpeg_output = jpeg_fallback.then_some(jpeg_thumb_path.as_path());
+        match image_processor.generate_thumbnail(&file_path, &thumb_path, jpeg_output, &variant_paths).await {
+            Ok(true) => {
+                derivatives.thumbnail_bytes = std::fs::metadata(&thumb_path).ok().map(|m| m.len());
+                derivatives.thumbnail_variant_bytes = variant_paths.iter()
+                    .filter_map(|(_, path)| std::fs::metadata(path).ok().map(|m| m.len()))
+                    .reduce(|total, bytes| total + bytes);
+            }
+            Ok(false) => {
+                derivatives.is_own_thumbnail = true;
+                // A previous version of the image may have had one
+                let _ = std::fs::remove_file(&thumb_path);
+                let _ = std::fs::remove_file(&jpeg_thumb_path);
+            }
+            Err(_) => {}
+        }
+    }
+
+    // Analyze the small thumbnail rather than decoding the original again
+    if image_config.analysis_enabled {
+        match (derivatives.thumbnail_bytes.is_some(), derivatives.derivatives_skipped) {
+            (true, _) => derivatives.analysis = image_processor.analyze(&thumb_path).await.ok(),
+            (false, false) => derivatives.analysis = image_processor.analyze(&file_path).await.ok(),
+            (false, true) => {}
+        }
+    }
+
+    if image_config.tiff_page_count_enabled && ImageProcessor::is_tiff_file(filename) {
+        derivatives.page_count = image_processor.tiff_page_count(&file_path).await
+            .ok()
+            .filter(|&pages| pages > 1);
+    }
+
+    derivatives
+}
+
+/// Generate the thumbnail of a PDF or video from a rendered frame. Failures,
+/// such as the renderer not being installed, leave the file without one.
+async fn generate_media_thumbnail(
+    filename: &str,
+    mime_type: &str,
+    image_config: &ImageConfig,
+    file_manager: &FileManager,
+) -> ImageDerivatives {
+    let mut derivatives = ImageDerivatives::default();
+    let size = image_config.thumbnail_variant_sizes.iter().copied()
+        .fold(image_config.thumbnail_size, u32::max);
+    let frame_dir = match tempfile::tempdir() {
+        Ok(frame_dir) => frame_dir,
+        Err(e) => {
+            warn!("Failed to create a directory to render {}: {}", filename, e);
+            return derivatives;
+        }
+    };
+    let frame_path = match media_thumbnail::render_frame(&file_manager.get_file_path(filename), mime_type, size, frame_dir.path()).await {
+        Ok(frame_path) => frame_path,
+        Err(e) => {
+            warn!("No thumbnail for {}: {}", filename, e);
+            return derivatives;
+        }
+    };
+
+    // The frame is already scaled down, and the file has no other image to stand in
+    let mut image_config = image_config.clone();
+    image_config.thumbnail_min_source_dimension = 1;
+    let thumb_path = derivative_path(file_manager, &thumbnail_name(filename, &image_config.thumbnail_format));
+    let jpeg_thumb_path = derivative_path(file_manager, &thumbnail_name(filename, "jpeg"));
+    let jpeg_output = (image_config.thumbnail_jpeg_fallback && image_config.thumbnail_format != "jpeg")
+        .then_some(jpeg_thumb_path.as_path());
+    let variant_paths: Vec<_> = image_config.thumbnail_variant_sizes.iter()
+        .map(|&size| (size, derivative_path(file_manager, &thumbnail_variant_name(filename, size, &image_config.thumbnail_format))))
+        .collect();
+
+    match ImageProcessor::new(image_config).generate_thumbnail(&frame_path, &thumb_path, jpeg_output, &variant_paths).await {
+        Ok(_) => {
+            derivatives.thumbnail_bytes = std::fs::metadata(&thumb_path).ok().map(|m| m.len());
+            derivatives.thumbnail_variant_bytes = variant_paths.iter()
+                .filter_map(|(_, path)| std::fs::metadata(path).ok().map(|m| m.len()))
+                .reduce(|total, bytes| total + bytes);
+        }
+        Err(e) => warn!("Failed to generate the thumbnail of {}: {}", filename, e),
+    }
+    derivatives
+}
+
+/// Reject a write that would leave less than `min_free_bytes` available on the upload filesystem
+pub fn check_free_space(upload_dir: &str, incoming: u64, min_free_bytes: u64) -> Result<(), AppError> {
+    let available = fs2::available_space(upload_dir)?;
+    if available < incoming.saturating_add(min_free_bytes) {
+        return Err(AppError::InsufficientStorage(format!(
+            "{} bytes free, {} needed to store the file and keep {} free",
+            available,
+            incoming.saturating_add(min_free_bytes),
+            min_free_bytes,
+        )));
+    }
+    Ok(())
+}
+
+/// Write `<file>.gz` next to a stored file, unless compression doesn't make it smaller
+fn write_gzip_companion(file_path: &Path) -> std::io::Result<()> {
+    let mut companion_path = file_path.as_os_str().to_owned();
+    companion_path.push(".gz");
+    let companion_path = PathBuf::from(companion_path);
+
+    let mut encoder = GzEncoder::new(std::fs::File::create(&companion_path)?, Compression::best());
+    std::io::copy(&mut std::fs::File::open(file_path)?, &mut encoder)?;
+    let compressed = encoder.finish()?.metadata()?.len();
+
+    if compressed >= std::fs::metadata(file_path)?.len() {
+        std::fs::remove_file(&companion_path)?;
+    }
+    Ok(())
+}
+
+/// Path to write a derivative to. Failing to create its shard subdirectory
+/// is reported by the write itself.
+fn derivative_path(file_manager: &FileManager, filename: &str) -> PathBuf {
+    file_manager.writable_file_path(filename)
+        .unwrap_or_else(|_| file_manager.get_file_path(filename))
+}
+
+/// Move a staged file to its final path, copying it when it lives on another filesystem
+fn move_into_place(source: TempPath, destination: &Path) -> Result<(), AppError> {
+    if let Err(e) = source.persist(destination) {
+        // The source is removed once the returned path is dropped
+        std::fs::copy(&e.path, destination)?;
+    }
+    Ok(())
+}
+
+/// A stored original with this content, to share its copy on disk
+fn find_duplicate(
+    content_hash: &str,
+    size: u64,
+    file_manager: &FileManager,
+    folder_manager: &FolderManager,
+) -> Result<Option<PathBuf>, AppError> {
+    for meta in folder_manager.load_file_metadata()?.into_values() {
+        if meta.content_hash.as_deref() != Some(content_hash) || meta.size != size {
+            continue;
+        }
+        // The file may have been replaced outside the API since it was hashed
+        let path = file_manager.get_file_path(&meta.filename);
+        if path.is_file() && FileManager::hash_path(&path).is_ok_and(|hash| hash == content_hash) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Hard-link an identical stored file to `destination`, so both names share one
+/// copy that stays on disk until both are deleted. Stores the upload itself
+/// where links aren't supported.
+fn link_into_place(existing: &Path, source: TempPath, destination: &Path) -> Result<(), AppError> {
+    match std::fs::hard_link(existing, destination) {
+        // The source is removed once dropped
+        Ok(()) => {
+            info!("Stored {:?} as a link to identical {:?}", destination, existing);
+            Ok(())
+        }
+        Err(e) => {
+            warn!("Failed to link {:?} to identical {:?}, storing a copy: {}", destination, existing, e);
+            move_into_place(source, destination)
+        }
+    }
+}
+
+/// Read at most `max_bytes` from the start of a file
+fn read_prefix(path: &Path, max_bytes: usize) -> Result<Vec<u8>, AppError> {
+    let mut prefix = Vec::new();
+    std::fs::File::open(path)?.take(max_bytes as u64).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+/// Whether a requested stored filename is a plain, free name with the extension of the upload
+fn is_reusable_filename(name: &str, sanitized_filename: &str, file_manager: &FileManager) -> bool {
+    let extension = |filename: &str| {
+        Path::new(filename).extension().map(|ext| ext.to_string_lossy().to_lowercase())
+    };
+
+    Path::new(name).file_name().is_some_and(|file_name| file_name == name)
+        && !name.starts_with('.')
+        && extension(name) == extension(sanitized_filename)
+        && file_manager.stored_filename(name).is_none()
+}
+
+/// Extract the leading valid UTF-8 text of a file prefix
+fn extract_text_snippet(prefix: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(prefix) {
+        Ok(text) => text,
+        // The cap may split a multi-byte character; keep the valid part
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&prefix[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    use crate::handlers::auth::JwtService;
+    use crate::handlers::upload::upload_file;
+
+    const BOUNDARY: &str = "snapfilething-test-boundary";
+
+    /// A multipart body holding one text file
+    fn text_upload(filename: &str, content: &str) -> Vec<u8> {
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: text/plain\r\n\r\n{content}\r\n--{BOUNDARY}--\r\n"
+        ).into_bytes()
+    }
+
+    async fn upload(config: AppConfig, body: Vec<u8>) -> StatusCode {
+        let jwt_service = JwtService::new(&config.auth).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(jwt_service))
+                .service(upload_file)
+        ).await;
+        let req = test::TestRequest::post()
+            .uri("/upload")
+            .insert_header(("Content-Type", format!("multipart/form-data; boundary={BOUNDARY}")))
+            .set_payload(body)
+            .to_request();
+        test::call_service(&app, req).await.status()
+    }
+
+    fn config_for(dir: &Path) -> AppConfig {
+        let mut config = AppConfig::default();
+        config.server.upload_dir = dir.to_string_lossy().into_owned();
+        config
+    }
+
+    #[actix_web::test]
+    async fn upload_is_rejected_below_free_space_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let upload_dir = dir.path().to_string_lossy();
+        assert!(check_free_space(&upload_dir, 5, 0).is_ok());
+        assert!(matches!(
+            check_free_space(&upload_dir, 5, u64::MAX / 2),
+            Err(AppError::InsufficientStorage(_))
+        ));
+
+        let mut config = config_for(dir.path());
+        config.server.min_free_bytes = u64::MAX / 2;
+        assert_eq!(upload(config, text_upload("notes.txt", "hello")).await, StatusCode::INSUFFICIENT_STORAGE);
+        assert_nothing_stored(dir.path());
+    }
+
+    /// Neither staged data, stored files nor metadata entries are left behind
+    fn assert_nothing_stored(upload_dir: &Path) {
+        let staging = upload_dir.join(STAGING_DIR);
+        assert!(!staging.exists() || std::fs::read_dir(&staging).unwrap().next().is_none());
+        let stored: Vec<_> = std::fs::read_dir(upload_dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        assert!(stored.is_empty(), "{:?}", stored);
+        assert!(FolderManager::new(upload_dir).load_file_metadata().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn rejected_upload_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = upload(config_for(dir.path()), text_upload("photo.png", "not a png")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_nothing_stored(dir.path());
+    }
+
+    #[actix_web::test]
+    async fn oversized_upload_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        config.server.max_file_size = 4;
+        let status = upload(config, text_upload("notes.txt", "longer than four bytes")).await;
+        assert!(status.is_client_error(), "{}", status);
+        assert_nothing_stored(dir.path());
+    }
+
+    #[actix_web::test]
+    async fn aborted_upload_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        // The client goes away mid-file, before the closing boundary
+        let mut body = text_upload("notes.txt", &"x".repeat(64 * 1024));
+        body.truncate(body.len() / 2);
+        let status = upload(config_for(dir.path()), body).await;
+        assert!(!status.is_success(), "{}", status);
+        assert_nothing_stored(dir.path());
+    }
 }
@@ -1,25 +1,86 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use std::fs;
 use chrono::{DateTime, Utc};
 use crate::error::AppError;
-use crate::models::{FileInfo, FileUrls};
-use crate::services::image_processor::ImageProcessor;
-use crate::utils::mime_type::get_mime_type;
+use crate::models::{FileInfo, FileUrls, ListingCache, RawFileEntry};
+use crate::services::folder_manager::FileMetadata as StoredFileMetadata;
+use crate::services::image_processor::{display_dimensions, ImageProcessor};
+use crate::services::shard_layout::{ShardLayout, StorageDir};
+use crate::utils::mime_type::{get_mime_type, is_compressible_mime_type};
 use tracing::{info};
 
+/// Hidden directory of the deep-zoom tiles, not served by the static file server
+const TILES_DIR: &str = ".tiles";
+
+/// Extensions a thumbnail may be written with, in lookup order. `jpg` comes last
+/// since it is also the extension of the JPEG fallback of the other formats.
+const THUMBNAIL_EXTENSIONS: [&str; 3] = ["webp", "png", "jpg"];
+
+/// Entries of a listing page, the total number of files, and the listing data rebuilt on the way
+pub type ListingPage = (Vec<FileInfo>, usize, HashMap<String, ListingCache>);
+
 pub struct FileManager {
-    upload_dir: PathBuf,
+    storage: StorageDir,
     static_base_url: String,
+    url_subdir: Option<String>,
+    thumbnail_variant_sizes: Vec<u32>,
 }
 
 impl FileManager {
     pub fn new(upload_dir: impl Into<PathBuf>, static_base_url: String) -> Self {
         Self {
-            upload_dir: upload_dir.into(),
+            storage: StorageDir::new(upload_dir, ShardLayout::Flat),
             static_base_url,
+            url_subdir: None,
+            thumbnail_variant_sizes: Vec::new(),
         }
     }
 
+    /// Serve files from a subdirectory of the static `/uploads` route
+    pub fn with_url_subdir(mut self, subdir: Option<String>) -> Self {
+        self.url_subdir = subdir;
+        self
+    }
+
+    /// Sizes of the thumbnail variants looked up for listings and deleted with their image
+    pub fn with_thumbnail_variant_sizes(mut self, sizes: Vec<u32>) -> Self {
+        self.thumbnail_variant_sizes = sizes;
+        self
+    }
+
+    /// Spread stored files over subdirectories of the upload directory
+    pub fn with_shard_layout(mut self, layout: ShardLayout) -> Self {
+        self.storage = StorageDir::new(self.storage.root().to_path_buf(), layout);
+        self
+    }
+
+    /// Public URL of a file in the upload directory
+    pub fn file_url(&self, filename: &str) -> String {
+        build_file_url(&self.static_base_url, self.url_subdir.as_deref(), filename)
+    }
+
+    /// Path of a file on the static server, the part of its URL that signed URLs cover
+    pub fn url_path(&self, filename: &str) -> String {
+        upload_url_path(self.url_subdir.as_deref(), filename)
+    }
+
+    /// Name of the file shown as the thumbnail of an image, if it has one
+    pub fn thumbnail_filename(&self, filename: &str) -> Option<String> {
+        let stored = stored_filename(&self.storage, filename)?;
+        thumbnail_filename(&self.storage, filename, &stored)
+    }
+
+    /// Name of the generated thumbnail of an image, whatever its format
+    pub fn generated_thumbnail(&self, filename: &str) -> Option<String> {
+        find_thumbnail(&self.storage, filename)
+    }
+
+    /// Names of the thumbnail variants of an image on disk, by size
+    pub fn thumbnail_variants(&self, filename: &str) -> BTreeMap<u32, String> {
+        find_thumbnail_variants(&self.storage, filename, &self.thumbnail_variant_sizes)
+    }
+
     /// Generate a unique filename to avoid conflicts
     pub fn generate_unique_filename(&self, original_filename: &str) -> String {
         let sanitized = sanitize_filename::sanitize(original_filename);
@@ -44,147 +105,169 @@ impl FileManager {
 
     /// Get the full path for a filename in the upload directory
     pub fn get_file_path(&self, filename: &str) -> PathBuf {
-        self.upload_dir.join(filename)
+        self.storage.path(filename)
+    }
+
+    /// Get the full path to write a file to, creating its shard subdirectory if needed
+    pub fn writable_file_path(&self, filename: &str) -> Result<PathBuf, AppError> {
+        let path = self.storage.path(filename);
+        if let Some(parent) = path.parent().filter(|parent| *parent != self.storage.root()) {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(path)
     }
 
-    /// List files with optional filter by filename list
+    /// Directory of the deep-zoom tiles cut from a file, one subdirectory per tile size and level
+    pub fn tiles_dir(&self, filename: &str) -> PathBuf {
+        self.storage.root().join(TILES_DIR).join(filename)
+    }
+
+    /// Remove the deep-zoom tiles of a file, if any
+    pub fn remove_tiles(&self, filename: &str) -> Result<(), AppError> {
+        let tiles_dir = self.tiles_dir(filename);
+        if tiles_dir.exists() {
+            fs::remove_dir_all(&tiles_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Name of the file holding a file's content: the original if kept, otherwise its best derivative
+    pub fn stored_filename(&self, filename: &str) -> Option<String> {
+        stored_filename(&self.storage, filename)
+    }
+
+    /// Build listing entries for the given files, `None` for files not found on disk
+    pub async fn get_files_info(&self, filenames: Vec<String>) -> Result<Vec<(String, Option<FileInfo>)>, AppError> {
+        let storage = self.storage.clone();
+        let static_base_url = self.static_base_url.clone();
+        let url_subdir = self.url_subdir.clone();
+        let variant_sizes = self.thumbnail_variant_sizes.clone();
+
+        tokio::task::spawn_blocking(move || -> Result<Vec<(String, Option<FileInfo>)>, AppError> {
+            filenames
+                .into_iter()
+                .map(|filename| {
+                    let info = match stored_filename(&storage, &filename) {
+                        Some(stored) => Some(build_file_info(&storage, &static_base_url, url_subdir.as_deref(), &filename, &stored, &variant_sizes)?),
+                        None => None,
+                    };
+                    Ok((filename, info))
+                })
+                .collect()
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute file info task".to_string()))?
+    }
+
+    /// List the given files, newest first, from the listing data cached in their metadata.
+    ///
+    /// Only the requested page touches the disk: each of its entries is checked
+    /// against the stored file and rebuilt when missing or stale. Files gone from
+    /// disk are left out of the page but still counted in the total. Returns the
+    /// page, the total and the rebuilt entries, for the caller to store.
     pub async fn list_files_with_filter(
         &self,
         page: usize,
         per_page: usize,
-        filter_files: Option<Vec<String>>,
-    ) -> Result<(Vec<FileInfo>, usize), AppError> {
-        let upload_dir = self.upload_dir.clone();
+        mut files: Vec<StoredFileMetadata>,
+    ) -> Result<ListingPage, AppError> {
+        let storage = self.storage.clone();
         let static_base_url = self.static_base_url.clone();
+        let url_subdir = self.url_subdir.clone();
+        let variant_sizes = self.thumbnail_variant_sizes.clone();
         
-        tokio::task::spawn_blocking(move || -> Result<(Vec<FileInfo>, usize), AppError> {
-            let mut files = Vec::new();
-            
-            if !upload_dir.exists() {
-                return Ok((files, 0));
-            }
+        tokio::task::spawn_blocking(move || -> Result<ListingPage, AppError> {
+            // Sort by upload date (newest first), by name for a stable order between pages
+            files.sort_by(|a, b| b.uploaded_at.cmp(&a.uploaded_at).then_with(|| a.filename.cmp(&b.filename)));
             
-            let entries = fs::read_dir(&upload_dir)?;
-            let mut file_entries = Vec::new();
+            let total = files.len();
+            let start = std::cmp::min(page * per_page, total);
+            let end = std::cmp::min(start + per_page, total);
             
-            for entry in entries {
-                let entry = entry?;
-                let path = entry.path();
-                
-                if path.is_file() {
-                    let filename = path.file_name()
-                        .and_then(|name| name.to_str())
-                        .unwrap_or("unknown")
-                        .to_string();
-                    
-                    // Skip thumbnail and QOI files in listing
-                    if filename.contains("_thumb.") || filename.ends_with(".qoi") {
-                        continue;
-                    }
-                    
-                    // If filter is provided, only include files in the filter list
-                    if let Some(ref filter) = filter_files {
-                        if !filter.contains(&filename) {
+            let mut page_files = Vec::new();
+            let mut rebuilt = HashMap::new();
+            for meta in &files[start..end] {
+                let listing = match meta.listing {
+                    Some(ref listing) if is_fresh(&storage, listing) => listing.clone(),
+                    _ => {
+                        let Some(stored) = stored_filename(&storage, &meta.filename) else {
                             continue;
-                        }
+                        };
+                        let listing = listing_cache(&storage, &meta.filename, &stored, &variant_sizes)?;
+                        rebuilt.insert(meta.filename.clone(), listing.clone());
+                        listing
                     }
-                    
-                    let metadata = entry.metadata()?;
-                    let size = metadata.len();
-                    let modified = metadata.modified()?;
-                    let uploaded_at: DateTime<Utc> = modified.into();
-                    
-                    let mime_type = get_mime_type(&filename);
-                    let is_image = ImageProcessor::is_image_file(&filename);
-                    
-                    let urls = FileUrls {
-                        original: format!("{}/uploads/{}", static_base_url, filename),
-                        qoi: if is_image {
-                            let stem = Path::new(&filename).file_stem()
-                                .and_then(|s| s.to_str())
-                                .unwrap_or("file");
-                            let qoi_filename = format!("{}.qoi", stem);
-                            let qoi_path = upload_dir.join(&qoi_filename);
-                            if qoi_path.exists() {
-                                Some(format!("{}/uploads/{}", static_base_url, qoi_filename))
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        },
-                        thumbnail: if is_image {
-                            let stem = Path::new(&filename).file_stem()
-                                .and_then(|s| s.to_str())
-                                .unwrap_or("file");
-                            let thumb_filename = format!("{}_thumb.webp", stem);
-                            let thumb_path = upload_dir.join(&thumb_filename);
-                            if thumb_path.exists() {
-                                Some(format!("{}/uploads/{}", static_base_url, thumb_filename))
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        },
-                    };
-                    
-                    // Try to get image dimensions if it's an image
-                    let dimensions = if is_image {
-                        match image::ImageReader::open(&path).and_then(|r| r.into_dimensions().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))) {
-                            Ok(dims) => Some(dims),
-                            _ => None,
-                        }
-                    } else {
-                        None
-                    };
-                    
-                    file_entries.push((uploaded_at, FileInfo {
-                        filename,
-                        size,
-                        mime_type,
-                        uploaded_at,
-                        is_image,
-                        urls,
-                        dimensions,
-                        folder_id: None, // Will be set by the caller
-                    }));
-                }
+                };
+                page_files.push(file_info_from_cache(&static_base_url, url_subdir.as_deref(), &meta.filename, meta.uploaded_at, &listing));
             }
             
-            // Sort by upload date (newest first)
-            file_entries.sort_by(|a, b| b.0.cmp(&a.0));
-            
-            let total = file_entries.len();
-            let start = page * per_page;
-            let end = std::cmp::min(start + per_page, total);
-            
-            if start < total {
-                files = file_entries[start..end].iter().map(|(_, info)| info.clone()).collect();
-            }
-            
-            Ok((files, total))
+            Ok((page_files, total, rebuilt))
         })
         .await
         .map_err(|_| AppError::Internal("Failed to execute file listing task".to_string()))?
     }
 
+    /// Listing data of a file as currently stored on disk
+    pub fn listing_cache(&self, filename: &str) -> Result<ListingCache, AppError> {
+        let stored = self.stored_filename(filename)
+            .ok_or_else(|| AppError::FileNotFound(filename.to_string()))?;
+        listing_cache(&self.storage, filename, &stored, &self.thumbnail_variant_sizes)
+    }
+
+    /// Files physically present in the upload dir and its shard subdirectories,
+    /// without dotfiles and derivatives
+    ///
+    /// Unlike `list_files`, this doesn't go through the metadata at all.
+    pub async fn list_raw_files(&self) -> Result<Vec<RawFileEntry>, AppError> {
+        let storage = self.storage.clone();
+
+        tokio::task::spawn_blocking(move || -> Result<Vec<RawFileEntry>, AppError> {
+            let mut files = Vec::new();
+            if !storage.root().exists() {
+                return Ok(files);
+            }
+
+            for entry in storage.files()? {
+                let metadata = entry.metadata()?;
+                let Some(filename) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                if !metadata.is_file() || filename.starts_with('.') || is_derivative(&filename) {
+                    continue;
+                }
+
+                files.push(RawFileEntry {
+                    filename,
+                    size: metadata.len(),
+                    modified: metadata.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now()),
+                    tracked: false,
+                });
+            }
+
+            files.sort_by(|a, b| a.filename.cmp(&b.filename));
+            Ok(files)
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute raw listing task".to_string()))?
+    }
+
     /// Delete a file and its associated files (QOI, thumbnail)
     pub async fn delete_file(&self, filename: &str) -> Result<(), AppError> {
-        let upload_dir = self.upload_dir.clone();
+        let storage = self.storage.clone();
         let filename = filename.to_string();
+        let variant_sizes = self.thumbnail_variant_sizes.clone();
         
         tokio::task::spawn_blocking(move || -> Result<(), AppError> {
-            let file_path = upload_dir.join(&filename);
+            let file_path = storage.path(&filename);
             
-            if !file_path.exists() {
+            if file_path.exists() {
+                // Remove the main file
+                fs::remove_file(&file_path)?;
+                info!("Deleted file: {:?}", file_path);
+            } else if best_derivative(&storage, &filename).is_none() {
                 return Err(AppError::FileNotFound(filename));
             }
             
-            // Remove the main file
-            fs::remove_file(&file_path)?;
-            info!("Deleted file: {:?}", file_path);
-            
             // Remove associated files if they exist
             let path = Path::new(&filename);
             let stem = path.file_stem()
@@ -192,15 +275,38 @@ impl FileManager {
                 .unwrap_or("file");
             
             // Remove QOI file
-            let qoi_path = upload_dir.join(format!("{}.qoi", stem));
+            let qoi_path = storage.path(&format!("{}.qoi", stem));
             if qoi_path.exists() {
                 fs::remove_file(&qoi_path)?;
             }
             
-            // Remove thumbnail
-            let thumb_path = upload_dir.join(format!("{}_thumb.webp", stem));
-            if thumb_path.exists() {
-                fs::remove_file(&thumb_path)?;
+            // Remove AVIF copy
+            let avif_path = storage.path(&avif_name(&filename));
+            if avif_path.exists() {
+                fs::remove_file(&avif_path)?;
+            }
+            
+            // Remove thumbnails
+            for extension in THUMBNAIL_EXTENSIONS {
+                let thumb_path = storage.path(&format!("{}_thumb.{}", stem, extension));
+                if thumb_path.exists() {
+                    fs::remove_file(&thumb_path)?;
+                }
+            }
+            for variant_filename in find_thumbnail_variants(&storage, &filename, &variant_sizes).into_values() {
+                fs::remove_file(storage.path(&variant_filename))?;
+            }
+            
+            // Remove gzip companion
+            let gzip_path = storage.path(&format!("{}.gz", filename));
+            if gzip_path.exists() {
+                fs::remove_file(&gzip_path)?;
+            }
+            
+            // Remove deep-zoom tiles
+            let tiles_dir = storage.root().join(TILES_DIR).join(&filename);
+            if tiles_dir.exists() {
+                fs::remove_dir_all(&tiles_dir)?;
             }
             
             Ok(())
@@ -212,18 +318,17 @@ impl FileManager {
     /// Find a file by its stem (base filename)
     /// This allows deleting files by providing just the base name
     pub async fn find_file_by_stem(&self, stem: &str) -> Result<Option<String>, AppError> {
-        let upload_dir = self.upload_dir.clone();
+        let storage = self.storage.clone();
         let stem = stem.to_string();
         
         tokio::task::spawn_blocking(move || -> Result<Option<String>, AppError> {
-            if !upload_dir.exists() {
+            if !storage.root().exists() {
                 return Ok(None);
             }
             
-            let entries = fs::read_dir(&upload_dir)?;
+            let entries = storage.files()?;
             
             for entry in entries {
-                let entry = entry?;
                 let path = entry.path();
                 
                 if path.is_file() {
@@ -233,7 +338,7 @@ impl FileManager {
                         .to_string();
                     
                     // Skip thumbnail and QOI files - we want to find the original
-                    if filename.contains("_thumb.") || filename.ends_with(".qoi") {
+                    if is_derivative(&filename) {
                         continue;
                     }
                     
@@ -262,19 +367,244 @@ impl FileManager {
         .map_err(|_| AppError::Internal("Failed to execute filename generation task".to_string()))?
     }
 
+    /// Compute the content hash of a file's bytes
+    pub fn content_hash(data: &[u8]) -> String {
+        blake3::hash(data).to_hex().to_string()
+    }
+
+    /// Compute the content hash of a stored file without loading it whole
+    pub fn hash_file(&self, filename: &str) -> Result<String, AppError> {
+        let stored = self.stored_filename(filename)
+            .ok_or_else(|| AppError::FileNotFound(filename.to_string()))?;
+        Self::hash_path(&self.get_file_path(&stored))
+    }
+
+    /// Compute the content hash of any file on disk without loading it whole
+    pub fn hash_path(path: &Path) -> Result<String, AppError> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(&mut file)?;
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
     /// Check if a file exists
     pub fn file_exists(&self, filename: &str) -> bool {
-        self.get_file_path(filename).exists()
+        self.stored_filename(filename).is_some()
     }
 
     /// Get the size of a file in bytes
     pub fn get_file_size(&self, filename: &str) -> Result<u64, AppError> {
-        let file_path = self.get_file_path(filename);
-        if !file_path.exists() {
-            return Err(AppError::FileNotFound(filename.to_string()));
-        }
+        let stored = self.stored_filename(filename)
+            .ok_or_else(|| AppError::FileNotFound(filename.to_string()))?;
         
-        let metadata = fs::metadata(&file_path)?;
+        let metadata = fs::metadata(self.get_file_path(&stored))?;
         Ok(metadata.len())
     }
 }
+
+/// Build the static URL of a file, optionally under a subdirectory of `/uploads`
+fn build_file_url(static_base_url: &str, subdir: Option<&str>, filename: &str) -> String {
+    format!("{}{}", static_base_url, upload_url_path(subdir, filename))
+}
+
+/// Path of a file on the static server, e.g. `/uploads/photo.png`
+fn upload_url_path(subdir: Option<&str>, filename: &str) -> String {
+    match subdir {
+        Some(subdir) => format!("/uploads/{}/{}", subdir, filename),
+        None => format!("/uploads/{}", filename),
+    }
+}
+
+/// Name of the file holding a file's content: the original if kept, otherwise its best derivative
+fn stored_filename(storage: &StorageDir, filename: &str) -> Option<String> {
+    if storage.path(filename).is_file() {
+        Some(filename.to_string())
+    } else {
+        best_derivative(storage, filename)
+    }
+}
+
+/// Name of the derivative that stands in for an image whose original was discarded.
+/// The thumbnail is preferred since browsers can display it directly.
+fn best_derivative(storage: &StorageDir, filename: &str) -> Option<String> {
+    let stem = Path::new(filename).file_stem()?.to_str()?;
+    find_thumbnail(storage, filename).or_else(|| {
+        let qoi_filename = format!("{}.qoi", stem);
+        storage.path(&qoi_filename).is_file().then_some(qoi_filename)
+    })
+}
+
+/// Name of the thumbnail of `filename` written as `format` (see `THUMBNAIL_FORMAT`)
+pub fn thumbnail_name(filename: &str, format: &str) -> String {
+    let stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+    let extension = if format == "jpeg" { "jpg" } else { format };
+    format!("{}_thumb.{}", stem, extension)
+}
+
+/// Name of the AVIF copy of `filename`. The suffix tells it apart from
+/// uploaded AVIF files, which are stored under `{stem}.avif` as well.
+pub fn avif_name(filename: &str) -> String {
+    let stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+    format!("{}_avif.avif", stem)
+}
+
+/// Name of the `size` variant of the thumbnail of `filename` written as `format`
+pub fn thumbnail_variant_name(filename: &str, size: u32, format: &str) -> String {
+    let stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+    let extension = if format == "jpeg" { "jpg" } else { format };
+    format!("{}_thumb_{}.{}", stem, size, extension)
+}
+
+/// Names of the thumbnail variants of `filename` among `sizes` on disk, by size
+fn find_thumbnail_variants(storage: &StorageDir, filename: &str, sizes: &[u32]) -> BTreeMap<u32, String> {
+    sizes.iter()
+        .filter_map(|&size| {
+            THUMBNAIL_EXTENSIONS.iter()
+                .map(|extension| thumbnail_variant_name(filename, size, extension))
+                .find(|candidate| storage.path(candidate).is_file())
+                .map(|variant_filename| (size, variant_filename))
+        })
+        .collect()
+}
+
+/// Name of the thumbnail of `filename` on disk, whichever format it was written as
+fn find_thumbnail(storage: &StorageDir, filename: &str) -> Option<String> {
+    THUMBNAIL_EXTENSIONS.iter()
+        .map(|extension| thumbnail_name(filename, extension))
+        .find(|candidate| storage.path(candidate).is_file())
+}
+
+/// Name of the thumbnail file of a file stored as `stored_filename`
+fn thumbnail_filename(storage: &StorageDir, filename: &str, stored_filename: &str) -> Option<String> {
+    // PDFs and videos may have one rendered from a frame
+    if !ImageProcessor::is_image_file(filename) {
+        return find_thumbnail(storage, filename);
+    }
+    // Images too small for a thumbnail are their own thumbnail
+    find_thumbnail(storage, filename).or_else(|| Some(stored_filename.to_string()))
+}
+
+/// Build the listing entry of `filename`, whose content is stored as `stored_filename`
+fn build_file_info(
+    storage: &StorageDir,
+    static_base_url: &str,
+    url_subdir: Option<&str>,
+    filename: &str,
+    stored_filename: &str,
+    variant_sizes: &[u32],
+) -> Result<FileInfo, AppError> {
+    let listing = listing_cache(storage, filename, stored_filename, variant_sizes)?;
+    Ok(file_info_from_cache(static_base_url, url_subdir, filename, listing.modified, &listing))
+}
+
+/// Read the listing data of `filename`, whose content is stored as `stored_filename`,
+/// with its thumbnail variants among `variant_sizes`
+fn listing_cache(storage: &StorageDir, filename: &str, stored_filename: &str, variant_sizes: &[u32]) -> Result<ListingCache, AppError> {
+    let path = storage.path(stored_filename);
+    let metadata = fs::metadata(&path)?;
+    let is_image = ImageProcessor::is_image_file(filename);
+    let stem = Path::new(filename).file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("file");
+    let existing = |name: String| storage.path(&name).exists().then_some(name);
+    
+    // Try to get image dimensions if it's an image
+    let dimensions = if is_image { display_dimensions(&path).ok() } else { None };
+    
+    Ok(ListingCache {
+        stored_filename: stored_filename.to_string(),
+        size: metadata.len(),
+        modified: metadata.modified()?.into(),
+        mime_type: get_mime_type(stored_filename),
+        dimensions,
+        qoi_filename: if is_image { existing(format!("{}.qoi", stem)) } else { None },
+        avif_filename: if is_image { existing(avif_name(filename)) } else { None },
+        thumbnail_filename: thumbnail_filename(storage, filename, stored_filename),
+        thumbnail_jpeg_filename: existing(format!("{}_thumb.jpg", stem)),
+        thumbnail_variants: find_thumbnail_variants(storage, filename, variant_sizes),
+    })
+}
+
+/// Whether cached listing data still matches the stored file
+fn is_fresh(storage: &StorageDir, listing: &ListingCache) -> bool {
+    fs::metadata(storage.path(&listing.stored_filename)).is_ok_and(|metadata| {
+        metadata.len() == listing.size
+            && metadata.modified().is_ok_and(|modified| DateTime::<Utc>::from(modified) == listing.modified)
+    })
+}
+
+/// Build the listing entry of `filename` from its listing data
+fn file_info_from_cache(
+    static_base_url: &str,
+    url_subdir: Option<&str>,
+    filename: &str,
+    uploaded_at: DateTime<Utc>,
+    listing: &ListingCache,
+) -> FileInfo {
+    let url = |name: &String| build_file_url(static_base_url, url_subdir, name);
+    
+    FileInfo {
+        filename: filename.to_string(),
+        display_name: None, // Will be set by the caller
+        size: listing.size,
+        mime_type: listing.mime_type.clone(),
+        uploaded_at,
+        is_image: ImageProcessor::is_image_file(filename),
+        urls: FileUrls {
+            original: url(&listing.stored_filename),
+            qoi: listing.qoi_filename.as_ref().map(url),
+            avif: listing.avif_filename.as_ref().map(url),
+            thumbnail: listing.thumbnail_filename.as_ref().map(url),
+            thumbnail_jpeg: listing.thumbnail_jpeg_filename.as_ref().map(url),
+            thumbnails: listing.thumbnail_variants.iter().map(|(&size, name)| (size, url(name))).collect(),
+        },
+        dimensions: listing.dimensions,
+        folder_id: None, // Will be set by the caller
+        folder_path: None, // Will be set by the caller
+        derivatives_skipped: false, // Will be set by the caller
+        preview_snippet: None, // Will be set by the caller
+        page_count: None, // Will be set by the caller
+        content_hash: None, // Will be set by the caller
+    }
+}
+
+/// Whether a stored file is named like a thumbnail variant, `{stem}_thumb_{size}.{ext}`
+fn is_thumbnail_variant(filename: &str) -> bool {
+    let stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    stem.rsplit_once("_thumb_")
+        .is_some_and(|(_, size)| !size.is_empty() && size.bytes().all(|b| b.is_ascii_digit()))
+        && Path::new(filename).extension().and_then(|e| e.to_str())
+            .is_some_and(|extension| THUMBNAIL_EXTENSIONS.contains(&extension))
+}
+
+/// Stem of the file a stored file belongs to: its own for originals, that of
+/// the file it was made from for derivatives and gzip companions
+pub fn original_stem(filename: &str) -> &str {
+    let name = filename.strip_suffix(".gz")
+        .filter(|original| is_compressible_mime_type(&get_mime_type(original)))
+        .unwrap_or(filename);
+    let path = Path::new(name);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+
+    let original = if is_thumbnail_variant(name) {
+        stem.rsplit_once("_thumb_").map(|(original, _)| original)
+    } else if THUMBNAIL_EXTENSIONS.contains(&extension) {
+        stem.strip_suffix("_thumb")
+    } else if extension == "avif" {
+        stem.strip_suffix("_avif")
+    } else {
+        None
+    };
+    original.unwrap_or(stem)
+}
+
+/// Whether a stored file is a thumbnail, QOI or AVIF copy, or gzip companion of another one
+fn is_derivative(filename: &str) -> bool {
+    filename.contains("_thumb.")
+        || is_thumbnail_variant(filename)
+        || filename.ends_with(".qoi")
+        || filename.ends_with("_avif.avif")
+        // Uploaded `.gz` files are stored under `<name>_<id>_.gz`, never after a compressible extension
+        || filename.strip_suffix(".gz").is_some_and(|original| is_compressible_mime_type(&get_mime_type(original)))
+}
@@ -1,36 +1,93 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::Write;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
 use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::error::AppError;
-use crate::models::{FolderInfo, FolderListResponse};
-use tracing::{info};
+use crate::models::{Breadcrumb, FolderImageSettings, FolderInfo, FolderListResponse, ImageAnalysis, ListingCache, MovedFolder};
+use tracing::{info, warn};
+use utoipa::ToSchema;
 
 /// Folder metadata stored in JSON files
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct FolderMetadata {
     pub id: String,
     pub name: String,
     pub parent_id: Option<String>,
     pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "FolderImageSettings::is_empty")]
+    pub image_settings: FolderImageSettings,
+    /// Quota of the folder and its subfolders, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
 }
 
 /// File metadata with folder information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[schema(as = StoredFileMetadata)]
 pub struct FileMetadata {
     pub filename: String,
     pub folder_id: Option<String>,
     pub uploaded_at: DateTime<Utc>,
     #[serde(default)]
     pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview_snippet: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qoi_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avif_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_variant_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<ImageAnalysis>,
+    /// Filename as uploaded, before sanitization
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_name: Option<String>,
+    /// Number of pages of multi-page documents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_count: Option<u32>,
+    /// Name shown instead of the stored filename, which stays unchanged to keep URLs stable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// The image was too large for derivatives, see `max_derivative_source_pixels`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub derivatives_skipped: bool,
+    /// Size, dimensions and derivatives as last seen on disk, rebuilt when stale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listing: Option<ListingCache>,
+}
+
+impl FileMetadata {
+    /// Bytes taken by the upload and its derivatives, as counted by quotas
+    pub fn stored_bytes(&self) -> u64 {
+        self.size + self.qoi_bytes.unwrap_or(0) + self.avif_bytes.unwrap_or(0) + self.thumbnail_bytes.unwrap_or(0) + self.thumbnail_variant_bytes.unwrap_or(0)
+    }
+}
+
+/// A folder, its descendants and their files
+pub struct FolderSubtree {
+    /// Folders with their path from the subtree root (e.g. "Photos/2024"), parents first
+    pub folders: Vec<(String, FolderMetadata)>,
+    pub files: Vec<FileMetadata>,
 }
 
 pub struct FolderManager {
     upload_dir: PathBuf,
     metadata_file: PathBuf,
     file_metadata_file: PathBuf,
+    version_file: PathBuf,
+    case_insensitive_names: bool,
+    breadcrumb_max_depth: Option<usize>,
+    max_total_size: Option<u64>,
 }
 
 impl FolderManager {
@@ -38,25 +95,62 @@ impl FolderManager {
         let upload_dir: PathBuf = upload_dir.into();
         let metadata_file = upload_dir.join(".folder_metadata.json");
         let file_metadata_file = upload_dir.join(".file_metadata.json");
+        let version_file = upload_dir.join(".metadata_version");
         
         Self {
             upload_dir,
             metadata_file,
             file_metadata_file,
+            version_file,
+            case_insensitive_names: false,
+            breadcrumb_max_depth: None,
+            max_total_size: None,
         }
     }
 
+    /// Compare folder names case-insensitively when checking for duplicates
+    pub fn with_case_insensitive_names(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive_names = case_insensitive;
+        self
+    }
+
+    /// Only keep the closest `max_depth` ancestors in listing breadcrumbs
+    pub fn with_breadcrumb_max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.breadcrumb_max_depth = max_depth;
+        self
+    }
+
+    /// Reject new files once the storage holds `max_total_size` bytes
+    pub fn with_max_total_size(mut self, max_total_size: Option<u64>) -> Self {
+        self.max_total_size = max_total_size;
+        self
+    }
+
+    /// Check if two folder names collide under the configured comparison
+    fn names_conflict(&self, a: &str, b: &str) -> bool {
+        if self.case_insensitive_names {
+            a.to_lowercase() == b.to_lowercase()
+        } else {
+            a == b
+        }
+    }
+
+    /// Check if a folder other than `except_id` already uses a name under a parent
+    fn has_sibling_named(&self, metadata: &HashMap<String, FolderMetadata>, name: &str, parent_id: &Option<String>, except_id: Option<&str>) -> bool {
+        metadata.values().any(|existing| {
+            &existing.parent_id == parent_id
+                && Some(existing.id.as_str()) != except_id
+                && self.names_conflict(&existing.name, name)
+        })
+    }
+
     /// Load folder metadata from file
     pub fn load_folder_metadata(&self) -> Result<HashMap<String, FolderMetadata>, AppError> {
         if !self.metadata_file.exists() {
             return Ok(HashMap::new());
         }
 
-        let content = fs::read_to_string(&self.metadata_file)?;
-        let metadata: HashMap<String, FolderMetadata> = serde_json::from_str(&content)
-            .map_err(|e| AppError::Internal(format!("Failed to parse folder metadata: {}", e)))?;
-        
-        Ok(metadata)
+        read_metadata(&self.metadata_file, "folder metadata")
     }
 
     /// Save folder metadata to file
@@ -64,8 +158,8 @@ impl FolderManager {
         let content = serde_json::to_string_pretty(metadata)
             .map_err(|e| AppError::Internal(format!("Failed to serialize folder metadata: {}", e)))?;
         
-        fs::write(&self.metadata_file, content)?;
-        Ok(())
+        write_metadata(&self.metadata_file, content.as_bytes())?;
+        self.bump_version()
     }
 
     /// Load file metadata from file
@@ -74,79 +168,278 @@ impl FolderManager {
             return Ok(HashMap::new());
         }
 
-        let content = fs::read_to_string(&self.file_metadata_file)?;
-        let metadata: HashMap<String, FileMetadata> = serde_json::from_str(&content)
-            .map_err(|e| AppError::Internal(format!("Failed to parse file metadata: {}", e)))?;
-        
-        Ok(metadata)
+        read_metadata(&self.file_metadata_file, "file metadata")
     }
 
     /// Save file metadata to file
     fn save_file_metadata(&self, metadata: &HashMap<String, FileMetadata>) -> Result<(), AppError> {
+        self.write_file_metadata(metadata)?;
+        self.bump_version()
+    }
+
+    /// Save file metadata to file without changing the metadata version
+    fn write_file_metadata(&self, metadata: &HashMap<String, FileMetadata>) -> Result<(), AppError> {
         let content = serde_json::to_string_pretty(metadata)
             .map_err(|e| AppError::Internal(format!("Failed to serialize file metadata: {}", e)))?;
         
-        fs::write(&self.file_metadata_file, content)?;
+        write_metadata(&self.file_metadata_file, content.as_bytes())
+    }
+
+    /// Lock held while reading, changing and saving the metadata of this upload dir,
+    /// so concurrent updates don't overwrite each other
+    fn metadata_lock(&self) -> Arc<Mutex<()>> {
+        static LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = OnceLock::new();
+        let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(self.upload_dir.clone()).or_default().clone()
+    }
+
+    /// Opaque token that changes on every metadata save, for cache validation
+    pub fn metadata_version(&self) -> String {
+        fs::read_to_string(&self.version_file).unwrap_or_else(|_| "initial".to_string())
+    }
+
+    /// Record a new metadata version. A random token rather than a counter, so
+    /// versions never repeat even if the file is lost or the storage is wiped.
+    fn bump_version(&self) -> Result<(), AppError> {
+        fs::write(&self.version_file, Uuid::new_v4().to_string())?;
         Ok(())
     }
 
     /// Create a new folder
-    pub async fn create_folder(&self, name: &str, parent_id: Option<String>) -> Result<FolderInfo, AppError> {
+    pub async fn create_folder(&self, name: &str, parent_id: Option<String>, image_settings: FolderImageSettings) -> Result<FolderInfo, AppError> {
+        self.insert_folder(FolderMetadata {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            parent_id,
+            created_at: Utc::now(),
+            image_settings,
+            max_size: None,
+        }).await
+    }
+
+    /// Recreate a folder from exported metadata, keeping its id and creation date
+    pub async fn restore_folder(&self, folder: FolderMetadata) -> Result<FolderInfo, AppError> {
+        self.insert_folder(folder).await
+    }
+
+    async fn insert_folder(&self, folder: FolderMetadata) -> Result<FolderInfo, AppError> {
         let folder_manager = self.clone();
-        let name = name.to_string();
         
         tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
             let mut metadata = folder_manager.load_folder_metadata()?;
             
+            if metadata.contains_key(&folder.id) {
+                return Err(AppError::BadRequest(format!("Folder with id '{}' already exists", folder.id)));
+            }
+            
             // Validate parent folder exists if specified
-            if let Some(ref parent_id) = parent_id {
+            if let Some(ref parent_id) = folder.parent_id {
                 if !metadata.contains_key(parent_id) {
                     return Err(AppError::NotFound(format!("Parent folder with id '{}' not found", parent_id)));
                 }
             }
             
             // Check if folder with same name already exists in the parent
-            for folder in metadata.values() {
-                if folder.name == name && folder.parent_id == parent_id {
-                    return Err(AppError::BadRequest(format!("Folder '{}' already exists in this location", name)));
-                }
+            if folder_manager.has_sibling_named(&metadata, &folder.name, &folder.parent_id, None) {
+                return Err(AppError::BadRequest(format!("Folder '{}' already exists in this location", folder.name)));
             }
             
-            let folder_id = Uuid::new_v4().to_string();
-            let created_at = Utc::now();
-            
-            let folder_metadata = FolderMetadata {
-                id: folder_id.clone(),
-                name: name.clone(),
-                parent_id: parent_id.clone(),
-                created_at,
-            };
-            
-            metadata.insert(folder_id.clone(), folder_metadata);
+            metadata.insert(folder.id.clone(), folder.clone());
             folder_manager.save_folder_metadata(&metadata)?;
             
-            info!("Created folder: {} (id: {})", name, folder_id);
+            info!("Created folder: {} (id: {})", folder.name, folder.id);
             
             Ok(FolderInfo {
-                id: folder_id,
-                name,
-                parent_id,
-                created_at,
+                id: folder.id,
+                name: folder.name,
+                parent_id: folder.parent_id,
+                created_at: folder.created_at,
                 file_count: 0,
                 folder_count: 0,
                 size: 0,
+                image_settings: folder.image_settings,
+                max_size: folder.max_size,
             })
         })
         .await
         .map_err(|_| AppError::Internal("Failed to execute folder creation task".to_string()))?
     }
 
+    /// Resolve a path of folder names from the root, creating the missing
+    /// folders. Returns the id of the last folder, `None` for an empty path.
+    pub async fn ensure_folder_path(&self, names: Vec<String>) -> Result<Option<String>, AppError> {
+        let folder_manager = self.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut metadata = folder_manager.load_folder_metadata()?;
+            let mut parent_id: Option<String> = None;
+            let mut created = false;
+            
+            for name in names {
+                let existing = metadata.values()
+                    .find(|folder| folder.parent_id == parent_id && folder_manager.names_conflict(&folder.name, &name))
+                    .map(|folder| folder.id.clone());
+                let folder_id = match existing {
+                    Some(folder_id) => folder_id,
+                    None => {
+                        let folder = FolderMetadata {
+                            id: Uuid::new_v4().to_string(),
+                            name,
+                            parent_id: parent_id.clone(),
+                            created_at: Utc::now(),
+                            image_settings: FolderImageSettings::default(),
+                            max_size: None,
+                        };
+                        info!("Created folder: {} (id: {})", folder.name, folder.id);
+                        let folder_id = folder.id.clone();
+                        metadata.insert(folder_id.clone(), folder);
+                        created = true;
+                        folder_id
+                    }
+                };
+                parent_id = Some(folder_id);
+            }
+            
+            if created {
+                folder_manager.save_folder_metadata(&metadata)?;
+            }
+            Ok(parent_id)
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute folder path task".to_string()))?
+    }
+
+    /// Id of the folder with this name in the given parent, if any
+    pub fn find_folder(&self, name: &str, parent_id: &Option<String>) -> Result<Option<String>, AppError> {
+        Ok(self.load_folder_metadata()?
+            .into_values()
+            .find(|folder| &folder.parent_id == parent_id && self.names_conflict(&folder.name, name))
+            .map(|folder| folder.id))
+    }
+
+    /// Replace the image processing overrides of a folder
+    pub async fn set_image_settings(&self, folder_id: &str, image_settings: FolderImageSettings) -> Result<(), AppError> {
+        let folder_manager = self.clone();
+        let folder_id = folder_id.to_string();
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut folder_metadata = folder_manager.load_folder_metadata()?;
+            
+            let folder = folder_metadata.get_mut(&folder_id)
+                .ok_or_else(|| AppError::NotFound(format!("Folder with id '{}' not found", folder_id)))?;
+            folder.image_settings = image_settings;
+            
+            folder_manager.save_folder_metadata(&folder_metadata)
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute folder update task".to_string()))?
+    }
+
+    /// Set or remove the quota of a folder
+    pub async fn set_max_size(&self, folder_id: &str, max_size: Option<u64>) -> Result<(), AppError> {
+        let folder_manager = self.clone();
+        let folder_id = folder_id.to_string();
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut folder_metadata = folder_manager.load_folder_metadata()?;
+            
+            let folder = folder_metadata.get_mut(&folder_id)
+                .ok_or_else(|| AppError::NotFound(format!("Folder with id '{}' not found", folder_id)))?;
+            folder.max_size = max_size;
+            
+            folder_manager.save_folder_metadata(&folder_metadata)
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute folder update task".to_string()))?
+    }
+
+    /// Check that `incoming` more bytes in `folder_id` keep the storage and every
+    /// enclosing folder within their quotas. `moved` files are already stored and
+    /// only counted at their destination; other incoming bytes are new to the storage.
+    fn check_quota(
+        &self,
+        folder_metadata: &HashMap<String, FolderMetadata>,
+        file_metadata: &HashMap<String, FileMetadata>,
+        folder_id: Option<&str>,
+        incoming: u64,
+        moved: &[String],
+    ) -> Result<(), AppError> {
+        if let Some(max_total_size) = self.max_total_size.filter(|_| moved.is_empty()) {
+            let used: u64 = file_metadata.values().map(FileMetadata::stored_bytes).sum();
+            if used + incoming > max_total_size {
+                return Err(AppError::InsufficientStorage(format!(
+                    "Storing {} more bytes would exceed the storage quota of {} bytes ({} used)",
+                    incoming, max_total_size, used
+                )));
+            }
+        }
+
+        // Bounded in case of a parent cycle in hand-edited metadata
+        let is_within = |folder_id: Option<&String>, ancestor: &str| {
+            let mut current = folder_id.map(String::as_str);
+            for _ in 0..=folder_metadata.len() {
+                match current {
+                    Some(id) if id == ancestor => return true,
+                    Some(id) => current = folder_metadata.get(id).and_then(|folder| folder.parent_id.as_deref()),
+                    None => return false,
+                }
+            }
+            false
+        };
+        let mut current = folder_id.and_then(|id| folder_metadata.get(id));
+        for _ in 0..folder_metadata.len() {
+            let Some(folder) = current else {
+                break;
+            };
+            if let Some(max_size) = folder.max_size {
+                let used: u64 = file_metadata.values()
+                    .filter(|file| !moved.contains(&file.filename) && is_within(file.folder_id.as_ref(), &folder.id))
+                    .map(FileMetadata::stored_bytes)
+                    .sum();
+                if used + incoming > max_size {
+                    return Err(AppError::InsufficientStorage(format!(
+                        "Storing {} more bytes would exceed the quota of folder '{}' of {} bytes ({} used)",
+                        incoming, folder.name, max_size, used
+                    )));
+                }
+            }
+            current = folder.parent_id.as_ref().and_then(|id| folder_metadata.get(id));
+        }
+        Ok(())
+    }
+
+    /// Image processing overrides applying to files of a folder, inherited from its ancestors
+    pub fn effective_image_settings(&self, folder_id: Option<&str>) -> Result<FolderImageSettings, AppError> {
+        let folder_metadata = self.load_folder_metadata()?;
+        let mut settings = FolderImageSettings::default();
+        let mut current = folder_id.and_then(|id| folder_metadata.get(id));
+        // Bounded in case of a parent cycle in hand-edited metadata
+        for _ in 0..folder_metadata.len() {
+            let Some(folder) = current else {
+                break;
+            };
+            settings = settings.inherit(&folder.image_settings);
+            current = folder.parent_id.as_ref().and_then(|id| folder_metadata.get(id));
+        }
+        Ok(settings)
+    }
+
     /// Delete a folder (must be empty)
     pub async fn delete_folder(&self, folder_id: &str) -> Result<(), AppError> {
         let folder_manager = self.clone();
         let folder_id = folder_id.to_string();
         
         tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
             let mut folder_metadata = folder_manager.load_folder_metadata()?;
             let file_metadata = folder_manager.load_file_metadata()?;
             
@@ -179,29 +472,27 @@ impl FolderManager {
     }
 
     /// Get folder contents
-    pub async fn list_folder_contents(&self, folder_id: Option<String>) -> Result<FolderListResponse, AppError> {
+    ///
+    /// Breadcrumbs only carry folder stats when `breadcrumb_stats` is set.
+    pub async fn list_folder_contents(&self, folder_id: Option<String>, breadcrumb_stats: bool) -> Result<FolderListResponse, AppError> {
         let folder_manager = self.clone();
         
         tokio::task::spawn_blocking(move || {
             let folder_metadata = folder_manager.load_folder_metadata()?;
             let file_metadata = folder_manager.load_file_metadata()?;
             
-            // Helper function to calculate counts and size for a folder
+            // File count, subfolder count and size of every folder, in a single pass
+            let mut folder_stats: HashMap<Option<String>, (usize, usize, u64)> = HashMap::new();
+            for file in file_metadata.values() {
+                let stats = folder_stats.entry(file.folder_id.clone()).or_default();
+                stats.0 += 1;
+                stats.2 += file.size;
+            }
+            for folder in folder_metadata.values() {
+                folder_stats.entry(folder.parent_id.clone()).or_default().1 += 1;
+            }
             let calculate_folder_stats = |target_folder_id: &Option<String>| -> (usize, usize, u64) {
-                let file_count = file_metadata.values()
-                    .filter(|file| &file.folder_id == target_folder_id)
-                    .count();
-                
-                let folder_count = folder_metadata.values()
-                    .filter(|folder| &folder.parent_id == target_folder_id)
-                    .count();
-                
-                let size = file_metadata.values()
-                    .filter(|file| &file.folder_id == target_folder_id)
-                    .map(|file| file.size)
-                    .sum();
-                
-                (file_count, folder_count, size)
+                folder_stats.get(target_folder_id).copied().unwrap_or_default()
             };
             
             // Validate folder exists if specified and calculate its stats
@@ -217,6 +508,8 @@ impl FolderManager {
                             file_count,
                             folder_count,
                             size,
+                            image_settings: metadata.image_settings.clone(),
+                            max_size: metadata.max_size,
                         })
                     },
                     None => return Err(AppError::NotFound(format!("Folder with id '{}' not found", folder_id))),
@@ -238,6 +531,8 @@ impl FolderManager {
                         file_count,
                         folder_count,
                         size,
+                        image_settings: metadata.image_settings.clone(),
+                        max_size: metadata.max_size,
                     }
                 })
                 .collect();
@@ -245,44 +540,52 @@ impl FolderManager {
             // Sort folders by name
             folders.sort_by(|a, b| a.name.cmp(&b.name));
             
-            // Build breadcrumbs with calculated stats
+            // Build breadcrumbs, from the current folder up, stopping at the configured depth
             let mut breadcrumbs = Vec::new();
+            let mut breadcrumbs_truncated = false;
             let mut current_id = folder_id.clone();
             
             while let Some(id) = current_id {
-                if let Some(metadata) = folder_metadata.get(&id) {
-                    let (file_count, folder_count, size) = calculate_folder_stats(&Some(id.clone()));
-                    breadcrumbs.insert(0, FolderInfo {
-                        id: metadata.id.clone(),
-                        name: metadata.name.clone(),
-                        parent_id: metadata.parent_id.clone(),
-                        created_at: metadata.created_at,
-                        file_count,
-                        folder_count,
-                        size,
-                    });
-                    current_id = metadata.parent_id.clone();
-                } else {
+                let Some(metadata) = folder_metadata.get(&id) else {
+                    break;
+                };
+                if folder_manager.breadcrumb_max_depth.is_some_and(|max_depth| breadcrumbs.len() >= max_depth) {
+                    breadcrumbs_truncated = true;
                     break;
                 }
+                let stats = breadcrumb_stats.then(|| calculate_folder_stats(&Some(id.clone())));
+                breadcrumbs.push(Breadcrumb {
+                    id: metadata.id.clone(),
+                    name: metadata.name.clone(),
+                    parent_id: metadata.parent_id.clone(),
+                    file_count: stats.map(|(file_count, _, _)| file_count),
+                    folder_count: stats.map(|(_, folder_count, _)| folder_count),
+                    size: stats.map(|(_, _, size)| size),
+                });
+                current_id = metadata.parent_id.clone();
             }
+            breadcrumbs.reverse();
             
             Ok(FolderListResponse {
                 folders,
                 current_folder,
                 breadcrumbs,
+                breadcrumbs_truncated,
             })
         })
         .await
         .map_err(|_| AppError::Internal("Failed to execute folder listing task".to_string()))?
     }
 
-    /// Assign a file to a folder
-    pub async fn assign_file_to_folder(&self, filename: &str, folder_id: Option<String>, size: u64) -> Result<(), AppError> {
+    /// Set the folder of a file, creating its metadata entry if needed.
+    /// New entries get `uploaded_at`, or the current time.
+    pub async fn assign_file_to_folder(&self, filename: &str, folder_id: Option<String>, size: u64, uploaded_at: Option<DateTime<Utc>>) -> Result<(), AppError> {
         let folder_manager = self.clone();
         let filename = filename.to_string();
         
         tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
             let folder_metadata = folder_manager.load_folder_metadata()?;
             let mut file_metadata = folder_manager.load_file_metadata()?;
             
@@ -293,15 +596,44 @@ impl FolderManager {
                 }
             }
             
+            // Checked under the lock, so concurrent uploads can't both squeeze under a quota
+            match file_metadata.get(&filename) {
+                Some(existing) if existing.folder_id != folder_id => {
+                    folder_manager.check_quota(&folder_metadata, &file_metadata, folder_id.as_deref(), existing.stored_bytes(), std::slice::from_ref(&filename))?;
+                }
+                Some(_) => {}
+                None => folder_manager.check_quota(&folder_metadata, &file_metadata, folder_id.as_deref(), size, &[])?,
+            }
+            
             // Update or create file metadata
-            let file_meta = FileMetadata {
-                filename: filename.clone(),
-                folder_id: folder_id.clone(),
-                uploaded_at: Utc::now(),
-                size,
-            };
+            match file_metadata.get_mut(&filename) {
+                Some(file_meta) => {
+                    file_meta.folder_id = folder_id.clone();
+                    file_meta.size = size;
+                }
+                None => {
+                    let file_meta = FileMetadata {
+                        filename: filename.clone(),
+                        folder_id: folder_id.clone(),
+                        uploaded_at: uploaded_at.unwrap_or_else(Utc::now),
+                        size,
+                        preview_snippet: None,
+                        qoi_bytes: None,
+                        avif_bytes: None,
+                        thumbnail_bytes: None,
+                        thumbnail_variant_bytes: None,
+                        content_hash: None,
+                        analysis: None,
+                        original_name: None,
+                        page_count: None,
+                        display_name: None,
+                        derivatives_skipped: false,
+                        listing: None,
+                    };
+                    file_metadata.insert(filename.clone(), file_meta);
+                }
+            }
             
-            file_metadata.insert(filename.clone(), file_meta);
             folder_manager.save_file_metadata(&file_metadata)?;
             
             Ok(())
@@ -310,7 +642,109 @@ impl FolderManager {
         .map_err(|_| AppError::Internal("Failed to execute file assignment task".to_string()))?
     }
 
+    /// Apply an update to the metadata entry of an existing file
+    pub async fn update_file_metadata<F>(&self, filename: &str, update: F) -> Result<(), AppError>
+    where
+        F: FnOnce(&mut FileMetadata) + Send + 'static,
+    {
+        let folder_manager = self.clone();
+        let filename = filename.to_string();
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut file_metadata = folder_manager.load_file_metadata()?;
+            
+            let file_meta = file_metadata.get_mut(&filename)
+                .ok_or_else(|| AppError::FileNotFound(filename.clone()))?;
+            update(file_meta);
+            
+            folder_manager.save_file_metadata(&file_metadata)?;
+            Ok(())
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute file metadata update task".to_string()))?
+    }
+
+    /// Move several files to a folder in a single metadata write
+    pub async fn move_files(&self, filenames: Vec<String>, folder_id: Option<String>) -> Result<(), AppError> {
+        let folder_manager = self.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let folder_metadata = folder_manager.load_folder_metadata()?;
+            let mut file_metadata = folder_manager.load_file_metadata()?;
+            
+            if let Some(ref folder_id) = folder_id {
+                if !folder_metadata.contains_key(folder_id) {
+                    return Err(AppError::NotFound(format!("Folder with id '{}' not found", folder_id)));
+                }
+            }
+            
+            // Files already in the target folder don't change its usage
+            let moved: Vec<String> = filenames.into_iter()
+                .filter(|filename| file_metadata.get(filename).is_some_and(|meta| meta.folder_id != folder_id))
+                .collect();
+            let incoming = moved.iter().filter_map(|filename| file_metadata.get(filename)).map(FileMetadata::stored_bytes).sum();
+            folder_manager.check_quota(&folder_metadata, &file_metadata, folder_id.as_deref(), incoming, &moved)?;
+            
+            for filename in moved {
+                if let Some(file_meta) = file_metadata.get_mut(&filename) {
+                    file_meta.folder_id = folder_id.clone();
+                }
+            }
+            folder_manager.save_file_metadata(&file_metadata)?;
+            Ok(())
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute file move task".to_string()))?
+    }
+
+    /// Store content hashes for several files in a single metadata write
+    pub async fn set_content_hashes(&self, hashes: HashMap<String, String>) -> Result<(), AppError> {
+        let folder_manager = self.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut file_metadata = folder_manager.load_file_metadata()?;
+            for (filename, hash) in hashes {
+                if let Some(file_meta) = file_metadata.get_mut(&filename) {
+                    file_meta.content_hash = Some(hash);
+                }
+            }
+            folder_manager.save_file_metadata(&file_metadata)?;
+            Ok(())
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute content hash update task".to_string()))?
+    }
+
+    /// Store rebuilt listing data for several files in a single metadata write.
+    /// The version is kept: listings already reflect what is on disk, so the
+    /// `ETag` they were sent with stays valid.
+    pub async fn set_listing_caches(&self, listings: HashMap<String, ListingCache>) -> Result<(), AppError> {
+        let folder_manager = self.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut file_metadata = folder_manager.load_file_metadata()?;
+            for (filename, listing) in listings {
+                if let Some(file_meta) = file_metadata.get_mut(&filename) {
+                    file_meta.listing = Some(listing);
+                }
+            }
+            folder_manager.write_file_metadata(&file_metadata)?;
+            Ok(())
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute listing cache update task".to_string()))?
+    }
+
     /// Get folder ID for a file
+    #[allow(dead_code)]
     pub async fn get_file_folder(&self, filename: &str) -> Result<Option<String>, AppError> {
         let folder_manager = self.clone();
         let filename = filename.to_string();
@@ -340,14 +774,53 @@ impl FolderManager {
         Ok(files)
     }
 
+    /// A folder with all its descendant folders and the files they contain
+    pub fn folder_subtree(&self, folder_id: &str) -> Result<FolderSubtree, AppError> {
+        let folder_metadata = self.load_folder_metadata()?;
+        let file_metadata = self.load_file_metadata()?;
+
+        let root = folder_metadata.get(folder_id)
+            .ok_or_else(|| AppError::NotFound(format!("Folder with id '{}' not found", folder_id)))?;
+
+        let mut folders = vec![(root.name.clone(), root.clone())];
+        let mut next = 0;
+        while next < folders.len() {
+            let (path, parent) = &folders[next];
+            let mut children: Vec<(String, FolderMetadata)> = folder_metadata
+                .values()
+                .filter(|folder| folder.parent_id.as_ref() == Some(&parent.id))
+                .map(|folder| (format!("{}/{}", path, folder.name), folder.clone()))
+                .collect();
+            children.sort_by(|a, b| a.0.cmp(&b.0));
+            folders.extend(children);
+            next += 1;
+        }
+
+        let mut files: Vec<FileMetadata> = file_metadata
+            .into_values()
+            .filter(|file| file.folder_id.as_ref().is_some_and(|id| folders.iter().any(|(_, folder)| &folder.id == id)))
+            .collect();
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+        Ok(FolderSubtree { folders, files })
+    }
+
     /// Remove file from metadata when deleted
     pub async fn remove_file_metadata(&self, filename: &str) -> Result<(), AppError> {
+        self.remove_files_metadata(vec![filename.to_string()]).await
+    }
+
+    /// Remove the metadata of several files in a single metadata write
+    pub async fn remove_files_metadata(&self, filenames: Vec<String>) -> Result<(), AppError> {
         let folder_manager = self.clone();
-        let filename = filename.to_string();
         
         tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
             let mut file_metadata = folder_manager.load_file_metadata()?;
-            file_metadata.remove(&filename);
+            for filename in filenames {
+                file_metadata.remove(&filename);
+            }
             folder_manager.save_file_metadata(&file_metadata)?;
             Ok(())
         })
@@ -355,12 +828,15 @@ impl FolderManager {
         .map_err(|_| AppError::Internal("Failed to execute remove file metadata task".to_string()))?
     }
 
-    /// Move a folder to a new parent folder
-    pub async fn move_folder(&self, folder_id: &str, new_parent_id: Option<String>) -> Result<(), AppError> {
+    /// Move a folder under a new parent, returning the new paths of the moved subtree.
+    /// Runs under the metadata lock, so uploads into the subtree can't interleave with it.
+    pub async fn move_folder(&self, folder_id: &str, new_parent_id: Option<String>) -> Result<Vec<MovedFolder>, AppError> {
         let folder_manager = self.clone();
         let folder_id = folder_id.to_string();
         
         tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
             let mut folder_metadata = folder_manager.load_folder_metadata()?;
             
             // Check if the folder exists
@@ -385,12 +861,20 @@ impl FolderManager {
             }
             
             // Check if a folder with the same name already exists in the target location
-            for existing_folder in folder_metadata.values() {
-                if existing_folder.name == folder.name 
-                    && existing_folder.parent_id == new_parent_id 
-                    && existing_folder.id != folder_id {
-                    return Err(AppError::BadRequest(format!("Folder '{}' already exists in target location", folder.name)));
-                }
+            if folder_manager.has_sibling_named(&folder_metadata, &folder.name, &new_parent_id, Some(&folder_id)) {
+                return Err(AppError::BadRequest(format!("Folder '{}' already exists in target location", folder.name)));
+            }
+            
+            // The files of the moved subtree count towards the quotas of its new ancestors
+            let file_metadata = folder_manager.load_file_metadata()?;
+            let subtree_files: Vec<String> = moved_subtree(&folder_id, &folder_metadata)
+                .into_iter()
+                .flat_map(|moved| file_metadata.values().filter(move |file| file.folder_id.as_deref() == Some(moved.id.as_str())))
+                .map(|file| file.filename.clone())
+                .collect();
+            let incoming = subtree_files.iter().filter_map(|filename| file_metadata.get(filename)).map(FileMetadata::stored_bytes).sum();
+            if incoming > 0 {
+                folder_manager.check_quota(&folder_metadata, &file_metadata, new_parent_id.as_deref(), incoming, &subtree_files)?;
             }
             
             // Update the folder's parent_id
@@ -401,12 +885,46 @@ impl FolderManager {
             folder_manager.save_folder_metadata(&folder_metadata)?;
             
             info!("Moved folder '{}' (id: {}) to new parent: {:?}", folder.name, folder_id, new_parent_id);
-            Ok(())
+            Ok(moved_subtree(&folder_id, &folder_metadata))
         })
         .await
         .map_err(|_| AppError::Internal("Failed to execute move folder task".to_string()))?
     }
 
+    /// Rename a folder, keeping names unique among its siblings
+    pub async fn rename_folder(&self, folder_id: &str, name: &str) -> Result<(), AppError> {
+        let folder_manager = self.clone();
+        let folder_id = folder_id.to_string();
+        let name = name.trim().to_string();
+        
+        if name.is_empty() {
+            return Err(AppError::BadRequest("Folder name cannot be empty".to_string()));
+        }
+        
+        tokio::task::spawn_blocking(move || {
+            let lock = folder_manager.metadata_lock();
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut folder_metadata = folder_manager.load_folder_metadata()?;
+            
+            let parent_id = folder_metadata.get(&folder_id)
+                .ok_or_else(|| AppError::NotFound(format!("Folder with id '{}' not found", folder_id)))?
+                .parent_id
+                .clone();
+            
+            if folder_manager.has_sibling_named(&folder_metadata, &name, &parent_id, Some(&folder_id)) {
+                return Err(AppError::BadRequest(format!("Folder '{}' already exists in this location", name)));
+            }
+            
+            if let Some(folder) = folder_metadata.get_mut(&folder_id) {
+                info!("Renamed folder '{}' (id: {}) to '{}'", folder.name, folder_id, name);
+                folder.name = name;
+            }
+            folder_manager.save_folder_metadata(&folder_metadata)
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute rename folder task".to_string()))?
+    }
+
     /// Get folder info by ID
     pub async fn get_folder_info(&self, folder_id: &str) -> Result<FolderInfo, AppError> {
         let folder_manager = self.clone();
@@ -439,6 +957,8 @@ impl FolderManager {
                         file_count,
                         folder_count,
                         size,
+                        image_settings: metadata.image_settings.clone(),
+                        max_size: metadata.max_size,
                     })
                 },
                 None => Err(AppError::NotFound(format!("Folder with id '{}' not found", folder_id))),
@@ -450,12 +970,216 @@ impl FolderManager {
 
 }
 
+/// Split a `/`-separated folder path into folder names, ignoring empty segments
+pub fn split_folder_path(path: &str) -> Result<Vec<String>, AppError> {
+    path.split('/')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match name {
+            "." | ".." => Err(AppError::BadRequest(format!("Invalid folder name in path: {}", name))),
+            _ => Ok(name.to_string()),
+        })
+        .collect()
+}
+
+/// Path of the copy of a metadata file kept from before its last save
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// Parse a metadata file, recovering from its backup when it is corrupt
+fn read_metadata<T: DeserializeOwned>(path: &Path, kind: &str) -> Result<T, AppError> {
+    let error = match serde_json::from_str(&fs::read_to_string(path)?) {
+        Ok(metadata) => return Ok(metadata),
+        Err(e) => e,
+    };
+
+    let backup = backup_path(path);
+    match fs::read_to_string(&backup).ok().and_then(|content| serde_json::from_str(&content).ok()) {
+        Some(metadata) => {
+            warn!("Failed to parse {} ({}), using the backup from before the last save", kind, error);
+            Ok(metadata)
+        }
+        None => Err(AppError::Internal(format!("Failed to parse {}: {}", kind, error))),
+    }
+}
+
+/// Save a metadata file, first keeping its current content as a backup if it is valid JSON
+fn write_metadata(path: &Path, content: &[u8]) -> Result<(), AppError> {
+    if let Ok(previous) = fs::read(path) {
+        // Never replace a good backup with the corrupt file it is standing in for
+        if serde_json::from_slice::<serde::de::IgnoredAny>(&previous).is_ok() {
+            write_atomically(&backup_path(path), &previous)?;
+        }
+    }
+    write_atomically(path, content)
+}
+
+/// Write a file by renaming a fully written sibling temp file over it.
+/// A crash mid-write leaves either the old or the new content, never a truncated file.
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> Result<(), AppError> {
+    let file_name = path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| AppError::Internal(format!("Invalid metadata path: {:?}", path)))?;
+    let temp_path = path.with_file_name(format!("{}.tmp-{}", file_name, Uuid::new_v4()));
+
+    let result = (|| -> std::io::Result<()> {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+
+    // Persist the rename itself
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
+/// A folder and its descendants, parents first, with their full paths
+fn moved_subtree(folder_id: &str, folder_metadata: &HashMap<String, FolderMetadata>) -> Vec<MovedFolder> {
+    let Some(root) = folder_metadata.get(folder_id) else {
+        return Vec::new();
+    };
+
+    let mut folders = vec![MovedFolder {
+        id: root.id.clone(),
+        name: root.name.clone(),
+        path: build_folder_path(&Some(root.id.clone()), folder_metadata),
+    }];
+    let mut next = 0;
+    while next < folders.len() {
+        let parent = &folders[next];
+        let mut children: Vec<MovedFolder> = folder_metadata
+            .values()
+            .filter(|folder| folder.parent_id.as_deref() == Some(parent.id.as_str()))
+            .map(|folder| MovedFolder {
+                id: folder.id.clone(),
+                name: folder.name.clone(),
+                path: format!("{}/{}", parent.path, folder.name),
+            })
+            .collect();
+        children.sort_by(|a, b| a.path.cmp(&b.path));
+        folders.extend(children);
+        next += 1;
+    }
+    folders
+}
+
+/// Build the display path of a folder (e.g. "/Photos/2024"), "/" for root
+pub fn build_folder_path(folder_id: &Option<String>, folder_metadata: &HashMap<String, FolderMetadata>) -> String {
+    let mut components = Vec::new();
+    let mut current_folder = folder_id.clone();
+    while let Some(ref id) = current_folder {
+        match folder_metadata.get(id) {
+            Some(folder) => {
+                components.push(folder.name.clone());
+                current_folder = folder.parent_id.clone();
+            }
+            None => break,
+        }
+    }
+    components.reverse();
+    format!("/{}", components.join("/"))
+}
+
 impl Clone for FolderManager {
     fn clone(&self) -> Self {
         Self {
             upload_dir: self.upload_dir.clone(),
             metadata_file: self.metadata_file.clone(),
             file_metadata_file: self.file_metadata_file.clone(),
+            version_file: self.version_file.clone(),
+            case_insensitive_names: self.case_insensitive_names,
+            breadcrumb_max_depth: self.breadcrumb_max_depth,
+            max_total_size: self.max_total_size,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_assignments_all_survive() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder_manager = FolderManager::new(dir.path());
+
+        let tasks: Vec<_> = (0..64)
+            .map(|i| {
+                let folder_manager = folder_manager.clone();
+                tokio::spawn(async move {
+                    folder_manager.assign_file_to_folder(&format!("file_{}.txt", i), None, 1, None).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        let file_metadata = folder_manager.load_file_metadata().unwrap();
+        assert_eq!(file_metadata.len(), 64);
+        assert!((0..64).all(|i| file_metadata.contains_key(&format!("file_{}.txt", i))));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn folder_moves_while_files_are_uploaded_into_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder_manager = FolderManager::new(dir.path());
+        let photos = folder_manager.create_folder("photos", None, FolderImageSettings::default()).await.unwrap();
+        let archive = folder_manager.create_folder("archive", None, FolderImageSettings::default()).await.unwrap();
+
+        let mut tasks: Vec<_> = (0..32)
+            .map(|i| {
+                let folder_manager = folder_manager.clone();
+                let folder_id = photos.id.clone();
+                tokio::spawn(async move {
+                    folder_manager.assign_file_to_folder(&format!("photo_{}.jpg", i), Some(folder_id), 1, None).await
+                })
+            })
+            .collect();
+        let mover = folder_manager.clone();
+        let (photos_id, archive_id) = (photos.id.clone(), archive.id.clone());
+        tasks.insert(16, tokio::spawn(async move {
+            let moved = mover.move_folder(&photos_id, Some(archive_id)).await?;
+            assert_eq!(moved.len(), 1);
+            assert_eq!(moved[0].path, "/archive/photos");
+            Ok(())
+        }));
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        let folder_metadata = folder_manager.load_folder_metadata().unwrap();
+        assert_eq!(folder_metadata[&photos.id].parent_id.as_deref(), Some(archive.id.as_str()));
+        let files = folder_manager.get_files_in_folder(Some(photos.id.clone())).unwrap();
+        assert_eq!(files.len(), 32);
+    }
+
+
+    #[test]
+    fn failed_write_leaves_previous_file_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        // Long enough that the name of its temp sibling exceeds the file name limit,
+        // so the write fails after the target exists
+        let path = dir.path().join("m".repeat(240));
+        fs::write(&path, b"previous").unwrap();
+
+        assert!(write_atomically(&path, b"next").is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"previous");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+}
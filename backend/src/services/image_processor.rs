@@ -1,8 +1,29 @@
-use image::{ImageFormat, GenericImageView};
-use std::path::Path;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, GenericImageView};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::metadata::Orientation;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 use crate::error::AppError;
 use crate::config::ImageConfig;
-use tracing::{info};
+use crate::models::{ColorHistogram, ImageAnalysis, TileLevel};
+use crate::services::folder_manager::write_atomically;
+use crate::utils::metadata;
+use tracing::{info, warn};
+
+/// Sources up to this many pixels get the top of the adaptive WebP quality range
+const ADAPTIVE_QUALITY_SMALL_PIXELS: f32 = 512.0 * 512.0;
+/// Sources from this many pixels get the bottom of the adaptive WebP quality range
+const ADAPTIVE_QUALITY_LARGE_PIXELS: f32 = 4096.0 * 4096.0;
+
+/// rav1e speed of AVIF copies, from 1 (smallest files) to 10 (fastest)
+const AVIF_SPEED: u8 = 8;
+
+/// Longest side of the copy analyzed for image statistics
+const ANALYSIS_SIZE: u32 = 128;
+const HISTOGRAM_BINS: usize = 8;
+/// Max channel spread for a pixel to still count as gray
+const GRAYSCALE_TOLERANCE: u8 = 12;
 
 pub struct ImageProcessor {
     config: ImageConfig,
@@ -13,7 +34,7 @@ impl ImageProcessor {
         Self { config }
     }
 
-    /// Check if a file is an image based on its extension
+    /// Check if a file is an image the processor can decode, based on its extension
     pub fn is_image_file(filename: &str) -> bool {
         let extension = Path::new(filename)
             .extension()
@@ -22,12 +43,144 @@ impl ImageProcessor {
 
         matches!(
             extension.as_deref(),
-            Some("jpg") | Some("jpeg") | Some("png") | Some("gif") | 
+            Some("jpg") | Some("jpeg") | Some("jfif") | Some("png") | Some("apng") | Some("gif") |
             Some("bmp") | Some("tiff") | Some("tif") | Some("webp")
         )
     }
 
-    /// Convert image to QOI format
+    /// Re-encode image bytes to the configured canonical format, upright since
+    /// the EXIF orientation doesn't survive re-encoding.
+    /// Returns the new bytes and their file extension, or `None` when no canonical format is set.
+    pub async fn encode_canonical(&self, data: &[u8]) -> Result<Option<(Vec<u8>, &'static str)>, AppError> {
+        let Some(format) = self.config.canonical_image_format.clone() else {
+            return Ok(None);
+        };
+        let data = data.to_vec();
+        let quality = self.config.canonical_image_quality;
+
+        tokio::task::spawn_blocking(move || -> Result<Option<(Vec<u8>, &'static str)>, AppError> {
+            let mut decoder = ImageReader::new(Cursor::new(&data)).with_guessed_format()?.into_decoder()?;
+            let orientation = decoder.orientation()?;
+            let mut img = DynamicImage::from_decoder(decoder)?;
+            img.apply_orientation(orientation);
+            let mut output = Cursor::new(Vec::new());
+
+            let extension = match format.as_str() {
+                "jpeg" => {
+                    // JPEG has no alpha channel
+                    let encoder = JpegEncoder::new_with_quality(&mut output, quality);
+                    img.to_rgb8().write_with_encoder(encoder)?;
+                    "jpg"
+                }
+                "png" => {
+                    img.write_to(&mut output, ImageFormat::Png)?;
+                    "png"
+                }
+                "webp" => {
+                    // The bundled WebP encoder is lossless only
+                    img.write_to(&mut output, ImageFormat::WebP)?;
+                    "webp"
+                }
+                other => return Err(AppError::Internal(format!("Unsupported canonical image format: {}", other))),
+            };
+
+            Ok(Some((output.into_inner(), extension)))
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute canonical conversion task".to_string()))?
+    }
+
+    /// Rotate an image clockwise by 90, 180 or 270 degrees, re-encoded in its own format.
+    /// Returns the encoded bytes of the rotated image.
+    pub async fn rotate(&self, path: &Path, degrees: u16) -> Result<Vec<u8>, AppError> {
+        let path = path.to_owned();
+        let jpeg_quality = self.config.jpeg_quality;
+
+        tokio::task::spawn_blocking(move || -> Result<Vec<u8>, AppError> {
+            let format = ImageFormat::from_path(&path)?;
+            // Start from the image as displayed, re-encoding drops its EXIF orientation
+            let img = open_image(&path, true)?;
+            let rotated = match degrees {
+                90 => img.rotate90(),
+                180 => img.rotate180(),
+                270 => img.rotate270(),
+                other => return Err(AppError::BadRequest(format!("Invalid rotation: {} degrees", other))),
+            };
+
+            encode_as(&rotated, format, jpeg_quality)
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute image rotation task".to_string()))?
+    }
+
+    /// Remove EXIF, XMP and text metadata from a JPEG, PNG or WebP file in place,
+    /// losslessly when possible. Images with an EXIF orientation are re-encoded
+    /// upright, as the orientation goes away with the rest of the EXIF data.
+    /// Other formats are left as is.
+    pub async fn strip_metadata(&self, path: &Path, format: ImageFormat) -> Result<(), AppError> {
+        let path = path.to_owned();
+        let jpeg_quality = self.config.jpeg_quality;
+
+        tokio::task::spawn_blocking(move || -> Result<(), AppError> {
+            let strip = match format {
+                ImageFormat::Jpeg => metadata::strip_jpeg,
+                ImageFormat::Png => metadata::strip_png,
+                ImageFormat::WebP => metadata::strip_webp,
+                _ => return Ok(()),
+            };
+            let data = std::fs::read(&path)?;
+            let mut decoder = ImageReader::with_format(Cursor::new(&data), format).into_decoder()?;
+            let orientation = decoder.orientation()?;
+
+            let stripped = match orientation {
+                Orientation::NoTransforms => strip(&data),
+                _ => None,
+            };
+            let stripped = match stripped {
+                Some(stripped) => stripped,
+                // Re-encoding keeps no metadata
+                None => {
+                    let mut img = DynamicImage::from_decoder(decoder)?;
+                    img.apply_orientation(orientation);
+                    encode_as(&img, format, jpeg_quality)?
+                }
+            };
+            std::fs::write(&path, stripped)?;
+            Ok(())
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute metadata stripping task".to_string()))?
+    }
+
+    /// Re-encode an image as lossy AVIF at `avif_quality`
+    pub async fn convert_to_avif(&self, input_path: &Path, output_path: &Path) -> Result<(), AppError> {
+        let input_path = input_path.to_owned();
+        let output_path = output_path.to_owned();
+        let auto_orient = self.config.auto_orient;
+        let quality = self.config.avif_quality;
+
+        tokio::task::spawn_blocking(move || -> Result<(), AppError> {
+            let img = open_image(&input_path, auto_orient)?;
+            // The encoder takes 8-bit pixels only
+            let img = if img.color().has_alpha() {
+                DynamicImage::ImageRgba8(img.to_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(img.to_rgb8())
+            };
+
+            let mut avif_data = Vec::new();
+            img.write_with_encoder(AvifEncoder::new_with_speed_quality(&mut avif_data, AVIF_SPEED, quality))?;
+            std::fs::write(&output_path, avif_data)?;
+
+            info!("Successfully converted image to AVIF: {:?}", output_path);
+            Ok(())
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute AVIF conversion task".to_string()))?
+    }
+
+    /// Convert image to QOI format.
+    /// QOI has no room for a color profile, pixels are stored as is and read as sRGB.
     pub async fn convert_to_qoi(
         &self,
         input_path: &Path,
@@ -35,9 +188,10 @@ impl ImageProcessor {
     ) -> Result<(u32, u32), AppError> {
         let input_path = input_path.to_owned();
         let output_path = output_path.to_owned();
+        let auto_orient = self.config.auto_orient;
 
         tokio::task::spawn_blocking(move || -> Result<(u32, u32), AppError> {            
-            let img = image::open(&input_path)?;
+            let img = open_image(&input_path, auto_orient)?;
             let (width, height) = img.dimensions();
             
             // Convert to RGBA8
@@ -58,59 +212,478 @@ impl ImageProcessor {
         .map_err(|_| AppError::Internal("Failed to execute QOI conversion task".to_string()))?
     }
 
-    /// Generate thumbnail for an image
+    /// Whether a file is a TIFF, which may hold several pages
+    pub fn is_tiff_file(filename: &str) -> bool {
+        Path::new(filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("tif") || ext.eq_ignore_ascii_case("tiff"))
+    }
+
+    /// Count the pages (image file directories) of a TIFF without decoding them
+    pub async fn tiff_page_count(&self, path: &Path) -> Result<u32, AppError> {
+        let path = path.to_owned();
+
+        tokio::task::spawn_blocking(move || -> Result<u32, AppError> {
+            let reader = std::io::BufReader::new(std::fs::File::open(&path)?);
+            let tiff_error = |e: tiff::TiffError| AppError::Internal(format!("Failed to read TIFF pages: {}", e));
+            let mut decoder = tiff::decoder::Decoder::new(reader).map_err(tiff_error)?;
+
+            let mut pages = 1;
+            while decoder.more_images() {
+                decoder.next_image().map_err(tiff_error)?;
+                pages += 1;
+            }
+            Ok(pages)
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute TIFF page count task".to_string()))?
+    }
+
+    /// Generate thumbnail for an image, from the first page of multi-page TIFFs.
+    /// A JPEG copy is also written to `jpeg_output_path` when given, and a
+    /// thumbnail of each size of `variant_outputs` no larger than the source.
+    /// Returns false when the source is too small to need one and is its own thumbnail.
     pub async fn generate_thumbnail(
         &self,
         input_path: &Path,
         output_path: &Path,
-    ) -> Result<(), AppError> {
+        jpeg_output_path: Option<&Path>,
+        variant_outputs: &[(u32, PathBuf)],
+    ) -> Result<bool, AppError> {
         let input_path = input_path.to_owned();
         let output_path = output_path.to_owned();
+        let jpeg_output_path = jpeg_output_path.map(Path::to_owned);
+        let variant_outputs = variant_outputs.to_vec();
+        let jpeg_quality = self.config.jpeg_quality;
+        let auto_orient = self.config.auto_orient;
         let thumbnail_size = self.config.thumbnail_size;
-        let _webp_quality = self.config.webp_quality;
+        let min_source_dimension = match self.config.thumbnail_min_source_dimension {
+            0 => thumbnail_size,
+            dimension => dimension,
+        };
+        let webp_quality = self.config.webp_quality;
+        let webp_quality_range = self.config.webp_quality_range;
+        let thumbnail_format = self.config.thumbnail_format.clone();
 
-        tokio::task::spawn_blocking(move || -> Result<(), AppError> {            
-            let img = image::open(&input_path)?;
+        tokio::task::spawn_blocking(move || -> Result<bool, AppError> {            
+            // The TIFF decoder only yields the first page
+            let img = open_image(&input_path, auto_orient)?;
             
-            // Calculate thumbnail dimensions while maintaining aspect ratio
+            // Skip small sources rather than upscaling them
             let (orig_width, orig_height) = img.dimensions();
-            let aspect_ratio = orig_width as f32 / orig_height as f32;
+            if orig_width.max(orig_height) < min_source_dimension {
+                info!("Skipping thumbnail for small image: {:?}", input_path);
+                return Ok(false);
+            }
             
-            let (thumb_width, thumb_height) = if aspect_ratio > 1.0 {
-                // Landscape
-                (thumbnail_size, (thumbnail_size as f32 / aspect_ratio) as u32)
-            } else {
-                // Portrait or square
-                ((thumbnail_size as f32 * aspect_ratio) as u32, thumbnail_size)
+            // Never upscale beyond the source
+            let thumbnail = resize_thumbnail(&img, thumbnail_size.min(orig_width.max(orig_height)));
+            let webp_quality = match webp_quality_range {
+                Some((min, max)) => adaptive_quality(min, max, orig_width, orig_height),
+                None => webp_quality,
             };
-            
-            let thumbnail = img.resize(
-                thumb_width,
-                thumb_height,
-                image::imageops::FilterType::Lanczos3,
-            );
-            
-            // Save as WebP for better compression
-            thumbnail.save_with_format(&output_path, ImageFormat::WebP)?;
+            write_thumbnail(&thumbnail, &output_path, &thumbnail_format, jpeg_quality, webp_quality)?;
+
+            if let Some(jpeg_output_path) = jpeg_output_path {
+                write_thumbnail(&thumbnail, &jpeg_output_path, "jpeg", jpeg_quality, webp_quality)?;
+            }
+
+            for (size, variant_path) in variant_outputs {
+                if size <= orig_width.max(orig_height) {
+                    write_thumbnail(&resize_thumbnail(&img, size), &variant_path, &thumbnail_format, jpeg_quality, webp_quality)?;
+                }
+            }
             
             info!("Successfully generated thumbnail: {:?}", output_path);
-            Ok(())
+            Ok(true)
         })
         .await
         .map_err(|_| AppError::Internal("Failed to execute thumbnail generation task".to_string()))?
     }
 
-    /// Get image dimensions without loading the full image
-    #[allow(dead_code)]
-    pub async fn get_dimensions(&self, path: &Path) -> Result<(u32, u32), AppError> {
+    /// Compute brightness, grayscale likelihood and a coarse color histogram.
+    /// Works on a small downscaled copy, so a thumbnail is as good a source as the original.
+    pub async fn analyze(&self, input_path: &Path) -> Result<ImageAnalysis, AppError> {
+        let input_path = input_path.to_owned();
+
+        tokio::task::spawn_blocking(move || -> Result<ImageAnalysis, AppError> {
+            let img = image::open(&input_path)?
+                .thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE)
+                .to_rgb8();
+
+            let mut red = [0u64; HISTOGRAM_BINS];
+            let mut green = [0u64; HISTOGRAM_BINS];
+            let mut blue = [0u64; HISTOGRAM_BINS];
+            let mut luma_sum = 0.0f64;
+            let mut color_pixels = 0u64;
+
+            for pixel in img.pixels() {
+                let [r, g, b] = pixel.0;
+                red[r as usize * HISTOGRAM_BINS / 256] += 1;
+                green[g as usize * HISTOGRAM_BINS / 256] += 1;
+                blue[b as usize * HISTOGRAM_BINS / 256] += 1;
+                luma_sum += 0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64;
+
+                let spread = r.max(g).max(b) - r.min(g).min(b);
+                if spread > GRAYSCALE_TOLERANCE {
+                    color_pixels += 1;
+                }
+            }
+
+            let total = (img.width() as u64 * img.height() as u64).max(1);
+            let normalize = |bins: [u64; HISTOGRAM_BINS]| -> Vec<f32> {
+                bins.iter().map(|&count| count as f32 / total as f32).collect()
+            };
+
+            Ok(ImageAnalysis {
+                brightness: (luma_sum / total as f64 / 255.0) as f32,
+                // Allow a few stray colored pixels from compression artifacts
+                is_grayscale: color_pixels * 100 <= total,
+                histogram: ColorHistogram {
+                    red: normalize(red),
+                    green: normalize(green),
+                    blue: normalize(blue),
+                },
+            })
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute image analysis task".to_string()))?
+    }
+
+    /// Get the dimensions of an image as its derivatives show it, upright when
+    /// `auto_orient` is set, without loading the full image
+    pub async fn get_oriented_dimensions(&self, path: &Path) -> Result<(u32, u32), AppError> {
         let path = path.to_owned();
+        let auto_orient = self.config.auto_orient;
         
         tokio::task::spawn_blocking(move || -> Result<(u32, u32), AppError> {
-            let reader = image::ImageReader::open(&path)?;
-            let dimensions = reader.into_dimensions()?;
-            Ok(dimensions)
+            if auto_orient {
+                display_dimensions(&path)
+            } else {
+                Ok(ImageReader::open(&path)?.into_dimensions()?)
+            }
+        })
+        .await
+        .map_err(|_| AppError::Internal("Failed to execute image dimensions task".to_string()))?
+    }
+
+    /// Cut one level of a deep-zoom pyramid into WebP tiles named `{x}_{y}.webp`.
+    /// The whole level is written at once, as the source has to be decoded anyway.
+    pub async fn generate_tile_level(
+        &self,
+        input_path: &Path,
+        level: TileLevel,
+        tile_size: u32,
+        output_dir: &Path,
+    ) -> Result<(), AppError> {
+        let input_path = input_path.to_owned();
+        let output_dir = output_dir.to_owned();
+        let auto_orient = self.config.auto_orient;
+        let webp_quality = self.config.webp_quality;
+
+        tokio::task::spawn_blocking(move || -> Result<(), AppError> {
+            let img = open_image(&input_path, auto_orient)?;
+            let img = if img.dimensions() == (level.width, level.height) {
+                img
+            } else {
+                img.resize_exact(level.width, level.height, image::imageops::FilterType::Triangle)
+            };
+
+            std::fs::create_dir_all(&output_dir)?;
+            for y in 0..level.rows {
+                for x in 0..level.columns {
+                    let (left, top) = (x * tile_size, y * tile_size);
+                    let tile = img.crop_imm(
+                        left,
+                        top,
+                        tile_size.min(level.width - left),
+                        tile_size.min(level.height - top),
+                    );
+                    write_atomically(&output_dir.join(format!("{}_{}.webp", x, y)), &encode_webp(&tile, webp_quality))?;
+                }
+            }
+
+            info!("Generated {} tiles of level {} for {:?}", level.columns * level.rows, level.level, input_path);
+            Ok(())
         })
         .await
+        .map_err(|_| AppError::Internal("Failed to execute tile generation task".to_string()))?
+    }
+
+    /// Get image dimensions as displayed, see `display_dimensions`
+    pub async fn get_dimensions(&self, path: &Path) -> Result<(u32, u32), AppError> {
+        let path = path.to_owned();
+        
+        tokio::task::spawn_blocking(move || display_dimensions(&path))
+        .await
         .map_err(|_| AppError::Internal("Failed to execute image dimensions task".to_string()))?
     }
 }
+
+/// Levels of the deep-zoom pyramid of a `width` x `height` image, smallest first
+pub fn tile_pyramid(width: u32, height: u32, tile_size: u32) -> Vec<TileLevel> {
+    // ceil(log2(longest side)): the level where the image is a single pixel is 0
+    let max_level = u32::BITS - (width.max(height).max(1) - 1).leading_zeros();
+    (0..=max_level)
+        .map(|level| {
+            let shift = max_level - level;
+            let scaled = |size: u32| (((size as u64) + (1u64 << shift) - 1) >> shift) as u32;
+            let (level_width, level_height) = (scaled(width).max(1), scaled(height).max(1));
+            TileLevel {
+                level,
+                width: level_width,
+                height: level_height,
+                columns: level_width.div_ceil(tile_size),
+                rows: level_height.div_ceil(tile_size),
+            }
+        })
+        .collect()
+}
+
+/// Encode an image in `format`, JPEGs at `jpeg_quality`
+fn encode_as(img: &DynamicImage, format: ImageFormat, jpeg_quality: u8) -> Result<Vec<u8>, AppError> {
+    let mut output = Cursor::new(Vec::new());
+    if format == ImageFormat::Jpeg {
+        let encoder = JpegEncoder::new_with_quality(&mut output, jpeg_quality);
+        img.to_rgb8().write_with_encoder(encoder)?;
+    } else {
+        img.write_to(&mut output, format)?;
+    }
+    Ok(output.into_inner())
+}
+
+/// Scale an image so its longest side is `size`, keeping its aspect ratio
+fn resize_thumbnail(img: &DynamicImage, size: u32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    let aspect_ratio = width as f32 / height as f32;
+    
+    let (thumb_width, thumb_height) = if aspect_ratio > 1.0 {
+        // Landscape
+        (size, (size as f32 / aspect_ratio) as u32)
+    } else {
+        // Portrait or square
+        ((size as f32 * aspect_ratio) as u32, size)
+    };
+    
+    img.resize(
+        thumb_width.max(1),
+        thumb_height.max(1),
+        image::imageops::FilterType::Lanczos3,
+    )
+}
+
+/// Write a thumbnail as `format` (see `THUMBNAIL_FORMAT`). WebP has no
+/// progressive or interlaced mode, so such a thumbnail only renders once fully loaded.
+fn write_thumbnail(thumbnail: &DynamicImage, path: &Path, format: &str, jpeg_quality: u8, webp_quality: f32) -> Result<(), AppError> {
+    match format {
+        // JPEG has no alpha channel, transparent areas come out black
+        "jpeg" => {
+            let mut output = std::fs::File::create(path)?;
+            let encoder = JpegEncoder::new_with_quality(&mut output, jpeg_quality);
+            thumbnail.to_rgb8().write_with_encoder(encoder)?;
+        }
+        "png" => thumbnail.save_with_format(path, ImageFormat::Png)?,
+        _ => std::fs::write(path, encode_webp(thumbnail, webp_quality))?,
+    }
+    Ok(())
+}
+
+/// Encode an image as lossy WebP, keeping its alpha channel if it has one
+fn encode_webp(img: &DynamicImage, quality: f32) -> Vec<u8> {
+    if img.color().has_alpha() {
+        let rgba = img.to_rgba8();
+        webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(quality).to_vec()
+    } else {
+        let rgb = img.to_rgb8();
+        webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode(quality).to_vec()
+    }
+}
+
+/// Interpolate WebP quality on the source's pixel count, on a log scale:
+/// artifacts show more on small images, bandwidth matters more for large ones
+fn adaptive_quality(min: f32, max: f32, width: u32, height: u32) -> f32 {
+    let pixels = (width as f32 * height as f32).max(1.0);
+    let position = (pixels.ln() - ADAPTIVE_QUALITY_SMALL_PIXELS.ln())
+        / (ADAPTIVE_QUALITY_LARGE_PIXELS.ln() - ADAPTIVE_QUALITY_SMALL_PIXELS.ln());
+    max - position.clamp(0.0, 1.0) * (max - min)
+}
+
+/// Dimensions of an image as browsers display it, upright according to its
+/// EXIF orientation, without loading the full image
+pub fn display_dimensions(path: &Path) -> Result<(u32, u32), AppError> {
+    let mut decoder = ImageReader::open(path)?.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    let transposed = matches!(
+        decoder.orientation()?,
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+    );
+    Ok(if transposed { (height, width) } else { (width, height) })
+}
+
+/// Decode an image, upright when `auto_orient` is set.
+/// Color profiles are dropped; a warning is logged for profiles other than sRGB.
+fn open_image(path: &Path, auto_orient: bool) -> Result<DynamicImage, AppError> {
+    let mut decoder = ImageReader::open(path)?.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    if let Some(profile) = decoder.icc_profile().ok().flatten() {
+        if !is_srgb_profile(&profile) {
+            warn!("Ignoring the non-sRGB color profile of {:?}, colors of its derivatives may shift", path);
+        }
+    }
+
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    if auto_orient {
+        img.apply_orientation(orientation);
+    }
+    Ok(img)
+}
+
+/// Whether an ICC profile describes sRGB, judging by its description
+/// (ASCII in v2 profiles, UTF-16 in v4 ones)
+fn is_srgb_profile(profile: &[u8]) -> bool {
+    let ascii = b"sRGB";
+    let utf16: Vec<u8> = ascii.iter().flat_map(|&byte| [0, byte]).collect();
+    profile.windows(ascii.len()).any(|window| window == ascii)
+        || profile.windows(utf16.len()).any(|window| window == utf16.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use image::{Rgb, RgbImage};
+
+    /// An opaque image of pseudo-random noise, which lossy encoders can't shrink for free
+    fn noise(width: u32, height: u32) -> DynamicImage {
+        let mut state: u32 = 0x2545_f491;
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |_, _| {
+            let mut channel = || {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            };
+            Rgb([channel(), channel(), channel()])
+        }))
+    }
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    /// A 32x16 JPEG, red on its left half and blue on its right half,
+    /// with an EXIF orientation of 6 (displayed rotated 90° clockwise)
+    fn rotated_jpeg() -> Vec<u8> {
+        let img = RgbImage::from_fn(32, 16, |x, _| if x < 16 { RED } else { BLUE });
+        let mut jpeg = Cursor::new(Vec::new());
+        img.write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+
+        // Big-endian TIFF header, then one IFD holding the orientation tag
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        with_segment(jpeg.into_inner(), 0xe1, &exif)
+    }
+
+    /// Insert an APPn segment right after the SOI marker of a JPEG
+    fn with_segment(jpeg: Vec<u8>, marker: u8, payload: &[u8]) -> Vec<u8> {
+        let length = (payload.len() + 2) as u16;
+        let mut output = jpeg[..2].to_vec();
+        output.extend_from_slice(&[0xff, marker]);
+        output.extend_from_slice(&length.to_be_bytes());
+        output.extend_from_slice(payload);
+        output.extend_from_slice(&jpeg[2..]);
+        output
+    }
+
+    fn is_close(pixel: Rgb<u8>, expected: Rgb<u8>) -> bool {
+        pixel.0.iter().zip(expected.0).all(|(&a, b)| a.abs_diff(b) < 48)
+    }
+
+    #[test]
+    fn rotated_image_opens_upright() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rotated.jpg");
+        std::fs::write(&path, rotated_jpeg()).unwrap();
+
+        assert_eq!(display_dimensions(&path).unwrap(), (16, 32));
+
+        let upright = open_image(&path, true).unwrap().to_rgb8();
+        assert_eq!(upright.dimensions(), (16, 32));
+        // The left half of the stored pixels ends up on top
+        assert!(is_close(*upright.get_pixel(8, 4), RED), "{:?}", upright.get_pixel(8, 4));
+        assert!(is_close(*upright.get_pixel(8, 28), BLUE), "{:?}", upright.get_pixel(8, 28));
+
+        let stored = open_image(&path, false).unwrap().to_rgb8();
+        assert_eq!(stored.dimensions(), (32, 16));
+        assert!(is_close(*stored.get_pixel(4, 8), RED), "{:?}", stored.get_pixel(4, 8));
+    }
+
+    #[test]
+    fn thumbnail_size_follows_webp_quality() {
+        let dir = tempfile::tempdir().unwrap();
+        let thumbnail = noise(128, 128);
+        let sizes: Vec<u64> = [10.0, 50.0, 95.0]
+            .into_iter()
+            .map(|quality| {
+                let path = dir.path().join(format!("thumb_{}.webp", quality));
+                write_thumbnail(&thumbnail, &path, "webp", 85, quality).unwrap();
+                assert_eq!(image::open(&path).unwrap().dimensions(), (128, 128));
+                std::fs::metadata(&path).unwrap().len()
+            })
+            .collect();
+        assert!(sizes.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", sizes);
+    }
+
+    /// A JPEG tagged with an ICC profile described as Display P3
+    fn wide_gamut_jpeg() -> Vec<u8> {
+        let img = RgbImage::from_fn(32, 16, |x, _| if x < 16 { RED } else { BLUE });
+        let mut jpeg = Cursor::new(Vec::new());
+        img.write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+
+        // Single-chunk ICC segment: identifier, chunk number, chunk count, profile
+        let mut icc = b"ICC_PROFILE\0\x01\x01".to_vec();
+        icc.extend_from_slice(&[0; 128]);
+        icc.extend_from_slice(b"desc\0\0\0\0\0\0\0\x0aDisplay P3\0");
+        with_segment(jpeg.into_inner(), 0xe2, &icc)
+    }
+
+    async fn qoi_copy(source: Vec<u8>) -> (u32, u32, RgbImage) {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("source.jpg"), dir.path().join("source.qoi"));
+        std::fs::write(&input, source).unwrap();
+
+        let processor = ImageProcessor::new(AppConfig::default().image);
+        let (width, height) = processor.convert_to_qoi(&input, &output).await.unwrap();
+        let (header, pixels) = qoi::decode_to_vec(std::fs::read(&output).unwrap()).unwrap();
+        assert_eq!((header.width, header.height), (width, height));
+        let rgba = image::RgbaImage::from_raw(width, height, pixels).unwrap();
+        (width, height, DynamicImage::ImageRgba8(rgba).to_rgb8())
+    }
+
+    #[tokio::test]
+    async fn qoi_copy_of_rotated_image_is_upright() {
+        let (width, height, pixels) = qoi_copy(rotated_jpeg()).await;
+        assert_eq!((width, height), (16, 32));
+        assert!(is_close(*pixels.get_pixel(8, 4), RED), "{:?}", pixels.get_pixel(8, 4));
+        assert!(is_close(*pixels.get_pixel(8, 28), BLUE), "{:?}", pixels.get_pixel(8, 28));
+    }
+
+    #[tokio::test]
+    async fn qoi_copy_of_wide_gamut_image_keeps_its_pixels() {
+        let source = wide_gamut_jpeg();
+        let profile = ImageReader::new(Cursor::new(&source)).with_guessed_format().unwrap()
+            .into_decoder().unwrap()
+            .icc_profile().unwrap()
+            .expect("the source should carry its ICC profile");
+        assert!(!is_srgb_profile(&profile));
+        assert!(is_srgb_profile(b"desc\0\0\0\0\0\0\0\x0csRGB IEC61966-2.1"));
+
+        // Pixels are stored as is, without converting them to sRGB
+        let (width, height, pixels) = qoi_copy(source).await;
+        assert_eq!((width, height), (32, 16));
+        assert!(is_close(*pixels.get_pixel(4, 8), RED), "{:?}", pixels.get_pixel(4, 8));
+        assert!(is_close(*pixels.get_pixel(28, 8), BLUE), "{:?}", pixels.get_pixel(28, 8));
+    }
+
+}
@@ -0,0 +1,198 @@
+use chrono::Utc;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+use crate::models::LogLine;
+
+/// Lines kept until the configuration is loaded
+pub const DEFAULT_LOG_BUFFER_LINES: usize = 1000;
+
+/// Lines a slow stream client may fall behind before skipping ahead
+const LIVE_CHANNEL_CAPACITY: usize = 256;
+
+/// Words that mark a field or `key=value` pair as sensitive
+const SENSITIVE_KEYS: &[&str] = &["password", "secret", "token", "authorization", "cookie", "api_key", "signature"];
+
+const REDACTED: &str = "[redacted]";
+
+/// Recent application log lines, kept in memory for the admin log stream
+#[derive(Clone)]
+pub struct LogBuffer {
+    inner: Arc<LogBufferInner>,
+}
+
+struct LogBufferInner {
+    lines: Mutex<VecDeque<LogLine>>,
+    capacity: AtomicUsize,
+    live: broadcast::Sender<LogLine>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(LogBufferInner {
+                lines: Mutex::new(VecDeque::new()),
+                capacity: AtomicUsize::new(DEFAULT_LOG_BUFFER_LINES),
+                live: broadcast::channel(LIVE_CHANNEL_CAPACITY).0,
+            }),
+        }
+    }
+
+    /// Change the number of lines kept, 0 disables the buffer
+    pub fn set_capacity(&self, capacity: usize) {
+        self.inner.capacity.store(capacity, Ordering::Relaxed);
+        let mut lines = self.inner.lines.lock().unwrap_or_else(|e| e.into_inner());
+        let excess = lines.len().saturating_sub(capacity);
+        lines.drain(..excess);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.capacity.load(Ordering::Relaxed) > 0
+    }
+
+    /// The buffered lines, oldest first, and a receiver for the lines logged after them
+    pub fn subscribe(&self) -> (Vec<LogLine>, broadcast::Receiver<LogLine>) {
+        let lines = self.inner.lines.lock().unwrap_or_else(|e| e.into_inner());
+        (lines.iter().cloned().collect(), self.inner.live.subscribe())
+    }
+
+    fn push(&self, line: LogLine) {
+        let capacity = self.inner.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return;
+        }
+        // Sent under the lock, so a subscriber never misses or repeats a line
+        let mut lines = self.inner.lines.lock().unwrap_or_else(|e| e.into_inner());
+        while lines.len() >= capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line.clone());
+        // Fails only when nobody is streaming
+        let _ = self.inner.live.send(line);
+    }
+
+    /// Tracing layer feeding this buffer, next to the stdout layer
+    pub fn layer(&self) -> LogBufferLayer {
+        LogBufferLayer { buffer: self.clone() }
+    }
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct LogBufferLayer {
+    buffer: LogBuffer,
+}
+
+impl<S: Subscriber> Layer<S> for LogBufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if !self.buffer.is_enabled() {
+            return;
+        }
+
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        let mut message = redact(&visitor.message);
+        if !visitor.fields.is_empty() {
+            let _ = write!(message, " {}", visitor.fields.join(" "));
+        }
+
+        self.buffer.push(LogLine {
+            timestamp: Utc::now(),
+            level: metadata.level().to_string(),
+            // Records bridged from the `log` crate carry their target as a field
+            target: visitor.log_target.unwrap_or_else(|| metadata.target().to_string()),
+            message,
+        });
+    }
+}
+
+/// Collects the message and fields of an event, redacting sensitive values
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: Vec<String>,
+    log_target: Option<String>,
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            "log.target" => self.log_target = Some(value.to_string()),
+            name if name.starts_with("log.") => {}
+            name if is_sensitive(name) => self.fields.push(format!("{}={}", name, REDACTED)),
+            name => self.fields.push(format!("{}={}", name, redact(value))),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            name if name.starts_with("log.") => {}
+            name if is_sensitive(name) => self.fields.push(format!("{}={}", name, REDACTED)),
+            name => self.fields.push(format!("{}={}", name, redact(&format!("{:?}", value)))),
+        }
+    }
+}
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SENSITIVE_KEYS.iter().any(|key| name.contains(key))
+}
+
+/// Mask credentials in a log message: `Bearer`/`Basic` credentials, JWTs and
+/// the values of sensitive `key=value` or `key: value` pairs
+fn redact(text: &str) -> String {
+    let is_separator = |c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '&' | '?' | ',' | ';');
+    let mut output = String::with_capacity(text.len());
+    let mut redact_next = false;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let separators = rest.find(|c: char| !is_separator(c)).unwrap_or(rest.len());
+        output.push_str(&rest[..separators]);
+        rest = &rest[separators..];
+        let end = rest.find(is_separator).unwrap_or(rest.len());
+        let (word, tail) = rest.split_at(end);
+        rest = tail;
+        if word.is_empty() {
+            continue;
+        }
+
+        if redact_next {
+            output.push_str(REDACTED);
+            redact_next = false;
+        } else if word.starts_with("eyJ") && word.matches('.').count() == 2 {
+            output.push_str(REDACTED);
+        } else if word.eq_ignore_ascii_case("bearer") || word.eq_ignore_ascii_case("basic") {
+            output.push_str(word);
+            redact_next = true;
+        } else if let Some(split) = word.find(['=', ':']) {
+            let (key, value) = word.split_at(split + 1);
+            if is_sensitive(key) {
+                output.push_str(key);
+                if value.is_empty() {
+                    redact_next = true;
+                } else {
+                    output.push_str(REDACTED);
+                }
+            } else {
+                output.push_str(word);
+            }
+        } else {
+            output.push_str(word);
+        }
+    }
+    output
+}
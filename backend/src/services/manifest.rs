@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+
+use crate::services::folder_manager::{FileMetadata, FolderMetadata};
+
+/// Name of the manifest entry at the root of export archives
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Current manifest format version
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Folder tree and file metadata of an export, so an import can restore
+/// what the archive's paths alone can't carry (ids, dates, original names...)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Manifest {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub folders: Vec<FolderMetadata>,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ManifestFile {
+    /// Path of the file inside the archive
+    pub path: String,
+    pub metadata: FileMetadata,
+}
+
+impl Manifest {
+    pub fn new(folders: Vec<FolderMetadata>, files: Vec<ManifestFile>) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            exported_at: Utc::now(),
+            folders,
+            files,
+        }
+    }
+
+    /// Folders by their path in the archive (names joined with `/`)
+    pub fn folders_by_path(&self) -> HashMap<String, &FolderMetadata> {
+        let by_id: HashMap<&str, &FolderMetadata> = self.folders
+            .iter()
+            .map(|folder| (folder.id.as_str(), folder))
+            .collect();
+
+        self.folders
+            .iter()
+            .filter_map(|folder| {
+                let mut components = vec![folder.name.as_str()];
+                let mut parent_id = folder.parent_id.as_deref();
+                while let Some(id) = parent_id {
+                    // Folders left out of the export (like "root") don't appear in paths
+                    let Some(parent) = by_id.get(id) else {
+                        break;
+                    };
+                    // Guard against cycles in a hand-edited manifest
+                    if components.len() > by_id.len() {
+                        return None;
+                    }
+                    components.push(parent.name.as_str());
+                    parent_id = parent.parent_id.as_deref();
+                }
+                components.reverse();
+                Some((components.join("/"), folder))
+            })
+            .collect()
+    }
+}
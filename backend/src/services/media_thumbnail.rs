@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::error::AppError;
+
+/// Longest a renderer may run before the file is left without a thumbnail
+const RENDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether this build renders thumbnails of files of this type: PDFs with the
+/// `pdf-thumbnails` feature (`pdftoppm` from poppler-utils), videos with the
+/// `video-thumbnails` feature (`ffmpeg`)
+pub fn is_supported(mime_type: &str) -> bool {
+    (cfg!(feature = "pdf-thumbnails") && mime_type == "application/pdf")
+        || (cfg!(feature = "video-thumbnails") && mime_type.starts_with("video/"))
+}
+
+/// Render the first page of a PDF, or a representative frame of a video, as a
+/// PNG in `output_dir`, at most `size` pixels on its longest side
+pub async fn render_frame(input_path: &Path, mime_type: &str, size: u32, output_dir: &Path) -> Result<PathBuf, AppError> {
+    let output_path = output_dir.join("frame.png");
+    let program = if mime_type == "application/pdf" { "pdftoppm" } else { "ffmpeg" };
+    let mut command = Command::new(program);
+    if program == "pdftoppm" {
+        command
+            .args(["-f", "1", "-l", "1", "-singlefile", "-png", "-scale-to"])
+            .arg(size.to_string())
+            .arg(input_path)
+            // pdftoppm adds the extension
+            .arg(output_dir.join("frame"));
+    } else {
+        command
+            .args(["-v", "error", "-nostdin", "-y", "-i"])
+            .arg(input_path)
+            // `thumbnail` picks the most representative of the first frames, skipping black intros
+            .arg("-vf")
+            .arg(format!("thumbnail,scale={0}:{0}:force_original_aspect_ratio=decrease", size))
+            .args(["-frames:v", "1"])
+            .arg(&output_path);
+    }
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::piped()).kill_on_drop(true);
+
+    let output = tokio::time::timeout(RENDER_TIMEOUT, command.output())
+        .await
+        .map_err(|_| AppError::Internal(format!("Rendering {:?} timed out", input_path)))?
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::Internal(format!("{} is not installed", program)),
+            _ => e.into(),
+        })?;
+    if !output.status.success() || !output_path.is_file() {
+        return Err(AppError::Internal(format!(
+            "Rendering {:?} failed: {}",
+            input_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output_path)
+}
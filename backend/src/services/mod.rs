@@ -2,3 +2,11 @@ pub mod image_processor;
 pub mod file_utils;
 pub mod folder_manager;
 pub mod file_upload;
+pub mod storage_scope;
+pub mod manifest;
+pub mod credentials;
+pub mod log_buffer;
+pub mod media_thumbnail;
+pub mod resumable_upload;
+pub mod signed_url;
+pub mod shard_layout;
@@ -0,0 +1,145 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::services::file_upload::STAGING_DIR;
+
+/// Prefix of the staged data and state files of resumable uploads
+const FILE_PREFIX: &str = "resumable-";
+
+/// A resumable upload in progress, saved next to its data in the staging dir
+/// so clients can continue it after reconnecting or a server restart.
+/// The current offset is the size of the data received so far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingUpload {
+    pub filename: String,
+    /// Total size of the file, in bytes
+    pub length: u64,
+    /// Target folder as sent at creation, `root` included
+    pub folder_id: Option<String>,
+    pub uploaded_at: Option<DateTime<Utc>>,
+    /// Storage the file goes to, only requests in the same storage may continue it
+    pub upload_dir: PathBuf,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PendingUpload {
+    /// When the upload is discarded if not completed
+    pub fn expires_at(&self, expiry_hours: u64) -> DateTime<Utc> {
+        self.created_at + Duration::hours(expiry_hours as i64)
+    }
+}
+
+/// Staged data of an upload
+pub fn data_path(upload_dir: &str, upload_id: &str) -> PathBuf {
+    Path::new(upload_dir).join(STAGING_DIR).join(format!("{}{}", FILE_PREFIX, upload_id))
+}
+
+/// Saved state of an upload
+fn state_path(upload_dir: &str, upload_id: &str) -> PathBuf {
+    data_path(upload_dir, upload_id).with_extension("json")
+}
+
+/// Start an upload with no data received yet, returning its ID
+pub fn create(upload_dir: &str, upload: &PendingUpload) -> Result<String, AppError> {
+    let upload_id = Uuid::new_v4().to_string();
+    let data_path = data_path(upload_dir, &upload_id);
+    fs::create_dir_all(data_path.parent().unwrap_or(Path::new(upload_dir)))?;
+    fs::File::create_new(&data_path)?;
+
+    let state = serde_json::to_vec(upload)
+        .map_err(|e| AppError::Internal(format!("Failed to serialize upload state: {}", e)))?;
+    if let Err(e) = fs::write(state_path(upload_dir, &upload_id), state) {
+        let _ = fs::remove_file(&data_path);
+        return Err(e.into());
+    }
+    Ok(upload_id)
+}
+
+/// State of an upload that is still in progress
+pub fn load(upload_dir: &str, upload_id: &str) -> Result<PendingUpload, AppError> {
+    let not_found = || AppError::NotFound(format!("Upload {}", upload_id));
+    // Also keeps arbitrary paths out of the staging dir
+    Uuid::parse_str(upload_id).map_err(|_| not_found())?;
+    let state = fs::read(state_path(upload_dir, upload_id)).map_err(|_| not_found())?;
+    serde_json::from_slice(&state)
+        .map_err(|e| AppError::Internal(format!("Failed to parse the state of upload {}: {}", upload_id, e)))
+}
+
+/// Bytes received so far
+pub fn offset(upload_dir: &str, upload_id: &str) -> Result<u64, AppError> {
+    Ok(fs::metadata(data_path(upload_dir, upload_id))?.len())
+}
+
+/// Forget an upload; its data is left to the caller when `keep_data` is set
+pub fn remove(upload_dir: &str, upload_id: &str, keep_data: bool) {
+    let mut paths = vec![state_path(upload_dir, upload_id)];
+    if !keep_data {
+        paths.push(data_path(upload_dir, upload_id));
+    }
+    for path in paths {
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Discard uploads started more than `expiry_hours` ago
+pub fn purge_expired(upload_dir: &str, expiry_hours: u64) {
+    let Ok(entries) = fs::read_dir(Path::new(upload_dir).join(STAGING_DIR)) else {
+        return;
+    };
+    let now = Utc::now();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(upload_id) = name.to_str()
+            .and_then(|name| name.strip_prefix(FILE_PREFIX))
+            .and_then(|name| name.strip_suffix(".json")) else {
+            continue;
+        };
+        match load(upload_dir, upload_id) {
+            Ok(upload) if upload.expires_at(expiry_hours) > now => {}
+            // Uploads in progress are left alone, they are expired on a later call
+            _ if is_busy(upload_id) => {}
+            _ => {
+                info!("Discarding expired upload {}", upload_id);
+                remove(upload_dir, upload_id, false);
+            }
+        }
+    }
+}
+
+/// IDs of the uploads currently receiving data
+fn busy_uploads() -> &'static Mutex<HashSet<String>> {
+    static BUSY: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    BUSY.get_or_init(Default::default)
+}
+
+fn is_busy(upload_id: &str) -> bool {
+    busy_uploads().lock().unwrap_or_else(|e| e.into_inner()).contains(upload_id)
+}
+
+/// Exclusive access to an upload while data is appended, released on drop
+pub struct UploadGuard(String);
+
+impl UploadGuard {
+    /// `None` if another request is already appending to the upload
+    pub fn acquire(upload_id: &str) -> Option<Self> {
+        let mut busy = busy_uploads().lock().unwrap_or_else(|e| e.into_inner());
+        busy.insert(upload_id.to_string()).then(|| Self(upload_id.to_string()))
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        busy_uploads().lock().unwrap_or_else(|e| e.into_inner()).remove(&self.0);
+    }
+}
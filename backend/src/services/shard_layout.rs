@@ -0,0 +1,122 @@
+use std::fs;
+use std::io;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use crate::config::ServerConfig;
+use crate::services::file_utils::original_stem;
+
+/// Characters in the name of each shard subdirectory
+const SHARD_NAME_LENGTH: usize = 2;
+
+/// Stands in for the characters of a name that can't appear in a shard name,
+/// and pads names too short to fill every level
+const SHARD_FILLER: char = '_';
+
+/// How the files of a storage directory are spread over subdirectories, so no
+/// single directory grows to tens of thousands of entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardLayout {
+    /// Every file directly in the storage directory
+    Flat,
+    /// Nested subdirectories named after the first characters of the file name, e.g. `ph/ot/photo.png`
+    Name { levels: usize },
+    /// Nested subdirectories named after the first characters of the name's BLAKE3 hash, e.g. `3f/a0/photo.png`
+    Hash { levels: usize },
+}
+
+impl ShardLayout {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let levels = config.upload_shard_levels;
+        match config.upload_sharding.as_str() {
+            "name" => Self::Name { levels },
+            "hash" => Self::Hash { levels },
+            _ => Self::Flat,
+        }
+    }
+
+    fn levels(&self) -> usize {
+        match *self {
+            Self::Flat => 0,
+            Self::Name { levels } | Self::Hash { levels } => levels,
+        }
+    }
+
+    /// Subdirectory a file is stored in, `None` when flat.
+    ///
+    /// Derivatives and gzip companions are keyed on the stem of the file they
+    /// were made from, so they land next to it.
+    pub fn subdir(&self, filename: &str) -> Option<PathBuf> {
+        let stem = original_stem(filename);
+        let prefix: Vec<char> = match *self {
+            Self::Flat => return None,
+            Self::Name { .. } => stem.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { SHARD_FILLER })
+                .collect(),
+            Self::Hash { .. } => blake3::hash(stem.as_bytes()).to_hex().chars().collect(),
+        };
+
+        let mut chars = prefix.into_iter().chain(iter::repeat(SHARD_FILLER));
+        Some((0..self.levels())
+            .map(|_| chars.by_ref().take(SHARD_NAME_LENGTH).collect::<String>())
+            .collect())
+    }
+}
+
+/// Whether a directory entry may be a shard subdirectory
+fn is_shard_name(name: &str) -> bool {
+    name.len() == SHARD_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == SHARD_FILLER)
+}
+
+/// A storage directory and the layout of the files stored in it
+#[derive(Debug, Clone)]
+pub struct StorageDir {
+    root: PathBuf,
+    layout: ShardLayout,
+}
+
+impl StorageDir {
+    pub fn new(root: impl Into<PathBuf>, layout: ShardLayout) -> Self {
+        Self {
+            root: root.into(),
+            layout,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path of a stored file. Files stored before sharding was enabled keep
+    /// resolving at the top of the directory; other files, including those not
+    /// stored yet, are in their shard subdirectory.
+    pub fn path(&self, filename: &str) -> PathBuf {
+        let flat = self.root.join(filename);
+        match self.layout.subdir(filename) {
+            Some(subdir) if !flat.exists() => self.root.join(subdir).join(filename),
+            _ => flat,
+        }
+    }
+
+    /// Stored files, at the top of the directory and in its shard subdirectories
+    pub fn files(&self) -> io::Result<Vec<fs::DirEntry>> {
+        let mut files = Vec::new();
+        collect_files(&self.root, self.layout.levels(), &mut files)?;
+        Ok(files)
+    }
+}
+
+/// Files of `dir` and of its shard subdirectories up to `levels` deep
+fn collect_files(dir: &Path, levels: usize, files: &mut Vec<fs::DirEntry>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_file() {
+            files.push(entry);
+        } else if file_type.is_dir() && levels > 0 && entry.file_name().to_str().is_some_and(is_shard_name) {
+            collect_files(&entry.path(), levels - 1, files)?;
+        }
+    }
+    Ok(())
+}
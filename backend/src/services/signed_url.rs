@@ -0,0 +1,26 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC of a static file path and the Unix time its link expires at
+fn mac(key: &str, path: &str, expires: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+/// Signature letting anyone fetch `path` (e.g. `/uploads/photo.png`) until `expires`
+pub fn sign(key: &str, path: &str, expires: i64) -> String {
+    URL_SAFE_NO_PAD.encode(mac(key, path, expires).finalize().into_bytes())
+}
+
+/// Whether `signature` was made by `sign` for this path and expiry, compared in constant time
+pub fn verify(key: &str, path: &str, expires: i64, signature: &str) -> bool {
+    URL_SAFE_NO_PAD.decode(signature)
+        .is_ok_and(|signature| mac(key, path, expires).verify_slice(&signature).is_ok())
+}
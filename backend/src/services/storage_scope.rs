@@ -0,0 +1,158 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use futures::future::{ready, Ready};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::middleware::auth::{AuthenticatedUser, PublicRead};
+use crate::services::file_utils::FileManager;
+use crate::services::folder_manager::FolderManager;
+use crate::services::shard_layout::ShardLayout;
+
+/// Subdirectory of the upload dir holding per-user storage
+pub const USERS_DIR: &str = "users";
+
+#[derive(Deserialize)]
+struct ScopeQuery {
+    as_user: Option<String>,
+}
+
+/// Storage namespace a request operates in.
+///
+/// With `PER_USER_STORAGE` enabled, authenticated users get their own upload
+/// subdirectory (and metadata files) under `users/<username>`, and other
+/// requests are rejected except public gallery reads, which use the shared
+/// upload directory like all requests when the option is off or in local mode.
+/// The admin may act on another existing user's storage with the `as_user`
+/// query parameter.
+#[derive(Debug, Clone)]
+pub struct StorageScope {
+    pub upload_dir: PathBuf,
+    url_subdir: Option<String>,
+    static_base_url: String,
+    thumbnail_variant_sizes: Vec<u32>,
+    shard_layout: ShardLayout,
+}
+
+impl StorageScope {
+    /// The shared upload directory
+    pub fn shared(config: &AppConfig, static_base_url: String) -> Self {
+        Self {
+            upload_dir: PathBuf::from(&config.server.upload_dir),
+            url_subdir: None,
+            static_base_url,
+            thumbnail_variant_sizes: config.image.thumbnail_variant_sizes.clone(),
+            shard_layout: ShardLayout::from_config(&config.server),
+        }
+    }
+
+    /// Storage of a single user, created on first use
+    pub fn for_user(config: &AppConfig, username: &str, static_base_url: String) -> Result<Self, AppError> {
+        let scope = Self::user_scope(config, username, static_base_url)?;
+        std::fs::create_dir_all(&scope.upload_dir)?;
+        Ok(scope)
+    }
+
+    /// Storage of another user, which must already exist
+    fn existing_user(config: &AppConfig, username: &str, static_base_url: String) -> Result<Self, AppError> {
+        let scope = Self::user_scope(config, username, static_base_url)?;
+        if !scope.upload_dir.is_dir() {
+            return Err(AppError::NotFound(format!("No storage for user: {}", username)));
+        }
+        Ok(scope)
+    }
+
+    fn user_scope(config: &AppConfig, username: &str, static_base_url: String) -> Result<Self, AppError> {
+        if !is_valid_username(username) {
+            return Err(AppError::BadRequest(format!("Invalid username for storage: {}", username)));
+        }
+
+        let upload_dir = PathBuf::from(&config.server.upload_dir)
+            .join(USERS_DIR)
+            .join(username);
+
+        Ok(Self {
+            upload_dir,
+            url_subdir: Some(format!("{}/{}", USERS_DIR, username)),
+            static_base_url,
+            thumbnail_variant_sizes: config.image.thumbnail_variant_sizes.clone(),
+            shard_layout: ShardLayout::from_config(&config.server),
+        })
+    }
+
+    /// Whether this scope is the shared upload directory
+    pub fn is_shared(&self) -> bool {
+        self.url_subdir.is_none()
+    }
+
+    pub fn file_manager(&self) -> FileManager {
+        FileManager::new(&self.upload_dir, self.static_base_url.clone())
+            .with_url_subdir(self.url_subdir.clone())
+            .with_thumbnail_variant_sizes(self.thumbnail_variant_sizes.clone())
+            .with_shard_layout(self.shard_layout)
+    }
+
+    pub fn folder_manager(&self, config: &AppConfig) -> FolderManager {
+        FolderManager::new(&self.upload_dir)
+            .with_case_insensitive_names(config.server.case_insensitive_folder_names)
+            .with_breadcrumb_max_depth(config.server.breadcrumb_max_depth)
+            .with_max_total_size(config.server.max_total_size)
+    }
+
+    fn resolve(req: &HttpRequest) -> Result<Self, AppError> {
+        let config = req.app_data::<web::Data<AppConfig>>()
+            .ok_or_else(|| AppError::Internal("Missing application config".to_string()))?;
+
+        let static_base_url = {
+            let conn = req.connection_info();
+            config.static_base_url_for(conn.scheme(), conn.host())
+        };
+
+        if !config.server.per_user_storage || config.auth.mode == "local" {
+            return Ok(Self::shared(config, static_base_url));
+        }
+
+        let Some(user) = req.extensions().get::<AuthenticatedUser>().cloned() else {
+            if req.extensions().contains::<PublicRead>() {
+                return Ok(Self::shared(config, static_base_url));
+            }
+            return Err(AppError::Unauthorized(
+                "Authentication is required to access per-user storage".to_string(),
+            ));
+        };
+
+        let as_user = web::Query::<ScopeQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().as_user);
+
+        match as_user {
+            Some(target) if target != user.username => {
+                if user.username != config.auth.admin_username {
+                    return Err(AppError::Forbidden(
+                        "Only the admin can access another user's files".to_string(),
+                    ));
+                }
+                Self::existing_user(config, &target, static_base_url)
+            }
+            _ => Self::for_user(config, &user.username, static_base_url),
+        }
+    }
+}
+
+impl FromRequest for StorageScope {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Self::resolve(req))
+    }
+}
+
+/// Usernames become directory names, so keep them to a safe character set
+fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 64
+        && !username.starts_with('.')
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
@@ -0,0 +1,95 @@
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG chunks dropped: EXIF, text (which also carries XMP) and modification time
+const PNG_METADATA_CHUNKS: &[&[u8]] = &[b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// Flags of the WebP extended header announcing EXIF and XMP chunks
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+/// JPEG without its APP1 (EXIF, XMP), APP13 (IPTC) and comment segments,
+/// `None` if it isn't well formed. Color profiles (APP2) are kept.
+pub fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut output = data[..2].to_vec();
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        // Markers may be preceded by fill bytes
+        let mut marker_pos = pos;
+        while *data.get(marker_pos + 1)? == 0xFF {
+            marker_pos += 1;
+        }
+        let marker = data[marker_pos + 1];
+        match marker {
+            // Start of scan or end of image: the rest is image data
+            0xDA | 0xD9 => {
+                output.extend_from_slice(&data[pos..]);
+                return Some(output);
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                output.extend_from_slice(&data[pos..marker_pos + 2]);
+                pos = marker_pos + 2;
+            }
+            _ => {
+                let length = u16::from_be_bytes([*data.get(marker_pos + 2)?, *data.get(marker_pos + 3)?]) as usize;
+                let end = marker_pos + 2 + length;
+                if length < 2 || end > data.len() {
+                    return None;
+                }
+                if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+                    output.extend_from_slice(&data[pos..end]);
+                }
+                pos = end;
+            }
+        }
+    }
+}
+
+/// PNG without its EXIF, text and time chunks, `None` if it isn't well formed
+pub fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut rest = data.strip_prefix(PNG_SIGNATURE)?;
+    let mut output = PNG_SIGNATURE.to_vec();
+    while !rest.is_empty() {
+        // Length, type, data and CRC
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let chunk = rest.get(..12 + length)?;
+        if !PNG_METADATA_CHUNKS.contains(&&chunk[4..8]) {
+            output.extend_from_slice(chunk);
+        }
+        rest = &rest[chunk.len()..];
+    }
+    Some(output)
+}
+
+/// WebP without its EXIF and XMP chunks, `None` if it isn't well formed
+pub fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let mut output = data[..12].to_vec();
+    let mut rest = &data[12..];
+    while !rest.is_empty() {
+        // Type, size and data, padded to an even size
+        let size = u32::from_le_bytes(rest.get(4..8)?.try_into().ok()?) as usize;
+        let chunk = rest.get(..(8 + size + size % 2).min(rest.len()))?;
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let flags = output.len() + 8;
+                output.extend_from_slice(chunk);
+                *output.get_mut(flags)? &= !(WEBP_EXIF_FLAG | WEBP_XMP_FLAG);
+            }
+            _ => output.extend_from_slice(chunk),
+        }
+        rest = &rest[chunk.len()..];
+    }
+    let riff_size = u32::try_from(output.len() - 8).ok()?;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(output)
+}
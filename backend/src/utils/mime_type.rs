@@ -1,22 +1,42 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::OnceLock;
 
-/// Get MIME type based on file extension
+/// Operator-defined extension to MIME type mappings, see `CUSTOM_MIME_TYPES`
+static CUSTOM_MIME_TYPES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Register custom mappings, checked before the built-in table. Only the first call has an effect.
+pub fn set_custom_mime_types(mime_types: HashMap<String, String>) {
+    let _ = CUSTOM_MIME_TYPES.set(mime_types);
+}
+
+/// Get MIME type based on file extension, in any case
 pub fn get_mime_type(filename: &str) -> String {
     let extension = Path::new(filename)
         .extension()
         .and_then(|ext| ext.to_str())
         .map(|ext| ext.to_lowercase());
 
+    let custom = extension.as_ref().and_then(|ext| CUSTOM_MIME_TYPES.get()?.get(ext));
+    if let Some(mime_type) = custom {
+        return mime_type.clone();
+    }
+
     match extension.as_deref() {
         // Images
-        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("jpg") | Some("jpeg") | Some("jpe") | Some("jfif") | Some("pjpeg") | Some("pjp") => "image/jpeg",
         Some("png") => "image/png",
+        Some("apng") => "image/apng",
         Some("gif") => "image/gif",
         Some("webp") => "image/webp",
         Some("bmp") => "image/bmp",
         Some("tiff") | Some("tif") => "image/tiff",
         Some("svg") => "image/svg+xml",
         Some("ico") => "image/x-icon",
+        Some("heic") | Some("hif") => "image/heic",
+        Some("heif") => "image/heif",
+        Some("avif") => "image/avif",
+        Some("jxl") => "image/jxl",
         
         // Documents
         Some("pdf") => "application/pdf",
@@ -28,30 +48,33 @@ pub fn get_mime_type(filename: &str) -> String {
         Some("pptx") => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
         
         // Text
-        Some("txt") => "text/plain",
+        Some("txt") | Some("log") => "text/plain",
+        Some("md") | Some("markdown") => "text/markdown",
         Some("csv") => "text/csv",
         Some("html") | Some("htm") => "text/html",
         Some("css") => "text/css",
-        Some("js") => "application/javascript",
+        Some("js") | Some("mjs") => "application/javascript",
         Some("json") => "application/json",
         Some("xml") => "application/xml",
+        Some("yaml") | Some("yml") => "application/yaml",
         
         // Archives
         Some("zip") => "application/zip",
         Some("rar") => "application/vnd.rar",
         Some("7z") => "application/x-7z-compressed",
         Some("tar") => "application/x-tar",
-        Some("gz") => "application/gzip",
+        Some("gz") | Some("tgz") => "application/gzip",
         
         // Audio
         Some("mp3") => "audio/mpeg",
         Some("wav") => "audio/wav",
-        Some("ogg") => "audio/ogg",
+        Some("ogg") | Some("oga") | Some("opus") => "audio/ogg",
         Some("m4a") => "audio/mp4",
+        Some("aac") => "audio/aac",
         Some("flac") => "audio/flac",
         
         // Video
-        Some("mp4") => "video/mp4",
+        Some("mp4") | Some("m4v") => "video/mp4",
         Some("avi") => "video/x-msvideo",
         Some("mov") => "video/quicktime",
         Some("wmv") => "video/x-ms-wmv",
@@ -65,6 +88,12 @@ pub fn get_mime_type(filename: &str) -> String {
     .to_string()
 }
 
+/// Check if a MIME type is text-like and worth compressing
+pub fn is_compressible_mime_type(mime_type: &str) -> bool {
+    mime_type.starts_with("text/")
+        || matches!(mime_type, "image/svg+xml" | "application/json" | "application/xml" | "application/javascript")
+}
+
 /// Check if a MIME type represents an image
 #[allow(dead_code)]
 pub fn is_image_mime_type(mime_type: &str) -> bool {
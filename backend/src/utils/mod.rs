@@ -1,2 +1,5 @@
+pub mod metadata;
 pub mod mime_type;
+pub mod multipart;
 pub mod validation;
+pub mod throttle;
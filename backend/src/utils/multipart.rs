@@ -0,0 +1,112 @@
+use crate::config::ServerConfig;
+use crate::error::AppError;
+
+/// Bounds on a single multipart request, checked while its fields are read
+///
+/// Per-field size caps alone don't stop a client from sending thousands of
+/// tiny fields, so both the field count and the total body size are tracked.
+pub struct MultipartLimits {
+    max_fields: usize,
+    max_total_bytes: usize,
+    fields: usize,
+    total_bytes: usize,
+}
+
+impl MultipartLimits {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            max_fields: config.max_multipart_fields,
+            max_total_bytes: config.max_multipart_size,
+            fields: 0,
+            total_bytes: 0,
+        }
+    }
+
+    /// Account for a new field
+    pub fn add_field(&mut self) -> Result<(), AppError> {
+        self.fields += 1;
+        if self.fields > self.max_fields {
+            return Err(AppError::BadRequest(format!(
+                "Too many multipart fields (max {})",
+                self.max_fields
+            )));
+        }
+        Ok(())
+    }
+
+    /// Account for a chunk of field data
+    pub fn add_bytes(&mut self, len: usize) -> Result<(), AppError> {
+        self.total_bytes += len;
+        if self.total_bytes > self.max_total_bytes {
+            return Err(AppError::BadRequest(format!(
+                "Multipart request too large (max {} bytes)",
+                self.max_total_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, web, App};
+
+    use crate::config::AppConfig;
+    use crate::handlers::auth::JwtService;
+    use crate::handlers::files::import_files;
+    use crate::handlers::upload::upload_file;
+
+    const BOUNDARY: &str = "snapfilething-test-boundary";
+
+    /// A multipart body of `count` tiny text fields
+    fn many_fields(count: usize) -> Vec<u8> {
+        let mut body = String::new();
+        for index in 0..count {
+            body.push_str(&format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"field{index}\"\r\n\r\nx\r\n"
+            ));
+        }
+        body.push_str(&format!("--{BOUNDARY}--\r\n"));
+        body.into_bytes()
+    }
+
+    async fn post_fields(uri: &str, count: usize) -> (StatusCode, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.server.upload_dir = dir.path().to_string_lossy().into_owned();
+        let jwt_service = JwtService::new(&config.auth).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(jwt_service))
+                .service(upload_file)
+                .service(import_files)
+        ).await;
+
+        let req = test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("Content-Type", format!("multipart/form-data; boundary={BOUNDARY}")))
+            .set_payload(many_fields(count))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let status = res.status();
+        (status, String::from_utf8_lossy(&test::read_body(res).await).into_owned())
+    }
+
+    #[actix_web::test]
+    async fn upload_with_too_many_fields_is_rejected() {
+        let max_fields = AppConfig::default().server.max_multipart_fields;
+        let (status, body) = post_fields("/upload", max_fields + 1).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("Too many multipart fields"), "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn import_with_too_many_fields_is_rejected() {
+        let max_fields = AppConfig::default().server.max_multipart_fields;
+        let (status, body) = post_fields("/files/import", max_fields + 1).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("Too many multipart fields"), "{}", body);
+    }
+}
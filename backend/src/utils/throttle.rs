@@ -0,0 +1,41 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Token bucket pacing the reads of a single transfer
+///
+/// Up to one second worth of bytes goes through in a burst; past that, each
+/// chunk waits until the bucket has refilled enough to cover it. Not reading
+/// the request body lets TCP flow control slow the client down.
+pub struct Throttle {
+    bytes_per_sec: Option<f64>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: Option<u64>) -> Self {
+        let bytes_per_sec = bytes_per_sec.map(|rate| rate as f64);
+        Self {
+            bytes_per_sec,
+            tokens: bytes_per_sec.unwrap_or(0.0),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Wait until `len` more bytes may be consumed
+    pub async fn consume(&mut self, len: usize) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refill).min(rate) - len as f64;
+        self.last_refill = now;
+
+        // The debt is paid back by the refill accounted on the next call
+        if self.tokens < 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(-self.tokens / rate)).await;
+        }
+    }
+}
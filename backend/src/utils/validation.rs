@@ -1,29 +1,64 @@
+use crate::config::FilenameConfig;
 use crate::error::AppError;
+use chrono::{DateTime, Duration, Utc};
 
-const MAX_MAGIC_BYTES: usize = 12;
+/// Bytes needed to detect a file type from its signature
+pub const MAX_MAGIC_BYTES: usize = 12;
+
+/// How far in the future a client-provided upload date may be, to absorb clock skew
+const MAX_UPLOADED_AT_SKEW_HOURS: i64 = 24;
 
 /// Validate file type by checking magic numbers/file signatures
-pub fn validate_file_type(data: &[u8], filename: &str) -> Result<String, AppError> {
+///
+/// In strict mode the detected type must also agree with the type implied by
+/// the extension, for every extension with a known MIME type.
+pub fn validate_file_type(
+    data: &[u8],
+    filename: &str,
+    strict: bool,
+    trusted_extensions: &[String],
+) -> Result<String, AppError> {
+    let extension = std::path::Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    
+    // Trusted extensions skip content checks, for formats the magic table doesn't know
+    if extension.as_ref().is_some_and(|ext| trusted_extensions.contains(ext)) {
+        return Ok(crate::utils::mime_type::get_mime_type(filename));
+    }
+    
     let magic_bytes = &data[..std::cmp::min(data.len(), MAX_MAGIC_BYTES)];
     
     // Check magic numbers
     let detected_type = detect_file_type(magic_bytes);
     
     // If we couldn't detect the type and it's supposed to be an image, reject it
-    if detected_type.is_none() {
-        let extension = std::path::Path::new(filename)
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| ext.to_lowercase());
-        
-        if matches!(
-            extension.as_deref(),
-            Some("jpg") | Some("jpeg") | Some("png") | Some("gif") | 
-            Some("bmp") | Some("tiff") | Some("tif") | Some("webp")
-        ) {
-            return Err(AppError::InvalidFileType(
-                "File claims to be an image but has invalid format".to_string()
-            ));
+    if detected_type.is_none() && matches!(
+        extension.as_deref(),
+        Some("jpg") | Some("jpeg") | Some("jpe") | Some("jfif") | Some("png") | Some("apng") |
+        Some("gif") | Some("bmp") | Some("tiff") | Some("tif") | Some("webp")
+    ) {
+        return Err(AppError::InvalidFileType(
+            "File claims to be an image but has invalid format".to_string()
+        ));
+    }
+    
+    if strict {
+        let declared_type = crate::utils::mime_type::get_mime_type(filename);
+        match detected_type {
+            Some(ref detected) if declared_type != "application/octet-stream"
+                && !is_compatible_type(detected, &declared_type) => {
+                return Err(AppError::InvalidFileType(format!(
+                    "File content ({}) does not match its extension ({})", detected, declared_type
+                )));
+            }
+            None if has_magic_signature(&declared_type) => {
+                return Err(AppError::InvalidFileType(format!(
+                    "File claims to be {} but has invalid format", declared_type
+                )));
+            }
+            _ => {}
         }
     }
     
@@ -32,6 +67,39 @@ pub fn validate_file_type(data: &[u8], filename: &str) -> Result<String, AppErro
     }))
 }
 
+/// Check if a detected content type is acceptable for a declared type
+fn is_compatible_type(detected: &str, declared: &str) -> bool {
+    if detected == declared {
+        return true;
+    }
+    
+    match detected {
+        // Office documents are ZIP containers
+        "application/zip" => matches!(
+            declared,
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+                | "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+                | "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+        ),
+        // Animated PNGs keep the PNG signature
+        "image/png" => declared == "image/apng",
+        // ID3 tags can precede other audio formats
+        "audio/mpeg" => declared.starts_with("audio/"),
+        // ISO base media files share the same container signature
+        "video/mp4" => matches!(declared, "audio/mp4" | "video/quicktime"),
+        _ => false,
+    }
+}
+
+/// Check if a MIME type has a reliable signature in `detect_file_type`
+fn has_magic_signature(mime_type: &str) -> bool {
+    matches!(
+        mime_type,
+        "image/jpeg" | "image/png" | "image/gif" | "image/webp" | "image/bmp" | "image/tiff"
+            | "application/pdf" | "application/zip" | "application/vnd.rar" | "application/x-7z-compressed"
+    )
+}
+
 /// Detect file type based on magic numbers
 fn detect_file_type(data: &[u8]) -> Option<String> {
     if data.len() < 4 {
@@ -109,22 +177,45 @@ pub fn validate_file_size(size: usize, max_size: usize) -> Result<(), AppError>
     Ok(())
 }
 
+/// Parse a client-provided RFC 3339 upload date
+pub fn parse_uploaded_at(value: &str) -> Result<DateTime<Utc>, AppError> {
+    let uploaded_at = DateTime::parse_from_rfc3339(value.trim())
+        .map_err(|e| AppError::BadRequest(format!("Invalid uploaded_at, expected RFC 3339: {}", e)))?;
+    validate_uploaded_at(uploaded_at.with_timezone(&Utc))
+}
+
+/// Reject upload dates in the future, beyond clock skew
+pub fn validate_uploaded_at(uploaded_at: DateTime<Utc>) -> Result<DateTime<Utc>, AppError> {
+    if uploaded_at > Utc::now() + Duration::hours(MAX_UPLOADED_AT_SKEW_HOURS) {
+        return Err(AppError::BadRequest(format!("uploaded_at is in the future: {}", uploaded_at.to_rfc3339())));
+    }
+    Ok(uploaded_at)
+}
+
 /// Sanitize filename to prevent directory traversal attacks and normalize the name
-pub fn sanitize_filename(filename: &str) -> String {
+pub fn sanitize_filename(filename: &str, options: &FilenameConfig) -> String {
+    let transliterated;
+    let filename = if options.transliterate {
+        transliterated = deunicode::deunicode(filename);
+        transliterated.as_str()
+    } else {
+        filename
+    };
+    
     // Split filename into name and extension
     let path = std::path::Path::new(filename);
     let extension = path.extension()
         .and_then(|ext| ext.to_str())
-        .map(|ext| ext.to_lowercase())
+        .map(|ext| if options.lowercase_extension { ext.to_lowercase() } else { ext.to_string() })
         .unwrap_or_default();
     
     let name = path.file_stem()
         .and_then(|stem| stem.to_str())
         .unwrap_or(filename);
+    let name = if options.preserve_case { name.to_string() } else { name.to_lowercase() };
     
-    // Remove path separators and other dangerous characters, convert to lowercase
+    // Remove path separators and other dangerous characters
     let mut sanitized_name = name
-        .to_lowercase()
         .chars()
         .filter(|c| !matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|'))
         .collect::<String>();
@@ -133,9 +224,9 @@ pub fn sanitize_filename(filename: &str) -> String {
     sanitized_name = sanitized_name
         .chars()
         .map(|c| {
-            if c.is_whitespace() || matches!(c, ' ' | '\t' | '\n' | '\r') {
-                '_'
-            } else if c.is_ascii_punctuation() && !matches!(c, '-' | '_' | '.') {
+            if c.is_whitespace()
+                || (c.is_ascii_punctuation() && !matches!(c, '-' | '_' | '.'))
+            {
                 '_'
             } else {
                 c
@@ -153,7 +244,7 @@ pub fn sanitize_filename(filename: &str) -> String {
     
     // Ensure filename is not empty and doesn't start with a dot (hidden file)
     if sanitized_name.is_empty() || sanitized_name.starts_with('.') {
-        sanitized_name = format!("file_{}", uuid::Uuid::new_v4().to_string()[..8].to_string());
+        sanitized_name = format!("file_{}", &uuid::Uuid::new_v4().to_string()[..8]);
     }
     
     // Reconstruct filename with extension
@@ -163,3 +254,56 @@ pub fn sanitize_filename(filename: &str) -> String {
         format!("{}.{}", sanitized_name, extension)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0d";
+    const NOT_AN_IMAGE: &[u8] = b"plain text, no signature";
+    /// Start of a HEIC file: an ISO base media `ftyp` box with the `heic` brand
+    const HEIC_HEADER: &[u8] = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic";
+
+    fn trusted(extensions: &[&str]) -> Vec<String> {
+        extensions.iter().map(|ext| ext.to_string()).collect()
+    }
+
+    #[test]
+    fn trusted_extensions_skip_magic_checks() {
+        for strict in [false, true] {
+            assert_eq!(validate_file_type(NOT_AN_IMAGE, "photo.png", strict, &trusted(&["png"])).unwrap(), "image/png");
+            assert_eq!(validate_file_type(NOT_AN_IMAGE, "PHOTO.PNG", strict, &trusted(&["png"])).unwrap(), "image/png");
+            assert_eq!(validate_file_type(PNG_SIGNATURE, "report.pdf", strict, &trusted(&["pdf"])).unwrap(), "application/pdf");
+        }
+    }
+
+    #[test]
+    fn untrusted_extensions_are_still_checked() {
+        for strict in [false, true] {
+            let result = validate_file_type(NOT_AN_IMAGE, "photo.png", strict, &trusted(&["pdf"]));
+            assert!(matches!(result, Err(AppError::InvalidFileType(_))));
+        }
+        assert_eq!(validate_file_type(PNG_SIGNATURE, "photo.png", true, &trusted(&["pdf"])).unwrap(), "image/png");
+    }
+
+    #[test]
+    fn strict_mode_checks_untrusted_extensions_against_content() {
+        // Lenient mode goes by the detected content
+        assert_eq!(validate_file_type(PNG_SIGNATURE, "report.pdf", false, &[]).unwrap(), "image/png");
+
+        let mismatch = validate_file_type(PNG_SIGNATURE, "report.pdf", true, &trusted(&["png"]));
+        assert!(matches!(mismatch, Err(AppError::InvalidFileType(_))));
+        let missing_signature = validate_file_type(NOT_AN_IMAGE, "report.pdf", true, &trusted(&["png"]));
+        assert!(matches!(missing_signature, Err(AppError::InvalidFileType(_))));
+    }
+
+    #[test]
+    fn heic_is_accepted_with_default_trusted_extensions() {
+        let trusted_extensions = crate::config::AppConfig::default().server.trusted_extensions;
+        for strict in [false, true] {
+            let mime_type = validate_file_type(HEIC_HEADER, "photo.heic", strict, &trusted_extensions).unwrap();
+            assert_eq!(mime_type, "image/heic");
+        }
+    }
+
+}
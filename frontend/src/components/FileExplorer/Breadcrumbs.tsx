@@ -1,14 +1,16 @@
 import React from "react";
 import { ChevronRightIcon } from "@heroicons/react/24/outline";
-import { FolderInfo } from "../../types/api";
+import { Breadcrumb } from "../../types/api";
 
 interface BreadcrumbsProps {
-  breadcrumbs: FolderInfo[];
+  breadcrumbs: Breadcrumb[];
+  truncated?: boolean;
   onClick: (folderId?: string) => void;
 }
 
 export default function Breadcrumbs({
   breadcrumbs,
+  truncated = false,
   onClick,
 }: BreadcrumbsProps) {
   return (
@@ -19,6 +21,12 @@ export default function Breadcrumbs({
       >
         Root
       </button>
+      {truncated && (
+        <>
+          <ChevronRightIcon className="h-4 w-4" />
+          <span>…</span>
+        </>
+      )}
       {breadcrumbs.map((folder) => (
         <React.Fragment key={folder.id}>
           <ChevronRightIcon className="h-4 w-4" />
@@ -26,6 +26,7 @@ import {
 } from "@heroicons/react/24/outline";
 import type {
   FolderInfo,
+  Breadcrumb,
   CreateFolderRequest,
   MoveFileRequest,
   MoveFolderRequest,
@@ -67,7 +68,8 @@ interface ContextMenuState {
 interface ExplorerState {
   items: ExplorerItem[];
   currentFolder?: FolderInfo;
-  breadcrumbs: FolderInfo[];
+  breadcrumbs: Breadcrumb[];
+  breadcrumbsTruncated: boolean;
   loading: boolean;
   creating: boolean;
   newFolderName: string;
@@ -106,6 +108,7 @@ function FileExplorerInner({
   const [state, setState] = useState<ExplorerState>({
     items: [],
     breadcrumbs: [],
+    breadcrumbsTruncated: false,
     loading: false,
     creating: false,
     newFolderName: "",
@@ -179,6 +182,7 @@ function FileExplorerInner({
           items: finalItems,
           currentFolder: foldersResponse.current_folder,
           breadcrumbs: foldersResponse.breadcrumbs,
+          breadcrumbsTruncated: foldersResponse.breadcrumbs_truncated,
           loading: false,
           selectedItems: new Set(), // Clear selection when folder changes
         }));
@@ -814,6 +818,7 @@ function FileExplorerInner({
       />
       <Breadcrumbs
         breadcrumbs={state.breadcrumbs}
+        truncated={state.breadcrumbsTruncated}
         onClick={handleBreadcrumbClick}
       />
       {state.showCreateForm && (
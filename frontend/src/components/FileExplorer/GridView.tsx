@@ -109,7 +109,7 @@ export default function GridView({
             )}
             {/* Thumbnail or Icon */}
             <div className="w-16 h-16 flex items-center justify-center mb-2">
-              {item.type === "file" && item.isImage && item.urls?.thumbnail ? (
+              {item.type === "file" && item.urls?.thumbnail ? (
                 <img
                   src={item.urls.thumbnail}
                   alt={item.name}
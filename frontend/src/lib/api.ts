@@ -5,8 +5,16 @@ import {
   FolderListResponse,
   CreateFolderRequest,
   MoveFileRequest,
+  RenameFileRequest,
   MoveFolderRequest,
+  RenameFolderRequest,
+  FolderInfo,
   ImportResponse,
+  ImportMode,
+  FileInfo,
+  BatchDeleteResponse,
+  MoveFolderResponse,
+  TileInfo,
 } from "../types/api";
 
 // Token management
@@ -363,10 +371,17 @@ export const uploadApi = {
 // File management functions
 export const filesApi = {
   // Import files from ZIP
-  async importFiles(zipFile: File, folderId?: string): Promise<ImportResponse> {
+  async importFiles(
+    zipFile: File,
+    folderId?: string,
+    destinationFolder?: string,
+    mode?: ImportMode
+  ): Promise<ImportResponse> {
     const formData = new FormData();
     formData.append("file", zipFile);
     if (folderId) formData.append("folder_id", folderId);
+    if (destinationFolder) formData.append("destination_folder", destinationFolder);
+    if (mode) formData.append("mode", mode);
 
     const response = await authenticatedFetch(`${API_BASE}/files/import`, {
       method: "POST",
@@ -417,7 +432,9 @@ export const filesApi = {
   async listFiles(
     page = 0,
     perPage = 20,
-    folderId?: string
+    folderId?: string,
+    search?: string,
+    recursive = false
   ): Promise<FileListResponse> {
     const params = new URLSearchParams({
       page: page.toString(),
@@ -427,6 +444,12 @@ export const filesApi = {
     if (folderId) {
       params.append("folder_id", folderId);
     }
+    if (search) {
+      params.append("search", search);
+    }
+    if (recursive) {
+      params.append("recursive", "true");
+    }
 
     const response = await authenticatedFetch(
       `${API_BASE}/files?${params.toString()}`
@@ -444,6 +467,14 @@ export const filesApi = {
     await handleResponse(response);
   },
 
+  async deleteFiles(filenames: string[]): Promise<BatchDeleteResponse> {
+    const response = await authenticatedFetch(`${API_BASE}/files/delete-batch`, {
+      method: "POST",
+      body: JSON.stringify({ filenames }),
+    });
+    return handleResponse<BatchDeleteResponse>(response);
+  },
+
   async moveFile(filename: string, request: MoveFileRequest): Promise<void> {
     const response = await authenticatedFetch(
       `${API_BASE}/files/${encodeURIComponent(filename)}/move`,
@@ -454,6 +485,35 @@ export const filesApi = {
     );
     await handleResponse(response);
   },
+
+  async renameFile(filename: string, request: RenameFileRequest): Promise<FileInfo> {
+    const response = await authenticatedFetch(
+      `${API_BASE}/files/${encodeURIComponent(filename)}/rename`,
+      {
+        method: "PUT",
+        body: JSON.stringify(request),
+      }
+    );
+    return handleResponse<FileInfo>(response);
+  },
+
+  async rotateFile(filename: string, degrees: 90 | 180 | 270): Promise<FileInfo> {
+    const response = await authenticatedFetch(
+      `${API_BASE}/files/${encodeURIComponent(filename)}/rotate`,
+      {
+        method: "POST",
+        body: JSON.stringify({ degrees }),
+      }
+    );
+    return handleResponse<FileInfo>(response);
+  },
+
+  async getTileInfo(filename: string): Promise<TileInfo> {
+    const response = await authenticatedFetch(
+      `${API_BASE}/files/${encodeURIComponent(filename)}/tiles`
+    );
+    return handleResponse<TileInfo>(response);
+  },
 };
 
 // Folder management functions
@@ -491,7 +551,7 @@ export const foldersApi = {
   async moveFolder(
     folderId: string,
     request: MoveFolderRequest
-  ): Promise<void> {
+  ): Promise<MoveFolderResponse> {
     const response = await authenticatedFetch(
       `${API_BASE}/folders/${encodeURIComponent(folderId)}/move`,
       {
@@ -499,7 +559,21 @@ export const foldersApi = {
         body: JSON.stringify(request),
       }
     );
-    await handleResponse(response);
+    return handleResponse<MoveFolderResponse>(response);
+  },
+
+  async renameFolder(
+    folderId: string,
+    request: RenameFolderRequest
+  ): Promise<FolderInfo> {
+    const response = await authenticatedFetch(
+      `${API_BASE}/folders/${encodeURIComponent(folderId)}/rename`,
+      {
+        method: "PUT",
+        body: JSON.stringify(request),
+      }
+    );
+    return handleResponse<FolderInfo>(response);
   },
 };
 
@@ -510,6 +584,8 @@ export const healthApi = {
     version: string;
     uptime: number;
     auth_mode: "protected" | "unprotected";
+    total_files: number;
+    total_size_bytes: number;
   }> {
     const response = await fetch(`${API_BASE}/health`);
     return handleResponse(response);
@@ -1,6 +1,19 @@
+export interface ImportFailure {
+  path: string;
+  reason: string;
+}
+
+// "merge" keeps existing files, "replace" wipes the storage first
+export type ImportMode = "merge" | "replace";
 export interface ImportResponse {
   success: boolean;
   message: string;
+  folders_created: number;
+  files_imported: number;
+  files_skipped: number;
+  imported: string[];
+  stored_filenames: Record<string, string>;
+  failed: ImportFailure[];
 }
 export interface FileMetadata {
   size: number;
@@ -8,12 +21,48 @@ export interface FileMetadata {
   uploaded_at: string;
   width?: number;
   height?: number;
+  content_hash?: string;
+  original_name?: string;
+  analysis?: ImageAnalysis;
+  page_count?: number;
+}
+
+export interface ImageAnalysis {
+  brightness: number;
+  is_grayscale: boolean;
+  histogram: {
+    red: number[];
+    green: number[];
+    blue: number[];
+  };
+}
+
+export interface TileLevel {
+  level: number;
+  width: number;
+  height: number;
+  columns: number;
+  rows: number;
+}
+
+export interface TileInfo {
+  filename: string;
+  width: number;
+  height: number;
+  tile_size: number;
+  overlap: number;
+  format: string;
+  levels: TileLevel[];
 }
 
 export interface FileUrls {
   original: string;
   qoi?: string;
+  avif?: string;
   thumbnail?: string;
+  thumbnail_jpeg?: string;
+  // Thumbnail variants keyed by longest side in pixels
+  thumbnails?: Record<string, string>;
 }
 
 export interface UploadResponse {
@@ -23,8 +72,30 @@ export interface UploadResponse {
   metadata: FileMetadata;
 }
 
+export interface UploadFailure {
+  filename: string;
+  reason: string;
+}
+
+export interface MultiUploadResponse {
+  success: boolean;
+  total: number;
+  succeeded: number;
+  uploaded: UploadResponse[];
+  failed: UploadFailure[];
+}
+
+// Resumable upload created by POST /api/upload/init, continued with PATCH /api/upload/{id}
+export interface ResumableUploadResponse {
+  upload_id: string;
+  offset: number;
+  length: number;
+  expires_at: string;
+}
+
 export interface FileInfo {
   filename: string;
+  display_name?: string;
   size: number;
   mime_type: string;
   uploaded_at: string;
@@ -32,6 +103,44 @@ export interface FileInfo {
   urls: FileUrls;
   dimensions?: [number, number];
   folder_id?: string;
+  folder_path?: string;
+  preview_snippet?: string;
+  page_count?: number;
+  // BLAKE3 hash of the content, also the file's ETag on the static server
+  content_hash?: string;
+  derivatives_skipped?: boolean;
+}
+
+// Temporary link from GET /api/files/{filename}/signed-url, valid without credentials
+export interface SignedUrlResponse {
+  url: string;
+  expires_at: string;
+}
+
+export interface BatchInfoResponse {
+  files: Record<string, FileInfo | null>;
+}
+
+export type DeleteStatus = "deleted" | "not_found" | "failed";
+
+export interface BatchDeleteResult {
+  filename: string;
+  deleted_filename?: string;
+  status: DeleteStatus;
+  reason?: string;
+}
+
+export interface BatchDeleteResponse {
+  success: boolean;
+  deleted: number;
+  results: BatchDeleteResult[];
+}
+
+export interface OrganizeResponse {
+  success: boolean;
+  dry_run: boolean;
+  count: number;
+  files: string[];
 }
 
 export interface FolderInfo {
@@ -42,26 +151,58 @@ export interface FolderInfo {
   file_count: number;
   folder_count: number;
   size: number;
+  image_settings?: FolderImageSettings;
+  // Quota of the folder and its subfolders, in bytes
+  max_size?: number;
+}
+
+// Unset fields inherit from the parent folder, then the server configuration
+export interface FolderImageSettings {
+  qoi_enabled?: boolean;
+  thumbnail_size?: number;
+  thumbnail_quality?: number;
 }
 
 export interface CreateFolderRequest {
   name: string;
   parent_id?: string;
+  image_settings?: FolderImageSettings;
 }
 
 export interface MoveFileRequest {
   folder_id?: string;
 }
 
+export interface RenameFileRequest {
+  display_name?: string | null;
+}
+
+export interface RenameFolderRequest {
+  name: string;
+}
+
 export interface MoveFolderRequest {
   parent_id?: string;
 }
 
+export interface MovedFolder {
+  id: string;
+  name: string;
+  path: string;
+}
+
+export interface MoveFolderResponse {
+  success: boolean;
+  message: string;
+  folders: MovedFolder[];
+}
+
 export interface FileListResponse {
   files: FileInfo[];
   folders: FolderInfo[];
   current_folder?: FolderInfo;
-  breadcrumbs: FolderInfo[];
+  breadcrumbs: Breadcrumb[];
+  breadcrumbs_truncated: boolean;
   total: number;
   page: number;
   per_page: number;
@@ -71,7 +212,18 @@ export interface FileListResponse {
 export interface FolderListResponse {
   folders: FolderInfo[];
   current_folder?: FolderInfo;
-  breadcrumbs: FolderInfo[];
+  breadcrumbs: Breadcrumb[];
+  breadcrumbs_truncated: boolean;
+}
+
+// Stats are only present when requested with breadcrumb_stats=true
+export interface Breadcrumb {
+  id: string;
+  name: string;
+  parent_id?: string;
+  file_count?: number;
+  folder_count?: number;
+  size?: number;
 }
 
 export interface ErrorResponse {